@@ -10,4 +10,5 @@ zip -r source.zip \
 	resources/* \
 	-x"resources/helicopter.obj" \
 	-x"resources/lunarsurface.obj" \
+	-x"resources/*.meshcache" \
 	-x"resources/.gitkeep"
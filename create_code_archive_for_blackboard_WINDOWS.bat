@@ -10,5 +10,6 @@ vendor\7za.exe -tzip a source.zip ^
     resources/* ^
     -x!resources/helicopter.obj ^
     -x!resources/lunarsurface.obj ^
+    -x!resources/*.meshcache ^
     -x!resources/.gitkeep
 pause
@@ -0,0 +1,43 @@
+#version 430 core
+
+in layout(location=0) vec4 in_color;
+in layout(location=1) vec3 normal;
+in layout(location=2) vec3 position;
+in layout(location=3) vec2 texcoord;
+in layout(location=4) vec4 tangent;
+out vec4 color;
+
+layout(location=2) uniform vec3 material_specular;
+layout(location=3) uniform float material_shininess;
+layout(location=4) uniform vec3 camera_position;
+layout(location=5) uniform bool use_texture;
+layout(binding=0) uniform sampler2D diffuse_texture;
+layout(binding=1) uniform sampler2D normal_texture;
+
+vec3 lightDirection = normalize(vec3(0.8, -0.5, 0.6));
+
+// The normal from the tangent space normal map. Like MikkTSpace, the interpolated normal and
+// tangent are used as they are, and only the result is normalized.
+vec3 mapped_normal()
+{
+    if (dot(tangent.xyz, tangent.xyz) == 0) {
+        return normalize(normal);
+    }
+    vec3 bitangent = tangent.w * cross(normal, tangent.xyz);
+    vec3 n = texture(normal_texture, texcoord).xyz * 2.0 - 1.0;
+    return normalize(n.x * tangent.xyz + n.y * bitangent + n.z * normal);
+}
+
+void main()
+{
+    vec4 base_color = use_texture ? in_color * texture(diffuse_texture, texcoord) : in_color;
+
+    vec3 n = mapped_normal();
+    vec3 view_direction = normalize(camera_position - position);
+    vec3 half_vector = normalize(view_direction - lightDirection);
+
+    float diffuse = max(0, dot(n, -lightDirection));
+    float specular = diffuse > 0 ? pow(max(0, dot(n, half_vector)), material_shininess) : 0;
+
+    color = vec4(base_color.rgb * diffuse + material_specular * specular, base_color.a);
+}
\ No newline at end of file
@@ -3,16 +3,38 @@
 layout(location=0) in vec3 position;
 layout(location=1) in vec4 color;
 layout(location=2) in vec3 normal;
+layout(location=3) in vec2 texcoord;
+// Used instead of `normal` when that attribute is disabled, which makes it read as zero
+layout(location=4) in vec2 octahedral_normal;
+// The tangent and the sign of the bitangent, zero if the mesh has no tangents
+layout(location=5) in vec4 tangent;
 
 layout(location=0) out vec4 out_color;
 layout(location=1) out vec3 out_normal;
+layout(location=2) out vec3 out_position;
+layout(location=3) out vec2 out_texcoord;
+layout(location=4) out vec4 out_tangent;
 
 layout(location=0) uniform mat4 mvp_transform;
 layout(location=1) uniform mat4 model_transform;
 
+vec3 decode_octahedral(vec2 e)
+{
+    vec3 n = vec3(e, 1.0 - abs(e.x) - abs(e.y));
+    float t = max(-n.z, 0.0);
+    n.x += n.x >= 0.0 ? -t : t;
+    n.y += n.y >= 0.0 ? -t : t;
+    return normalize(n);
+}
+
 void main()
 {
+    vec3 object_normal = dot(normal, normal) > 0.0 ? normal : decode_octahedral(octahedral_normal);
     gl_Position = mvp_transform * vec4(position, 1.0);
     out_color = color;
-    out_normal = normalize(mat3(model_transform) * normal);
+    out_normal = normalize(mat3(model_transform) * object_normal);
+    out_position = vec3(model_transform * vec4(position, 1.0));
+    out_texcoord = texcoord;
+    // Left unnormalized, as MikkTSpace expects
+    out_tangent = vec4(mat3(model_transform) * tangent.xyz, tangent.w);
 }
\ No newline at end of file
@@ -0,0 +1,449 @@
+use crate::mesh::{Bounds, Material, Mesh, MeshLoadError};
+use crate::scene_graph::{
+    MaterialTextures, NodeId, SceneGraph, SceneGraphError, SceneNode, VertexArray,
+};
+use nalgebra_glm as glm;
+use std::path::Path;
+
+// internal helper, finds the angles for the X-Y-Z rotation order used by `draw_scene`
+fn euler_angles(rotation: [f32; 4]) -> glm::Vec3 {
+    let [x, y, z, w] = rotation;
+    let m = glm::quat_to_mat3(&glm::quat(x, y, z, w));
+    glm::vec3(
+        (-m[(1, 2)]).atan2(m[(2, 2)]),
+        m[(0, 2)].clamp(-1.0, 1.0).asin(),
+        (-m[(0, 1)]).atan2(m[(0, 0)]),
+    )
+}
+
+/// The material of a glTF primitive, along with the images of its textures
+#[derive(Clone, Debug)]
+pub struct GltfMaterial {
+    /// The texture paths are only set for images in files of their own
+    pub material: Material,
+    /// Indices into `GltfModel::images`
+    pub diffuse_image: Option<usize>,
+    pub normal_image: Option<usize>,
+}
+
+// internal helper
+fn material(material: gltf::Material, base_dir: &Path) -> GltfMaterial {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, a] = pbr.base_color_factor();
+    let image_path = |texture: &gltf::Texture| match texture.source().source() {
+        gltf::image::Source::Uri { uri, .. } => Some(base_dir.join(uri)),
+        gltf::image::Source::View { .. } => None,
+    };
+    let diffuse = pbr.base_color_texture().map(|info| info.texture());
+    let normal = material.normal_texture().map(|normal| normal.texture());
+
+    GltfMaterial {
+        material: Material {
+            name: material.name().unwrap_or_default().to_string(),
+            diffuse: [r, g, b],
+            dissolve: a,
+            diffuse_texture: diffuse.as_ref().and_then(image_path),
+            normal_texture: normal.as_ref().and_then(image_path),
+            ..Material::default()
+        },
+        diffuse_image: diffuse.map(|texture| texture.source().index()),
+        normal_image: normal.map(|texture| texture.source().index()),
+    }
+}
+
+// internal helper, the decoded pixels of a glTF image, `None` if they don't fill the image
+fn dynamic_image(data: &gltf::image::Data) -> Option<image::DynamicImage> {
+    use gltf::image::Format;
+    use image::{DynamicImage, ImageBuffer};
+
+    let (width, height) = (data.width, data.height);
+    let bytes = || data.pixels.clone();
+    // 16 bit channels are stored in native byte order
+    let words = || {
+        data.pixels
+            .chunks_exact(2)
+            .map(|pair| u16::from_ne_bytes([pair[0], pair[1]]))
+            .collect::<Vec<u16>>()
+    };
+    match data.format {
+        Format::R8 => ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageLumaA8)
+        }
+        Format::R8G8B8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageRgb8)
+        }
+        Format::R8G8B8A8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageRgba8)
+        }
+        Format::B8G8R8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageBgr8)
+        }
+        Format::B8G8R8A8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageBgra8)
+        }
+        Format::R16 => ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageLuma16),
+        Format::R16G16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageLumaA16)
+        }
+        Format::R16G16B16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageRgb16)
+        }
+        Format::R16G16B16A16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageRgba16)
+        }
+    }
+}
+
+// internal helper, merges all the triangle primitives of a glTF mesh into one mesh,
+// with a sub-mesh and a material for each of them
+fn mesh(
+    mesh: gltf::Mesh,
+    buffers: &[gltf::buffer::Data],
+    base_dir: &Path,
+) -> Result<(Mesh, Vec<GltfMaterial>), MeshLoadError> {
+    let mut parts = Vec::new();
+    let mut materials = Vec::new();
+
+    for (i, primitive) in mesh.primitives().enumerate() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            println!(
+                "Skipping primitive {} of {}, only triangles are supported",
+                i,
+                mesh.name().unwrap_or_default()
+            );
+            continue;
+        }
+
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<[f32; 3]> = match reader.read_positions() {
+            Some(positions) => positions.collect(),
+            None => continue,
+        };
+        let gltf_material = material(primitive.material(), base_dir);
+        let material = gltf_material.material.clone();
+        let [mr, mg, mb, ma] = material.color();
+
+        let colors = match reader.read_colors(0) {
+            Some(colors) => colors
+                .into_rgba_f32()
+                .flat_map(|[r, g, b, a]| vec![r * mr, g * mg, b * mb, a * ma])
+                .collect(),
+            None => material
+                .color()
+                .iter()
+                .cloned()
+                .cycle()
+                .take(positions.len() * 4)
+                .collect(),
+        };
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+            return Err(MeshLoadError::InconsistentAttributes {
+                object: mesh.name().unwrap_or_default().to_string(),
+                reason: format!(
+                    "index {} is out of range for {} vertices",
+                    index,
+                    positions.len()
+                ),
+            });
+        }
+
+        let vertices: Vec<f32> = positions.iter().flatten().cloned().collect();
+        let part = Mesh {
+            bounds: Bounds::from_positions(&vertices),
+            vertices,
+            normals: reader
+                .read_normals()
+                .map(|normals| normals.flatten().collect())
+                .unwrap_or_default(),
+            colors,
+            texcoords: reader
+                .read_tex_coords(0)
+                // glTF has the origin of the texture in the top left corner
+                .map(|uvs| uvs.into_f32().flat_map(|[u, v]| vec![u, 1.0 - v]).collect())
+                .unwrap_or_default(),
+            tangents: reader
+                .read_tangents()
+                // Flipping v mirrors the bitangent too
+                .map(|tangents| {
+                    tangents
+                        .flat_map(|[x, y, z, w]| vec![x, y, z, -w])
+                        .collect()
+                })
+                .unwrap_or_default(),
+            index_count: indices.len() as i32,
+            indices,
+            sub_meshes: Vec::new(),
+            material,
+        };
+        parts.push((format!("{}", i), part));
+        materials.push(gltf_material);
+    }
+
+    Ok((Mesh::merge(parts), materials))
+}
+
+/// The meshes, images and node hierarchy of a glTF file
+pub struct GltfModel {
+    pub document: gltf::Document,
+    pub meshes: Vec<Mesh>,
+    /// The materials of each mesh, one for each of its sub-meshes
+    pub materials: Vec<Vec<GltfMaterial>>,
+    /// The decoded images, whether they are embedded or in files of their own
+    pub images: Vec<gltf::image::Data>,
+}
+
+impl GltfModel {
+    /// Loads a .gltf or .glb file, along with the buffers and images it refers to
+    pub fn load(path: &str) -> Result<Self, MeshLoadError> {
+        println!("Loading glTF model...");
+        let before = std::time::Instant::now();
+        let (document, buffers, images) = gltf::import(path)?;
+        let after = std::time::Instant::now();
+        println!(
+            "Done in {:.3}ms.",
+            after.duration_since(before).as_micros() as f32 / 1e3
+        );
+
+        let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let (meshes, materials): (Vec<Mesh>, Vec<Vec<GltfMaterial>>) = document
+            .meshes()
+            .map(|m| mesh(m, &buffers, base_dir))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .unzip();
+
+        for (m, gltf_mesh) in meshes.iter().zip(document.meshes()) {
+            println!(
+                "Loaded {} with {} points and {} triangles.",
+                gltf_mesh.name().unwrap_or_default(),
+                m.vertices.len() / 3,
+                m.indices.len() / 3,
+            );
+        }
+
+        Ok(GltfModel {
+            document,
+            meshes,
+            materials,
+            images,
+        })
+    }
+
+    /// The pixels of an image, `None` if they don't match its size
+    pub fn image(&self, index: usize) -> Option<image::DynamicImage> {
+        self.images.get(index).and_then(dynamic_image)
+    }
+
+    /// Adds the default scene to a scene graph below `parent`, with a node for each glTF node
+    /// under a node for the whole scene, which is returned. The nodes keep their glTF names.
+    /// A mesh with several primitives is drawn by a child node for each of them.
+    /// `vaos` must hold the VAO of each of the meshes, and `textures` the textures of each of
+    /// their materials, in the same order. Every node pivots about its own origin, like in glTF.
+    pub fn build_scene(
+        &self,
+        graph: &mut SceneGraph,
+        parent: NodeId,
+        vaos: &[VertexArray],
+        textures: &[Vec<MaterialTextures>],
+    ) -> Result<NodeId, SceneGraphError> {
+        let scene = self
+            .document
+            .default_scene()
+            .or_else(|| self.document.scenes().next());
+        let root = graph.insert(
+            parent,
+            SceneNode {
+                name: scene.as_ref().and_then(|s| s.name()).map(String::from),
+                ..SceneNode::default()
+            },
+        )?;
+        if let Some(scene) = scene {
+            for node in scene.nodes() {
+                self.build_node(node, graph, root, vaos, textures)?;
+            }
+        }
+        Ok(root)
+    }
+
+    // internal helper, a node drawing one primitive of a mesh
+    fn primitive_node(
+        &self,
+        mesh: usize,
+        primitive: usize,
+        vaos: &[VertexArray],
+        textures: &[Vec<MaterialTextures>],
+    ) -> SceneNode {
+        let sub_mesh = &self.meshes[mesh].sub_meshes[primitive];
+        let mut scene_node =
+            SceneNode::with_range(vaos[mesh], sub_mesh.first_index, sub_mesh.index_count);
+        scene_node.material = self.materials[mesh][primitive].material.clone();
+        scene_node.texture_id = textures[mesh][primitive].diffuse;
+        scene_node.normal_texture_id = textures[mesh][primitive].normal;
+        scene_node
+    }
+
+    // internal helper
+    fn build_node(
+        &self,
+        node: gltf::Node,
+        graph: &mut SceneGraph,
+        parent: NodeId,
+        vaos: &[VertexArray],
+        textures: &[Vec<MaterialTextures>],
+    ) -> Result<(), SceneGraphError> {
+        let primitives = node
+            .mesh()
+            .map_or(0, |mesh| self.meshes[mesh.index()].sub_meshes.len());
+        let mut scene_node = match node.mesh() {
+            Some(mesh) if primitives == 1 => self.primitive_node(mesh.index(), 0, vaos, textures),
+            _ => SceneNode::default(),
+        };
+
+        scene_node.name = node.name().map(String::from);
+        let (translation, rotation, scale) = node.transform().decomposed();
+        scene_node.position = translation.into();
+        scene_node.rotation = euler_angles(rotation);
+        scene_node.scale = scale.into();
+
+        let id = graph.insert(parent, scene_node)?;
+        if let Some(mesh) = node.mesh().filter(|_| primitives > 1) {
+            for (i, sub_mesh) in self.meshes[mesh.index()].sub_meshes.iter().enumerate() {
+                let mut primitive = self.primitive_node(mesh.index(), i, vaos, textures);
+                primitive.name = Some(sub_mesh.name.clone());
+                graph.insert(id, primitive)?;
+            }
+        }
+        for child in node.children() {
+            self.build_node(child, graph, id, vaos, textures)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A .glb file with one mesh of two triangle primitives: the first has an embedded
+    // 2x1 texture, the second a plain red material
+    fn write_glb(path: &Path) {
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_raw(2, 1, vec![255; 8]).unwrap())
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        let triangle = |z: f32| -> Vec<u8> {
+            [0.0, 0.0, z, 1.0, 0.0, z, 0.0, 1.0, z]
+                .iter()
+                .flat_map(|x: &f32| x.to_le_bytes().to_vec())
+                .collect()
+        };
+        let mut bin = [triangle(0.0), triangle(1.0), png.clone()].concat();
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "scene": 0,
+                "scenes": [{{"nodes": [0]}}],
+                "nodes": [{{"name": "pair", "mesh": 0}}],
+                "meshes": [{{"primitives": [
+                    {{"attributes": {{"POSITION": 0}}, "material": 0}},
+                    {{"attributes": {{"POSITION": 1}}, "material": 1}}
+                ]}}],
+                "materials": [
+                    {{"pbrMetallicRoughness": {{"baseColorTexture": {{"index": 0}}}}}},
+                    {{"pbrMetallicRoughness": {{"baseColorFactor": [1, 0, 0, 1]}}}}
+                ],
+                "textures": [{{"source": 0}}],
+                "images": [{{"bufferView": 2, "mimeType": "image/png"}}],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 0], "max": [1, 1, 0]}},
+                    {{"bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 1], "max": [1, 1, 1]}}
+                ],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 36, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 72, "byteLength": {}}}
+                ],
+                "buffers": [{{"byteLength": {}}}]
+            }}"#,
+            png.len(),
+            bin.len()
+        );
+        let mut json = json.into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+
+        let mut glb = Vec::new();
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(12 + 8 + json.len() as u32 + 8 + bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+        std::fs::write(path, glb).unwrap();
+    }
+
+    #[test]
+    fn embedded_textures_and_primitive_materials() {
+        let path = std::env::temp_dir().join("gltf_import_embedded.glb");
+        write_glb(&path);
+        let model = GltfModel::load(path.to_str().unwrap()).unwrap();
+
+        let materials = &model.materials[0];
+        assert_eq!(model.meshes[0].sub_meshes.len(), 2);
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].diffuse_image, Some(0));
+        assert_eq!(materials[1].diffuse_image, None);
+        assert_eq!(materials[1].material.diffuse, [1.0, 0.0, 0.0]);
+        let image = model.image(0).unwrap();
+        assert_eq!(image.to_rgba8().dimensions(), (2, 1));
+    }
+
+    #[test]
+    fn one_node_per_primitive() {
+        let path = std::env::temp_dir().join("gltf_import_primitives.glb");
+        write_glb(&path);
+        let model = GltfModel::load(path.to_str().unwrap()).unwrap();
+
+        let vao = VertexArray {
+            id: 1,
+            index_format: crate::mesh::IndexFormat::U32,
+        };
+        let textures = vec![vec![
+            MaterialTextures {
+                diffuse: 7,
+                normal: 0,
+            },
+            MaterialTextures::default(),
+        ]];
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let scene = model
+            .build_scene(&mut graph, root, &[vao], &textures)
+            .unwrap();
+
+        let pair = graph.find(scene, "pair").unwrap();
+        assert_eq!(graph[pair].index_count, -1);
+        let primitives = graph.children(pair);
+        assert_eq!(primitives.len(), 2);
+        let (first, second) = (&graph[primitives[0]], &graph[primitives[1]]);
+        assert_eq!((first.first_index, first.index_count), (0, 3));
+        assert_eq!((second.first_index, second.index_count), (3, 3));
+        assert_eq!((first.texture_id, second.texture_id), (7, 0));
+        assert_eq!(second.material.diffuse, [1.0, 0.0, 0.0]);
+    }
+}
@@ -7,12 +7,15 @@
 #![allow(unused_unsafe)]
 #![allow(unused_variables)]
 */
+mod gltf_import;
 mod mesh;
 mod scene_graph;
 mod shader;
+mod texture;
 mod toolbox;
 mod util;
 
+use gltf_import::GltfModel;
 use glutin::{
     event::{
         DeviceEvent,
@@ -23,12 +26,18 @@ use glutin::{
     },
     event_loop::ControlFlow,
 };
-use mesh::{Helicopter, Mesh, Terrain};
+use mesh::{
+    ArticulatedModel, ComponentType, IndexData, IndexFormat, Material, Mesh, MeshLoadError,
+    RepairOptions, Terrain, TerrainGenerator, TerrainSampler, VertexLayout,
+};
 use nalgebra_glm as glm;
-use scene_graph::SceneNode;
+use scene_graph::{MaterialTextures, NodeId, SceneGraph, SceneNode, VertexArray};
 use std::{
+    collections::HashMap,
+    error::Error,
     mem,
     os::raw::c_void,
+    path::{Path, PathBuf},
     ptr,
     sync::{Arc, Mutex, RwLock},
     thread,
@@ -37,6 +46,8 @@ use std::{
 // initial window size
 const INITIAL_SCREEN_W: u32 = 800;
 const INITIAL_SCREEN_H: u32 = 600;
+// The vertical field of view, in radians
+const FIELD_OF_VIEW: f32 = 1.2915;
 const MOVEMENT_SPEED: f32 = 100.0;
 const LOOK_SPEED: f32 = 1.0;
 
@@ -55,112 +66,414 @@ unsafe fn buffer_with_data<T>(target: gl::types::GLenum, data: &[T]) -> u32 {
     buf_id
 }
 
-/// Create a Vertex Array Object.
-unsafe fn create_vao(mesh: &Mesh) -> u32 {
+/// Create a Vertex Array Object, with the mesh packed into one interleaved buffer following `layout`.
+/// The buffers are freed along with the VAO.
+unsafe fn create_vao(mesh: &Mesh, layout: &VertexLayout) -> VertexArray {
+    let data = mesh.interleave(layout);
+
     let mut vao_id = 0;
     gl::GenVertexArrays(1, &mut vao_id);
     gl::BindVertexArray(vao_id);
 
-    buffer_with_data(gl::ARRAY_BUFFER, &mesh.vertices);
-    gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, ptr::null());
-    gl::EnableVertexAttribArray(0);
+    let vertex_buffer = buffer_with_data(gl::ARRAY_BUFFER, &data.bytes);
+    for attribute in &data.attributes {
+        let (component_type, normalized) = match attribute.component_type {
+            ComponentType::F32 => (gl::FLOAT, gl::FALSE),
+            ComponentType::F16 => (gl::HALF_FLOAT, gl::FALSE),
+            ComponentType::U8Norm => (gl::UNSIGNED_BYTE, gl::TRUE),
+            ComponentType::I16Norm => (gl::SHORT, gl::TRUE),
+            ComponentType::I2101010Norm => (gl::INT_2_10_10_10_REV, gl::TRUE),
+        };
+        gl::VertexAttribPointer(
+            attribute.location,
+            attribute.components,
+            component_type,
+            normalized,
+            data.stride as i32,
+            attribute.offset as *const c_void,
+        );
+        gl::EnableVertexAttribArray(attribute.location);
+    }
 
-    buffer_with_data(gl::ARRAY_BUFFER, &mesh.colors);
-    gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, 0, ptr::null());
-    gl::EnableVertexAttribArray(1);
+    let index_buffer = match &data.indices {
+        IndexData::U16(indices) => buffer_with_data(gl::ELEMENT_ARRAY_BUFFER, indices),
+        IndexData::U32(indices) => buffer_with_data(gl::ELEMENT_ARRAY_BUFFER, indices),
+    };
 
-    buffer_with_data(gl::ARRAY_BUFFER, &mesh.normals);
-    gl::VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, 0, ptr::null());
-    gl::EnableVertexAttribArray(2);
+    // The VAO keeps its buffers alive, so their names can go right away. It has to be
+    // unbound first, deleting a buffer detaches it from the bound VAO.
+    gl::BindVertexArray(0);
+    gl::DeleteBuffers(2, [vertex_buffer, index_buffer].as_ptr());
 
-    buffer_with_data(gl::ELEMENT_ARRAY_BUFFER, &mesh.indices);
+    VertexArray {
+        id: vao_id,
+        index_format: data.indices.format(),
+    }
+}
+
+// How many issues of a single mesh are printed
+const MAX_PRINTED_ISSUES: usize = 10;
 
-    vao_id
+/// Reports the problems of a mesh, and repairs it if any of them would break drawing.
+fn check_mesh(name: &str, mesh: &mut Mesh) {
+    let issues = mesh.validate();
+    for issue in issues.iter().take(MAX_PRINTED_ISSUES) {
+        println!("{}: {}", name, issue);
+    }
+    if issues.len() > MAX_PRINTED_ISSUES {
+        println!(
+            "{}: ...and {} more issues",
+            name,
+            issues.len() - MAX_PRINTED_ISSUES
+        );
+    }
+    if issues.iter().any(|issue| issue.is_fatal()) {
+        let report = mesh.repair(&RepairOptions::default());
+        println!("Repaired {}: {}", name, report);
+    }
+}
+
+/// Generates the tangents a mesh with a normal map needs. Without texture coordinates
+/// there are none, and the normal map is left unused.
+fn prepare_normal_map(name: &str, mesh: &mut Mesh) {
+    if mesh.material.normal_texture.is_some() && !mesh.ensure_tangents() {
+        println!("{}: no texture coordinates, ignoring the normal map", name);
+    }
 }
 
-/// Generates five helicopters, sharing the same VAOs.
-unsafe fn generate_helicopters(parent_node: &mut SceneNode) {
-    let helicopter = Helicopter::load("resources/helicopter.obj");
-
-    let body_vao = create_vao(&helicopter.body);
-    let door_vao = create_vao(&helicopter.door);
-    let main_rotor_vao = create_vao(&helicopter.main_rotor);
-    let tail_rotor_vao = create_vao(&helicopter.tail_rotor);
-
-    for _ in 0..5 {
-        let mut body = SceneNode::new(body_vao, helicopter.body.index_count);
-        let mut door = SceneNode::new(door_vao, helicopter.door.index_count);
-        let mut main_rotor = SceneNode::new(main_rotor_vao, helicopter.main_rotor.index_count);
-        let mut tail_rotor = SceneNode::new(tail_rotor_vao, helicopter.tail_rotor.index_count);
-
-        // Seems to be a OK guess
-        door.reference_point = glm::vec3(1.0, 1.5, 0.0);
-        // Not needed if we only want rotation around the Y-axis
-        main_rotor.reference_point = glm::vec3(0.0, 2.3, 0.0);
-        tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4);
-
-        body.add_child(door);
-        body.add_child(main_rotor);
-        body.add_child(tail_rotor);
-        parent_node.add_child(body);
+/// Loads the diffuse and normal textures of a material, using 0 for those it doesn't have.
+unsafe fn load_textures(material: &Material) -> MaterialTextures {
+    let load = |path: &Option<PathBuf>| {
+        path.as_ref()
+            .and_then(|path| texture::Texture::load_or_warn(path, &texture::Sampler::default()))
+            .map_or(0, |texture| texture.texture_id)
+    };
+    MaterialTextures {
+        diffuse: load(&material.diffuse_texture),
+        normal: load(&material.normal_texture),
     }
 }
 
-/// Animates each of the five helicopters.
-fn animate_helicopters(parent_node: &mut SceneNode, elapsed: f32, delta_time: f32) {
-    for (i, heli_body) in parent_node.iter_mut().enumerate() {
-        let main_rotor = heli_body.get_child_mut(1).expect("missing main rotor");
-        main_rotor.rotation.y += delta_time;
+const HELICOPTER_COUNT: usize = 5;
+
+/// Generates the helicopters below `parent`, sharing the same VAOs. Each body is named
+/// `heli_<i>`, with its parts below it, so they are found at paths like `heli_3/main_rotor`.
+/// The part meshes are kept in `meshes`.
+unsafe fn generate_helicopters(
+    scene: &mut SceneGraph,
+    parent: NodeId,
+    meshes: &mut HashMap<u32, Mesh>,
+) -> Result<(), Box<dyn Error>> {
+    // The parts, their colors and pivots are listed in the descriptor
+    let mut helicopter = ArticulatedModel::load("resources/helicopter.parts")?;
+    // The animation looks for these
+    let part_index = |name: &str| {
+        helicopter
+            .parts
+            .iter()
+            .position(|p| p.name == name)
+            .ok_or_else(|| MeshLoadError::MissingPart {
+                name: name.to_string(),
+            })
+    };
+    let body = part_index("body")?;
+    part_index("main_rotor")?;
+    part_index("tail_rotor")?;
+    for part in &mut helicopter.parts {
+        check_mesh(&part.name, &mut part.mesh);
+        prepare_normal_map(&part.name, &mut part.mesh);
+    }
 
-        let tail_rotor = heli_body.get_child_mut(2).expect("missing tail rotor");
-        tail_rotor.rotation.x += delta_time;
+    let vaos: Vec<VertexArray> = helicopter
+        .parts
+        .iter()
+        .map(|p| create_vao(&p.mesh, &VertexLayout::compact()))
+        .collect();
+    let textures: Vec<MaterialTextures> = helicopter
+        .parts
+        .iter()
+        .map(|p| load_textures(&p.mesh.material))
+        .collect();
+
+    for i in 0..HELICOPTER_COUNT {
+        let nodes = helicopter.build_scene(scene, parent, &vaos, &textures)?;
+        scene[nodes[body]].name = Some(format!("heli_{}", i));
+    }
+    for (part, vao) in helicopter.parts.into_iter().zip(&vaos) {
+        meshes.insert(vao.id, part.mesh);
+    }
 
+    Ok(())
+}
+
+// How far above the ground the helicopters have to stay
+const HELICOPTER_CLEARANCE: f32 = 2.0;
+
+/// Poses each of the helicopters `elapsed` seconds into the animation,
+/// climbing over any hills in their path.
+fn animate_helicopters(scene: &mut SceneGraph, ground: &TerrainSampler, elapsed: f32) {
+    let root = scene.root();
+    for rotor in scene.find_all(root, "helicopters/*/main_rotor") {
+        scene[rotor].rotation.y = elapsed;
+    }
+    for rotor in scene.find_all(root, "helicopters/*/tail_rotor") {
+        scene[rotor].rotation.x = elapsed;
+    }
+
+    for i in 0..HELICOPTER_COUNT {
+        let heli_body = match scene.find(root, &format!("helicopters/heli_{}", i)) {
+            Some(body) => &mut scene[body],
+            None => continue,
+        };
         let heading = toolbox::simple_heading_animation(elapsed + i as f32 * 0.7);
         heli_body.position.x = heading.x;
         heli_body.position.z = heading.z;
+        heli_body.position.y = ground
+            .height_at(heading.x, heading.z)
+            .map_or(0.0, |height| (height + HELICOPTER_CLEARANCE).max(0.0));
         heli_body.rotation.x = heading.pitch;
         heli_body.rotation.y = heading.yaw;
         heli_body.rotation.z = heading.roll;
     }
 }
 
+/// Poses the scene `time` seconds into the animation, and writes it to `path` as a single mesh.
+fn export_snapshot(
+    scene: &mut SceneGraph,
+    meshes: &HashMap<u32, Mesh>,
+    ground: &TerrainSampler,
+    time: f32,
+    path: &Path,
+) {
+    animate_helicopters(scene, ground, time);
+    let snapshot = Mesh::bake_scene(scene, scene.root(), meshes);
+    match snapshot.export(path) {
+        Ok(()) => println!(
+            "Exported the scene at {:.2}s to {}, {} triangles.",
+            time,
+            path.display(),
+            snapshot.indices.len() / 3
+        ),
+        Err(e) => println!("Failed to export the scene to {}: {}", path.display(), e),
+    }
+}
+
+/// Prints the analysis of every object in an OBJ or glTF file
+fn analyze_file(path: &str) -> Result<(), MeshLoadError> {
+    let extension = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    let objects: Vec<(String, Mesh)> = match extension.as_deref() {
+        Some("gltf") | Some("glb") => {
+            let model = GltfModel::load(path)?;
+            let names: Vec<String> = model
+                .document
+                .meshes()
+                .map(|m| m.name().unwrap_or_default().to_string())
+                .collect();
+            names.into_iter().zip(model.meshes).collect()
+        }
+        _ => Mesh::load_objects(path)?,
+    };
+
+    for (name, mesh) in &objects {
+        println!("{}:", name);
+        for line in mesh.analyze().to_string().lines() {
+            println!("    {}", line);
+        }
+    }
+    Ok(())
+}
+
+/// Loads a glTF model and uploads its meshes, adding its scene below `parent` and returning its node.
+/// The meshes are kept in `meshes`.
+unsafe fn load_gltf_scene(
+    path: &str,
+    scene: &mut SceneGraph,
+    parent: NodeId,
+    meshes: &mut HashMap<u32, Mesh>,
+) -> Option<NodeId> {
+    match GltfModel::load(path) {
+        Ok(mut model) => {
+            for (i, mesh) in model.meshes.iter_mut().enumerate() {
+                let name = format!("{} mesh {}", path, i);
+                check_mesh(&name, mesh);
+                let normal_mapped = model.materials[i].iter().any(|m| m.normal_image.is_some());
+                if normal_mapped && !mesh.ensure_tangents() {
+                    println!("{}: no texture coordinates, ignoring the normal map", name);
+                }
+            }
+            let vaos: Vec<VertexArray> = model
+                .meshes
+                .iter()
+                .map(|m| create_vao(m, &VertexLayout::compact()))
+                .collect();
+            // Embedded images are decoded along with the model, so every image is uploaded from memory
+            let images: Vec<u32> = (0..model.images.len())
+                .map(|i| match model.image(i) {
+                    Some(image) => {
+                        texture::Texture::from_image(&image, &texture::Sampler::default())
+                            .texture_id
+                    }
+                    None => {
+                        println!("{}: image {} has an unsupported size or format", path, i);
+                        0
+                    }
+                })
+                .collect();
+            let image_texture = |image: Option<usize>| image.map_or(0, |i| images[i]);
+            let textures: Vec<Vec<MaterialTextures>> = model
+                .materials
+                .iter()
+                .map(|materials| {
+                    materials
+                        .iter()
+                        .map(|m| MaterialTextures {
+                            diffuse: image_texture(m.diffuse_image),
+                            normal: image_texture(m.normal_image),
+                        })
+                        .collect()
+                })
+                .collect();
+            let node = match model.build_scene(scene, parent, &vaos, &textures) {
+                Ok(node) => node,
+                Err(e) => {
+                    println!("Failed to add {} to the scene: {}", path, e);
+                    return None;
+                }
+            };
+            for (mesh, vao) in model.meshes.into_iter().zip(&vaos) {
+                meshes.insert(vao.id, mesh);
+            }
+            Some(node)
+        }
+        Err(e) => {
+            println!("Failed to load {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// How many pixels a unit long object covers at a distance of one, for a viewport `height` pixels high
+fn pixels_per_unit_for(height: u32) -> f32 {
+    height as f32 / (2.0 * (FIELD_OF_VIEW / 2.0).tan())
+}
+
+/// The shader programs `draw_scene` picks from for each node
+struct SceneShaders {
+    simple: shader::Shader,
+    normal_mapped: shader::Shader,
+}
+
 /// Traverses the scene graph and draws the nodes.
-unsafe fn draw_scene(node: &SceneNode, view_projection: &glm::Mat4, parent_model: glm::Mat4) {
-    let model_mat = if node.index_count > 0 {
-        let model_mat = glm::translation(&node.position)
-            * glm::translation(&node.reference_point)
-            * glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0))
-            * glm::rotation(node.rotation.y, &glm::vec3(0.0, 1.0, 0.0))
-            * glm::rotation(node.rotation.z, &glm::vec3(0.0, 0.0, 1.0))
-            * glm::scaling(&node.scale)
-            * glm::translation(&-node.reference_point)
-            * glm::identity();
-
-        let total_model_mat = parent_model * model_mat;
+/// Nodes with levels of detail are drawn at the coarsest one that stays within a pixel of the full mesh.
+unsafe fn draw_scene(
+    scene: &SceneGraph,
+    id: NodeId,
+    shaders: &SceneShaders,
+    view_projection: &glm::Mat4,
+    camera_position: &glm::Vec3,
+    pixels_per_unit: f32,
+    parent_model: glm::Mat4,
+) {
+    // Nodes without anything to draw still transform their children, e.g. the joints of an imported rig
+    let node = &scene[id];
+    let total_model_mat = parent_model * node.local_transform();
+
+    let (first_index, index_count) = match &node.lod {
+        Some(lod) => {
+            let sphere = lod.sphere.transformed(&total_model_mat);
+            let distance = glm::distance(camera_position, &sphere.center) - sphere.radius;
+            let level = lod.select(distance, pixels_per_unit);
+            (level.first_index, level.index_count)
+        }
+        None => (node.first_index, node.index_count),
+    };
+
+    if index_count > 0 {
         let mvp = view_projection * total_model_mat;
 
+        if node.normal_texture_id != 0 {
+            shaders.normal_mapped.activate();
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_2D, node.normal_texture_id);
+        } else {
+            shaders.simple.activate();
+        }
         gl::UniformMatrix4fv(0, 1, gl::FALSE, mvp.as_ptr());
         gl::UniformMatrix4fv(1, 1, gl::FALSE, total_model_mat.as_ptr());
+        gl::Uniform3fv(2, 1, node.material.specular.as_ptr());
+        gl::Uniform1f(3, node.material.shininess);
+
+        gl::Uniform1i(5, (node.texture_id != 0) as i32);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, node.texture_id);
 
         gl::BindVertexArray(node.vao_id);
+        let index_type = match node.index_format {
+            IndexFormat::U16 => gl::UNSIGNED_SHORT,
+            IndexFormat::U32 => gl::UNSIGNED_INT,
+        };
         gl::DrawElements(
             gl::TRIANGLES,
-            node.index_count,
-            gl::UNSIGNED_INT,
-            ptr::null(),
+            index_count,
+            index_type,
+            (first_index as usize * node.index_format.size()) as *const c_void,
         );
+    }
 
-        total_model_mat
-    } else {
-        parent_model
-    };
-
-    for child in node.iter() {
-        draw_scene(child, view_projection, model_mat);
+    for &child in scene.children(id) {
+        draw_scene(
+            scene,
+            child,
+            shaders,
+            view_projection,
+            camera_position,
+            pixels_per_unit,
+            total_model_mat,
+        );
     }
 }
 
+// The terrain levels of detail, each with about half the triangles of the one before
+const TERRAIN_LOD_LEVELS: usize = 5;
+const TERRAIN_LOD_RATIO: f32 = 0.5;
+// The width of the terrain generated when the lunar surface can't be loaded, about as wide as it
+const FALLBACK_TERRAIN_SIZE: f32 = 400.0;
+
 fn main() {
+    // Usage: gloom-rs [model.gltf] [--export <scene.obj|.stl|.ply> [--at <seconds>]]
+    //        gloom-rs analyze <model.obj|.gltf|.glb>
+    // The glTF model is shown in the scene, the export writes the scene posed at the given time.
+    // `analyze` prints a report on every object of the file instead of opening a window.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("analyze") {
+        match args.get(1) {
+            Some(path) => {
+                if let Err(e) = analyze_file(path) {
+                    eprintln!("Failed to analyze {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+            None => {
+                eprintln!("Usage: gloom-rs analyze <model.obj|.gltf|.glb>");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let flag = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    let export_path = flag("--export").map(PathBuf::from);
+    let export_time = flag("--at")
+        .and_then(|time| time.parse().ok())
+        .unwrap_or(0.0);
+    let gltf_path = args.first().filter(|arg| !arg.starts_with("--")).cloned();
+
     // Set up the necessary objects to deal with windows and event handling
     let el = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
@@ -228,29 +541,80 @@ fn main() {
             );
         }
 
-        let mut helicopters = SceneNode::default();
-        unsafe {
-            generate_helicopters(&mut helicopters);
+        // What each VAO draws, for exporting snapshots of the scene
+        let mut meshes: HashMap<u32, Mesh> = HashMap::new();
+
+        let mut scene = SceneGraph::new();
+        // The root is never removed, so adding to it can't fail
+        let helicopters = scene
+            .insert(scene.root(), SceneNode::group("helicopters"))
+            .unwrap();
+        if let Err(e) = unsafe { generate_helicopters(&mut scene, helicopters, &mut meshes) } {
+            println!("Failed to load the helicopters, flying without them: {}", e);
         }
 
-        let terrain = Terrain::load("resources/lunarsurface.obj");
-        let lunar_terrain = SceneNode::new(unsafe { create_vao(&terrain) }, terrain.index_count);
+        let mut terrain = Terrain::load("resources/lunarsurface.obj").unwrap_or_else(|e| {
+            println!("Failed to load the terrain, generating one instead: {}", e);
+            let mut generator = TerrainGenerator::default();
+            generator.heightmap.spacing = FALLBACK_TERRAIN_SIZE / (generator.samples() - 1) as f32;
+            Terrain::generate(&generator)
+        });
+        check_mesh("terrain", &mut terrain);
+        prepare_normal_map("terrain", &mut terrain);
+        let ground = TerrainSampler::new(&terrain);
+
+        println!("Simplifying terrain...");
+        let before = std::time::Instant::now();
+        let terrain_lods = terrain.lod_chain(TERRAIN_LOD_LEVELS, TERRAIN_LOD_RATIO);
+        let after = std::time::Instant::now();
+        println!(
+            "Done in {:.3}ms.",
+            after.duration_since(before).as_micros() as f32 / 1e3
+        );
+        let terrain_vao = unsafe { create_vao(&terrain_lods.mesh, &VertexLayout::compact()) };
+        let terrain_textures = unsafe { load_textures(&terrain.material) };
+        // Each object of the terrain file gets its own node, sharing the same VAO
+        let lunar_terrain = scene
+            .insert(scene.root(), SceneNode::group("lunar_terrain"))
+            .unwrap();
+        for (i, set) in terrain_lods.sets.into_iter().enumerate() {
+            let finest = set.levels[0];
+            let mut node =
+                SceneNode::with_range(terrain_vao, finest.first_index, finest.index_count);
+            node.material = terrain.material.clone();
+            node.texture_id = terrain_textures.diffuse;
+            node.normal_texture_id = terrain_textures.normal;
+            node.name = terrain_lods.mesh.sub_meshes.get(i).map(|s| s.name.clone());
+            node.lod = Some(set);
+            scene.insert(lunar_terrain, node).unwrap();
+        }
+        meshes.insert(terrain_vao.id, terrain_lods.mesh);
 
-        let mut scene_root = SceneNode::default();
-        scene_root.add_child(helicopters);
-        scene_root.add_child(lunar_terrain);
+        if let Some(path) = &gltf_path {
+            let root = scene.root();
+            unsafe { load_gltf_scene(path, &mut scene, root, &mut meshes) };
+        }
+        if let Some(path) = &export_path {
+            export_snapshot(&mut scene, &meshes, &ground, export_time, path);
+        }
 
         // Setup the simple shader
-        let _simple_shader = unsafe {
-            let s = shader::ShaderBuilder::new()
-                .attach_file("./shaders/simple.vert")
-                .attach_file("./shaders/simple.frag")
-                .link();
-            s.activate();
-            s
+        let shaders = unsafe {
+            SceneShaders {
+                simple: shader::ShaderBuilder::new()
+                    .attach_file("./shaders/simple.vert")
+                    .attach_file("./shaders/simple.frag")
+                    .link(),
+                normal_mapped: shader::ShaderBuilder::new()
+                    .attach_file("./shaders/simple.vert")
+                    .attach_file("./shaders/normal_mapped.frag")
+                    .link(),
+            }
         };
 
-        let mut perspective: glm::Mat4 = glm::perspective(window_aspect_ratio, 1.2915, 1.0, 1000.0);
+        let mut perspective: glm::Mat4 =
+            glm::perspective(window_aspect_ratio, FIELD_OF_VIEW, 1.0, 1000.0);
+        let mut pixels_per_unit = pixels_per_unit_for(INITIAL_SCREEN_H);
 
         let mut translate_x = 0.0;
         let mut translate_y = 0.0;
@@ -273,7 +637,8 @@ fn main() {
                 if new_size.2 {
                     context.resize(glutin::dpi::PhysicalSize::new(new_size.0, new_size.1));
                     window_aspect_ratio = new_size.0 as f32 / new_size.1 as f32;
-                    perspective = glm::perspective(window_aspect_ratio, 1.2915, 1.0, 1000.0);
+                    perspective = glm::perspective(window_aspect_ratio, FIELD_OF_VIEW, 1.0, 1000.0);
+                    pixels_per_unit = pixels_per_unit_for(new_size.1);
                     new_size.2 = false;
                     println!("Resized");
                     unsafe {
@@ -343,22 +708,30 @@ fn main() {
             let rotation_x = glm::rotation(rotate_x, &glm::vec3(1.0, 0.0, 0.0));
             let view_matrix: glm::Mat4 =
                 perspective * rotation_x * rotation_y * translation * glm::identity();
+            let camera_position = glm::vec3(-translate_x, -translate_y, -translate_z);
 
-            animate_helicopters(
-                scene_root
-                    .get_child_mut(0)
-                    .expect("helicopters root missing"),
-                elapsed,
-                delta_time,
-            );
+            animate_helicopters(&mut scene, &ground, elapsed);
 
             unsafe {
                 // Clear the color and depth buffers
                 gl::ClearColor(0.035, 0.046, 0.078, 1.0); // night sky, full opacity
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
+                for shader in [&shaders.simple, &shaders.normal_mapped] {
+                    shader.activate();
+                    gl::Uniform3fv(4, 1, camera_position.as_ptr());
+                }
+
                 // Issue draw calls
-                draw_scene(&scene_root, &view_matrix, glm::identity());
+                draw_scene(
+                    &scene,
+                    scene.root(),
+                    &shaders,
+                    &view_matrix,
+                    &camera_position,
+                    pixels_per_unit,
+                    glm::identity(),
+                );
             }
 
             // Display the new color buffer on the display
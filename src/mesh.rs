@@ -1,16 +1,186 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+mod analysis;
+mod articulated;
+mod bounds;
+mod bvh;
+mod cache;
+mod error;
+mod export;
+mod heightmap;
+mod isosurface;
+mod normals;
+mod optimize;
+mod primitives;
+mod procedural;
+mod sampling;
+mod simplify;
+mod subdivide;
+mod tangents;
+mod validate;
+mod vertex_format;
+
+#[allow(unused_imports)]
+pub use analysis::MeshAnalysis;
+#[allow(unused_imports)]
+pub use articulated::{ArticulatedModel, ModelDescriptor, Part, PartDescriptor, PartSubdivision};
+#[allow(unused_imports)]
+pub use bounds::{Aabb, BoundingSphere, Bounds};
+#[allow(unused_imports)]
+pub use bvh::{Bvh, ClosestPoint, Ray, RayHit};
+pub use error::MeshLoadError;
+#[allow(unused_imports)]
+pub use export::StlFormat;
+#[allow(unused_imports)]
+pub use heightmap::HeightmapOptions;
+#[allow(unused_imports)]
+pub use isosurface::SampleGrid;
+#[allow(unused_imports)]
+pub use normals::Shading;
+#[allow(unused_imports)]
+pub use optimize::MeshStats;
+#[allow(unused_imports)]
+pub use procedural::{NoiseKind, TerrainGenerator};
+#[allow(unused_imports)]
+pub use sampling::{GroundSample, TerrainSampler};
+#[allow(unused_imports)]
+pub use simplify::{Lod, LodChain, LodSet};
+#[allow(unused_imports)]
+pub use subdivide::{Subdivision, MAX_SUBDIVISION_LEVELS};
+#[allow(unused_imports)]
+pub use validate::{MeshIssue, RepairOptions, RepairReport};
+#[allow(unused_imports)]
+pub use vertex_format::{
+    ColorFormat, ComponentType, IndexData, IndexFormat, NormalFormat, PositionFormat,
+    TangentFormat, TexcoordFormat, VertexAttribute, VertexData, VertexLayout,
+};
+
 // internal helper
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num * 4).collect()
 }
 
+// internal helper, gathers the attributes of the given vertices into a new list.
+// Attributes that don't have exactly one entry per vertex are dropped.
+fn gather_attribute(data: &[f32], width: usize, num_verts: usize, source: &[u32]) -> Vec<f32> {
+    if data.len() != num_verts * width {
+        return Vec::new();
+    }
+    source
+        .iter()
+        .flat_map(|&v| {
+            data[v as usize * width..(v as usize + 1) * width]
+                .iter()
+                .cloned()
+        })
+        .collect()
+}
+
+// internal helper, texture paths in MTL files are relative to the OBJ file
+fn texture_path(base_dir: &Path, name: &str) -> Option<PathBuf> {
+    if name.is_empty() {
+        None
+    } else {
+        Some(base_dir.join(name))
+    }
+}
+
+// Material
+
+/// Surface parameters of a mesh, usually read from an MTL file
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct Material {
+    pub name: String,
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    pub dissolve: f32,
+    pub diffuse_texture: Option<PathBuf>,
+    pub specular_texture: Option<PathBuf>,
+    pub normal_texture: Option<PathBuf>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            diffuse: [1.0, 1.0, 1.0],
+            specular: [0.0, 0.0, 0.0],
+            shininess: 1.0,
+            dissolve: 1.0,
+            diffuse_texture: None,
+            specular_texture: None,
+            normal_texture: None,
+        }
+    }
+}
+
+impl Material {
+    pub fn from(material: &tobj::Material, base_dir: &Path) -> Self {
+        Self {
+            name: material.name.clone(),
+            diffuse: material.diffuse,
+            specular: material.specular,
+            shininess: material.shininess,
+            dissolve: material.dissolve,
+            diffuse_texture: texture_path(base_dir, &material.diffuse_texture),
+            specular_texture: texture_path(base_dir, &material.specular_texture),
+            normal_texture: texture_path(base_dir, &material.normal_texture),
+        }
+    }
+
+    /// The diffuse color, with the dissolve as alpha
+    pub fn color(&self) -> [f32; 4] {
+        let [r, g, b] = self.diffuse;
+        [r, g, b, self.dissolve]
+    }
+
+    /// Converts the materials returned by `tobj::load_obj` for the OBJ file at `path`.
+    /// A missing or broken MTL file is not fatal, the meshes will just use their fallback colors.
+    pub fn load_all(
+        materials: Result<Vec<tobj::Material>, tobj::LoadError>,
+        path: &str,
+    ) -> Vec<Material> {
+        let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        match materials {
+            Ok(materials) => materials
+                .iter()
+                .map(|m| Material::from(m, base_dir))
+                .collect(),
+            Err(e) => {
+                println!("No materials loaded for {}: {}", path, e);
+                Vec::new()
+            }
+        }
+    }
+}
+
 // Mesh
 
+/// A named range of indices within a mesh, e.g. one of the objects of a merged OBJ file
+#[derive(Clone, Debug)]
+pub struct SubMesh {
+    pub name: String,
+    pub first_index: i32,
+    pub index_count: i32,
+}
+
 pub struct Mesh {
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
     pub colors: Vec<f32>,
+    pub texcoords: Vec<f32>,
+    /// Four values per vertex, the tangent and the sign of the bitangent. Empty unless generated
+    /// by `generate_tangents` or loaded.
+    pub tangents: Vec<f32>,
     pub indices: Vec<u32>,
     pub index_count: i32,
+    pub sub_meshes: Vec<SubMesh>,
+    pub material: Material,
+    /// Computed when the mesh is built, call `update_bounds` after moving the vertices
+    pub bounds: Bounds,
 }
 
 impl Mesh {
@@ -18,134 +188,367 @@ impl Mesh {
         let num_verts = mesh.positions.len() / 3;
         let index_count = mesh.indices.len() as i32;
         Mesh {
+            bounds: Bounds::from_positions(&mesh.positions),
             vertices: mesh.positions,
             normals: mesh.normals,
+            texcoords: mesh.texcoords,
+            tangents: Vec::new(),
             indices: mesh.indices,
             colors: generate_color_vec(color, num_verts),
             index_count,
+            sub_meshes: Vec::new(),
+            material: Material::default(),
         }
+        .with_normals()
     }
-}
 
-// Lunar terrain
+    // internal helper, makes sure freshly loaded meshes can be lit
+    fn with_normals(mut self) -> Self {
+        self.ensure_normals();
+        self
+    }
 
-pub struct Terrain;
-impl Terrain {
-    pub fn load(path: &str) -> Mesh {
-        println!("Loading terrain model...");
-        let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(
+    /// Like `Mesh::from`, but colors the mesh by the diffuse color of its MTL material.
+    /// The given color is only used if the mesh has no material.
+    pub fn with_materials(
+        mesh: tobj::Mesh,
+        materials: &[Material],
+        fallback_color: [f32; 4],
+    ) -> Self {
+        match mesh.material_id.and_then(|id| materials.get(id)) {
+            Some(material) => Mesh {
+                material: material.clone(),
+                ..Mesh::from(mesh, material.color())
+            },
+            None => Mesh::from(mesh, fallback_color),
+        }
+    }
+
+    /// Loads every object of an OBJ file as its own mesh, colored by its material
+    pub fn load_objects(path: &str) -> Result<Vec<(String, Mesh)>, MeshLoadError> {
+        let (models, materials) = tobj::load_obj(
             path,
             &tobj::LoadOptions {
                 triangulate: true,
                 single_index: true,
                 ..Default::default()
             },
-        )
-        .expect("Failed to load terrain model");
-        let after = std::time::Instant::now();
-        println!(
-            "Done in {:.3}ms.",
-            after.duration_since(before).as_micros() as f32 / 1e3
-        );
+        )?;
+        let materials = Material::load_all(materials, path);
+        models
+            .into_iter()
+            .map(|model| {
+                error::check_attributes(&model.name, &model.mesh)?;
+                let mesh = Mesh::with_materials(model.mesh, &materials, [1.0, 1.0, 1.0, 1.0]);
+                Ok((model.name, mesh))
+            })
+            .collect()
+    }
+
+    /// Concatenates several named meshes into one, re-basing the indices of each part.
+    /// Every part is recorded as a `SubMesh`, so it can still be drawn on its own.
+    /// Normals are generated for the whole mesh if any of the parts is missing them,
+    /// while texture coordinates and tangents are only kept if all of the parts have them.
+    /// Merging is single-material: the merged mesh uses the material of the first part, and the
+    /// other parts keep only their vertex colors, e.g. the diffuse colors set by `with_materials`.
+    pub fn merge(parts: impl IntoIterator<Item = (String, Mesh)>) -> Self {
+        let mut merged = Mesh {
+            vertices: Vec::new(),
+            normals: Vec::new(),
+            colors: Vec::new(),
+            texcoords: Vec::new(),
+            tangents: Vec::new(),
+            indices: Vec::new(),
+            index_count: 0,
+            sub_meshes: Vec::new(),
+            material: Material::default(),
+            bounds: Bounds::default(),
+        };
+        let mut all_have_normals = true;
+        let mut all_have_texcoords = true;
+        let mut all_have_tangents = true;
+
+        for (name, part) in parts {
+            if merged.sub_meshes.is_empty() {
+                merged.material = part.material.clone();
+            }
+
+            let base_vertex = (merged.vertices.len() / 3) as u32;
+            let first_index = merged.indices.len() as i32;
+
+            all_have_normals &= part.normals.len() == part.vertices.len();
+            all_have_texcoords &= part.texcoords.len() / 2 == part.vertices.len() / 3;
+            all_have_tangents &= part.has_tangents();
+            merged.vertices.extend_from_slice(&part.vertices);
+            merged.normals.extend_from_slice(&part.normals);
+            merged.colors.extend_from_slice(&part.colors);
+            merged.texcoords.extend_from_slice(&part.texcoords);
+            merged.tangents.extend_from_slice(&part.tangents);
+            merged
+                .indices
+                .extend(part.indices.iter().map(|i| i + base_vertex));
 
-        if models.len() > 1 || models.is_empty() {
-            panic!("Please use a model with a single mesh!")
-            // You could try merging the vertices and indices
-            // of the separate meshes into a single mesh.
-            // I'll leave that as an optional exercise. ;)
+            merged.sub_meshes.push(SubMesh {
+                name,
+                first_index,
+                index_count: part.indices.len() as i32,
+            });
         }
 
-        let terrain = models[0].to_owned();
-        println!(
-            "Loaded {} with {} points and {} triangles.",
-            terrain.name,
-            terrain.mesh.positions.len() / 3,
-            terrain.mesh.indices.len() / 3,
-        );
+        if !all_have_normals {
+            merged.normals.clear();
+        }
+        if !all_have_texcoords {
+            merged.texcoords.clear();
+        }
+        if !all_have_tangents {
+            merged.tangents.clear();
+        }
+        merged.index_count = merged.indices.len() as i32;
+        merged.update_bounds();
+        merged.with_normals()
+    }
 
-        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
+    /// Returns true if there is exactly one texture coordinate per vertex
+    pub fn has_texcoords(&self) -> bool {
+        !self.texcoords.is_empty() && self.texcoords.len() / 2 == self.vertices.len() / 3
     }
-}
 
-// Helicopter
+    /// Rebuilds the per-vertex attributes, so that new vertex `i` is a copy of old vertex `source[i]`.
+    /// The indices are left untouched.
+    fn remap_vertices(&mut self, source: &[u32]) {
+        let num_verts = self.vertices.len() / 3;
+        self.normals = gather_attribute(&self.normals, 3, num_verts, source);
+        self.colors = gather_attribute(&self.colors, 4, num_verts, source);
+        self.texcoords = gather_attribute(&self.texcoords, 2, num_verts, source);
+        self.tangents = gather_attribute(&self.tangents, 4, num_verts, source);
+        self.vertices = gather_attribute(&self.vertices, 3, num_verts, source);
+    }
 
-pub struct Helicopter {
-    pub body: Mesh,
-    pub door: Mesh,
-    pub main_rotor: Mesh,
-    pub tail_rotor: Mesh,
-}
+    /// Removes the triangles that are not kept, shrinking the sub-meshes to match
+    fn retain_triangles(&mut self, keep: &[bool]) {
+        // The number of kept triangles before each triangle
+        let mut kept_before = Vec::with_capacity(keep.len() + 1);
+        kept_before.push(0);
+        for &k in keep {
+            kept_before.push(kept_before.last().unwrap() + k as usize);
+        }
+        let num_tris = keep.len();
+        let sub_meshes = std::mem::take(&mut self.sub_meshes);
+        self.sub_meshes = sub_meshes
+            .into_iter()
+            .map(|sub_mesh| {
+                let first = (sub_mesh.first_index.max(0) as usize / 3).min(num_tris);
+                let end = sub_mesh.first_index.saturating_add(sub_mesh.index_count);
+                let last = (end.max(0) as usize / 3).clamp(first, num_tris);
+                SubMesh {
+                    first_index: kept_before[first] as i32 * 3,
+                    index_count: (kept_before[last] - kept_before[first]) as i32 * 3,
+                    ..sub_mesh
+                }
+            })
+            .collect();
+        self.indices = self
+            .indices
+            .chunks_exact(3)
+            .zip(keep)
+            .filter(|(_, &k)| k)
+            .flat_map(|(tri, _)| tri.iter().cloned())
+            .collect();
+        self.index_count = self.indices.len() as i32;
+    }
 
-// You can use square brackets to access the components of the helicopter, if you want to use loops!
-use std::ops::Index;
-impl Index<usize> for Helicopter {
-    type Output = Mesh;
-    fn index(&self, i: usize) -> &Mesh {
-        match i {
-            0 => &self.body,
-            1 => &self.main_rotor,
-            2 => &self.tail_rotor,
-            3 => &self.door,
-            _ => panic!("Invalid index, try [0,3]"),
+    /// The index ranges of the sub-meshes, or of the whole mesh if it has none
+    fn triangle_ranges(&self) -> Vec<Range<usize>> {
+        if self.sub_meshes.is_empty() {
+            return std::iter::once(0..self.indices.len() / 3 * 3).collect();
         }
+        self.sub_meshes
+            .iter()
+            .map(|s| {
+                let start = (s.first_index.max(0) as usize).min(self.indices.len());
+                start..(start + s.index_count.max(0) as usize).min(self.indices.len())
+            })
+            .collect()
     }
 }
 
-impl Helicopter {
-    pub fn load(path: &str) -> Self {
-        println!("Loading helicopter model...");
+// Lunar terrain
+
+pub struct Terrain;
+
+// How close terrain vertices have to be to be welded
+const TERRAIN_WELD_EPSILON: f32 = 1e-4;
+
+impl Terrain {
+    /// Loads the terrain, from its binary cache if that is up to date
+    pub fn load(path: &str) -> Result<Mesh, MeshLoadError> {
+        Mesh::load_cached(path, || Terrain::load_obj(path))
+    }
+
+    // internal helper, parses the OBJ file itself
+    fn load_obj(path: &str) -> Result<Mesh, MeshLoadError> {
+        println!("Loading terrain model...");
         let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(
+        let (models, materials) = tobj::load_obj(
             path,
             &tobj::LoadOptions {
                 triangulate: true,
                 single_index: true,
                 ..Default::default()
             },
-        )
-        .expect("Failed to load helicopter model");
+        )?;
+        let materials = Material::load_all(materials, path);
         let after = std::time::Instant::now();
         println!(
-            "Done in {:.3}ms!",
+            "Done in {:.3}ms.",
             after.duration_since(before).as_micros() as f32 / 1e3
         );
 
+        if models.is_empty() {
+            return Err(MeshLoadError::WrongObjectCount {
+                expected: 1,
+                found: 0,
+            });
+        }
+
         for model in &models {
+            error::check_attributes(&model.name, &model.mesh)?;
             println!(
                 "Loaded {} with {} points and {} triangles.",
                 model.name,
                 model.mesh.positions.len() / 3,
-                model.mesh.indices.len() / 3
+                model.mesh.indices.len() / 3,
             );
         }
 
-        let body_model = models
-            .iter()
-            .find(|m| m.name == "Body_body")
-            .expect("Incorrect model file!")
-            .to_owned();
-        let door_model = models
+        // Terrain tiles are often exported as several objects, so merge them into a single mesh
+        if models
+            .windows(2)
+            .any(|pair| pair[0].mesh.material_id != pair[1].mesh.material_id)
+        {
+            println!(
+                "The terrain objects use different materials, only the first one is kept for textures."
+            );
+        }
+        let mut terrain = Mesh::merge(models.into_iter().map(|m| {
+            (
+                m.name,
+                Mesh::with_materials(m.mesh, &materials, [1.0, 1.0, 1.0, 1.0]),
+            )
+        }));
+
+        // The OBJ loader duplicates a vertex for every face using it, and the cache stores the result
+        let before = std::time::Instant::now();
+        let (unoptimized, optimized) = terrain.optimize(TERRAIN_WELD_EPSILON);
+        let after = std::time::Instant::now();
+        println!(
+            "Optimized terrain in {:.3}ms, from {} to {}.",
+            after.duration_since(before).as_micros() as f32 / 1e3,
+            unoptimized,
+            optimized
+        );
+        Ok(terrain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm as glm;
+    use std::fs;
+
+    // internal helper, a triangle with its corners `offset` along X
+    fn triangle(offset: f32) -> tobj::Mesh {
+        tobj::Mesh {
+            positions: vec![offset, 0.0, 0.0, offset + 1.0, 0.0, 0.0, offset, 1.0, 0.0],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merging_rebases_the_indices() {
+        let red = Material {
+            name: "red".to_string(),
+            diffuse: [1.0, 0.0, 0.0],
+            ..Material::default()
+        };
+        let mut quad = triangle(2.0);
+        quad.positions.extend([3.0, 1.0, 0.0]);
+        quad.indices.extend([1, 3, 2]);
+        quad.material_id = Some(0);
+        let merged = Mesh::merge(vec![
+            (
+                "a".to_string(),
+                Mesh::with_materials(triangle(0.0), &[], [0.0, 0.0, 1.0, 1.0]),
+            ),
+            (
+                "b".to_string(),
+                Mesh::with_materials(quad, &[red], [1.0; 4]),
+            ),
+        ]);
+
+        assert_eq!(merged.vertices.len(), 7 * 3);
+        assert_eq!(merged.indices, [0, 1, 2, 3, 4, 5, 4, 6, 5]);
+        assert_eq!(merged.index_count, 9);
+        let ranges: Vec<(&str, i32, i32)> = merged
+            .sub_meshes
             .iter()
-            .find(|m| m.name == "Door_door")
-            .expect("Incorrect model file!")
-            .to_owned();
-        let main_rotor_model = models
+            .map(|s| (s.name.as_str(), s.first_index, s.index_count))
+            .collect();
+        assert_eq!(ranges, [("a", 0, 3), ("b", 3, 6)]);
+        assert_eq!(merged.triangle_ranges(), [0..3, 3..9]);
+        assert!(merged.has_normals());
+        assert_eq!(merged.bounds.aabb.max, glm::vec3(3.0, 1.0, 0.0));
+
+        // Single-material: the first part's material, and every part's vertex colors
+        assert_eq!(merged.material.name, "");
+        assert_eq!(merged.colors[..4], [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(merged.colors[3 * 4..4 * 4], [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn objects_load_on_their_own_and_merge() {
+        let dir = std::env::temp_dir().join("mesh_load_objects");
+        fs::create_dir_all(&dir).unwrap();
+        let obj = dir.join("tiles.obj");
+        fs::write(
+            &obj,
+            "mtllib tiles.mtl\n\
+             o west\nusemtl grey\nv 0 0 0\nv 1 0 0\nv 0 0 -1\nv 1 0 -1\nf 1 2 4 3\n\
+             o east\nusemtl white\nv 1 0 0\nv 2 0 0\nv 1 0 -1\nf 5 6 7\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("tiles.mtl"),
+            "newmtl grey\nKd 0.5 0.5 0.5\nnewmtl white\nKd 1 1 1\n",
+        )
+        .unwrap();
+
+        let objects = Mesh::load_objects(obj.to_str().unwrap()).unwrap();
+        let names: Vec<&str> = objects.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["west", "east"]);
+        let (west, east) = (&objects[0].1, &objects[1].1);
+        assert_eq!((west.indices.len(), east.indices.len()), (6, 3));
+        assert_eq!(west.material.name, "grey");
+        assert_eq!(west.colors[..4], [0.5, 0.5, 0.5, 1.0]);
+        assert_eq!(east.material.name, "white");
+        assert!(east
+            .indices
             .iter()
-            .find(|m| m.name == "Main_Rotor_main_rotor")
-            .expect("Incorrect model file!")
-            .to_owned();
-        let tail_rotor_model = models
+            .all(|&i| (i as usize) < east.vertices.len() / 3));
+
+        let west_vertices = west.vertices.len() as u32 / 3;
+        let expected: Vec<u32> = west
+            .indices
             .iter()
-            .find(|m| m.name == "Tail_Rotor_tail_rotor")
-            .expect("Incorrect model file!")
-            .to_owned();
-
-        Helicopter {
-            body: Mesh::from(body_model.mesh, [0.3, 0.3, 0.3, 1.0]),
-            door: Mesh::from(door_model.mesh, [0.1, 0.1, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh, [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh, [0.1, 0.3, 0.1, 1.0]),
-        }
+            .cloned()
+            .chain(east.indices.iter().map(|i| i + west_vertices))
+            .collect();
+        let merged = Mesh::merge(objects);
+        assert_eq!(merged.indices, expected);
+        assert_eq!(merged.triangle_ranges(), [0..6, 6..9]);
+        assert_eq!(merged.material.name, "grey");
     }
 }
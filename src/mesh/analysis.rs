@@ -0,0 +1,272 @@
+use super::{Aabb, Mesh};
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::fmt;
+
+// The topology is measured on the surface the mesh describes rather than on its vertices: vertices
+// at the same position, like the two sides of a UV seam, are the same point of the surface.
+// Triangles with two corners at the same point have no area and are left out.
+
+// How close vertices have to be to be the same point, relative to the size of the mesh
+const POINT_TOLERANCE: f32 = 1e-6;
+
+/// The geometry and topology of a mesh, from `Mesh::analyze`
+#[derive(Clone, Copy, Debug)]
+pub struct MeshAnalysis {
+    /// The distinct positions used by the triangles
+    pub point_count: usize,
+    pub triangle_count: usize,
+    pub degenerate_triangles: usize,
+    pub surface_area: f32,
+    /// The signed volume, positive if the triangles wind counter-clockwise seen from outside.
+    /// It only means something if the mesh is closed.
+    pub volume: f32,
+    /// Edges with a single triangle
+    pub boundary_edges: usize,
+    /// Edges with more than two triangles
+    pub non_manifold_edges: usize,
+    /// The number of pieces connected by their edges or points
+    pub components: usize,
+    /// Points - edges + triangles, 2 for each sphere-like piece
+    pub euler_characteristic: i64,
+    pub bounds: Aabb,
+}
+
+impl MeshAnalysis {
+    /// Returns true if every edge has exactly two triangles, so the mesh encloses a volume
+    pub fn is_closed(&self) -> bool {
+        self.boundary_edges == 0 && self.non_manifold_edges == 0
+    }
+
+    /// The total number of handles of the pieces, like the one hole of a torus.
+    /// Only known for closed meshes.
+    pub fn genus(&self) -> Option<i64> {
+        let twice_genus = 2 * self.components as i64 - self.euler_characteristic;
+        if self.is_closed() && twice_genus >= 0 && twice_genus % 2 == 0 {
+            Some(twice_genus / 2)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for MeshAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} points, {} triangles ({} degenerate)",
+            self.point_count, self.triangle_count, self.degenerate_triangles
+        )?;
+        write!(f, "surface area {:.3}, ", self.surface_area)?;
+        if self.is_closed() {
+            writeln!(f, "volume {:.3}", self.volume)?;
+        } else {
+            writeln!(f, "no volume, the mesh is not closed")?;
+        }
+        writeln!(
+            f,
+            "{} boundary edges, {} non-manifold edges",
+            self.boundary_edges, self.non_manifold_edges
+        )?;
+        write!(
+            f,
+            "{} components, Euler characteristic {}",
+            self.components, self.euler_characteristic
+        )?;
+        match self.genus() {
+            Some(genus) => writeln!(f, ", genus {}", genus)?,
+            None => writeln!(f)?,
+        }
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        write!(
+            f,
+            "bounds ({:.3}, {:.3}, {:.3}) to ({:.3}, {:.3}, {:.3})",
+            min.x, min.y, min.z, max.x, max.y, max.z
+        )
+    }
+}
+
+// internal helper, finds the representative of a point, halving the paths on the way
+fn find(parents: &mut [u32], mut p: u32) -> u32 {
+    while parents[p as usize] != p {
+        parents[p as usize] = parents[parents[p as usize] as usize];
+        p = parents[p as usize];
+    }
+    p
+}
+
+impl Mesh {
+    /// Measures the surface, volume and topology of the mesh
+    pub fn analyze(&self) -> MeshAnalysis {
+        let num_verts = self.vertices.len() / 3;
+        // Vertices are looked up in a grid of cells as large as the tolerance, so the same point
+        // is either in the same cell or in a neighbouring one
+        let aabb = Aabb::from_positions(&self.vertices);
+        let size = if aabb.is_empty() {
+            0.0
+        } else {
+            glm::distance(&aabb.min, &aabb.max)
+        };
+        let tolerance = (size * POINT_TOLERANCE).max(f32::MIN_POSITIVE);
+        let cell = |p: &glm::Vec3| [p.x, p.y, p.z].map(|x| (x / tolerance).floor() as i64);
+        let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        let mut positions: Vec<glm::Vec3> = Vec::new();
+        let point_of: Vec<u32> = self
+            .vertices
+            .chunks_exact(3)
+            .map(|p| {
+                let p = glm::vec3(p[0], p[1], p[2]);
+                let [x, y, z] = cell(&p);
+                let neighbours = (0..27).map(|i| [x + i % 3 - 1, y + i / 3 % 3 - 1, z + i / 9 - 1]);
+                let existing = neighbours
+                    .filter_map(|key| cells.get(&key))
+                    .flatten()
+                    .find(|&&other| glm::distance(&positions[other as usize], &p) <= tolerance);
+                match existing {
+                    Some(&point) => point,
+                    None => {
+                        positions.push(p);
+                        let point = positions.len() as u32 - 1;
+                        cells.entry([x, y, z]).or_default().push(point);
+                        point
+                    }
+                }
+            })
+            .collect();
+
+        let mut used = vec![false; positions.len()];
+        let mut parents: Vec<u32> = (0..positions.len() as u32).collect();
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        let mut bounds = Aabb::default();
+        let mut triangle_count = 0;
+        let mut degenerate_triangles = 0;
+        let mut surface_area = 0.0;
+        let mut volume = 0.0;
+
+        for tri in self.indices.chunks_exact(3) {
+            if tri.iter().any(|&v| v as usize >= num_verts) {
+                continue;
+            }
+            triangle_count += 1;
+            let [a, b, c] = [0, 1, 2].map(|k| point_of[tri[k] as usize]);
+            if a == b || b == c || c == a {
+                degenerate_triangles += 1;
+                continue;
+            }
+
+            let p = [a, b, c].map(|point| positions[point as usize]);
+            surface_area += (p[1] - p[0]).cross(&(p[2] - p[0])).norm() / 2.0;
+            // The signed volume of the tetrahedron between the triangle and the origin
+            volume += p[0].dot(&p[1].cross(&p[2])) / 6.0;
+
+            for (from, to) in [(a, b), (b, c), (c, a)] {
+                *edges.entry((from.min(to), from.max(to))).or_default() += 1;
+                let (root_from, root_to) = (find(&mut parents, from), find(&mut parents, to));
+                parents[root_from as usize] = root_to;
+            }
+            for (point, position) in [a, b, c].iter().zip(&p) {
+                used[*point as usize] = true;
+                bounds.min = glm::min2(&bounds.min, position);
+                bounds.max = glm::max2(&bounds.max, position);
+            }
+        }
+
+        let used_points: Vec<u32> = (0..positions.len() as u32)
+            .filter(|&p| used[p as usize])
+            .collect();
+        let components = used_points
+            .iter()
+            .filter(|&&p| find(&mut parents, p) == p)
+            .count();
+        let faces = triangle_count - degenerate_triangles;
+
+        MeshAnalysis {
+            point_count: used_points.len(),
+            triangle_count,
+            degenerate_triangles,
+            surface_area,
+            volume,
+            boundary_edges: edges.values().filter(|&&count| count == 1).count(),
+            non_manifold_edges: edges.values().filter(|&&count| count > 2).count(),
+            components,
+            euler_characteristic: used_points.len() as i64 - edges.len() as i64 + faces as i64,
+            bounds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const COLOR: [f32; 4] = [1.0; 4];
+
+    #[test]
+    fn a_cube_is_closed() {
+        let analysis = Mesh::cube(2.0, 3, COLOR).analyze();
+        assert!((analysis.surface_area - 24.0).abs() < 1e-4);
+        assert!((analysis.volume - 8.0).abs() < 1e-4);
+        assert_eq!(analysis.boundary_edges, 0);
+        assert_eq!(analysis.non_manifold_edges, 0);
+        assert_eq!(analysis.degenerate_triangles, 0);
+        assert_eq!(analysis.components, 1);
+        // The faces have their own vertices, but share the points along the edges
+        assert_eq!(analysis.point_count, 6 * 2 * 2 + 12 * 2 + 8);
+        assert_eq!(analysis.euler_characteristic, 2);
+        assert_eq!(analysis.genus(), Some(0));
+        assert_eq!(analysis.bounds.min, glm::vec3(-1.0, -1.0, -1.0));
+        assert_eq!(analysis.bounds.max, glm::vec3(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn a_sphere_is_closed() {
+        let analysis = Mesh::uv_sphere(2.0, 64, 32, COLOR).analyze();
+        // The facets lie a little inside the sphere
+        let area = 4.0 * PI * 4.0;
+        let volume = 4.0 / 3.0 * PI * 8.0;
+        assert!(analysis.surface_area < area && analysis.surface_area > 0.99 * area);
+        assert!(analysis.volume < volume && analysis.volume > 0.98 * volume);
+        assert!(analysis.is_closed());
+        assert_eq!(analysis.components, 1);
+        assert_eq!(analysis.euler_characteristic, 2);
+        assert_eq!(analysis.genus(), Some(0));
+    }
+
+    #[test]
+    fn inside_out_meshes_have_negative_volume() {
+        let mut cube = Mesh::cube(2.0, 1, COLOR);
+        for triangle in cube.indices.chunks_mut(3) {
+            triangle.swap(1, 2);
+        }
+        assert!((cube.analyze().volume + 8.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn a_plane_is_open() {
+        let analysis = Mesh::plane(4.0, 2.0, 4, 2, COLOR).analyze();
+        assert!((analysis.surface_area - 8.0).abs() < 1e-4);
+        assert!(!analysis.is_closed());
+        assert_eq!(analysis.boundary_edges, 2 * (4 + 2));
+        assert_eq!(analysis.non_manifold_edges, 0);
+        assert_eq!(analysis.components, 1);
+        assert_eq!(analysis.point_count, 5 * 3);
+        assert_eq!(analysis.euler_characteristic, 1);
+        assert_eq!(analysis.genus(), None);
+    }
+
+    #[test]
+    fn separate_pieces_are_counted() {
+        let mut two = Mesh::cube(1.0, 1, COLOR);
+        let mut other = Mesh::torus(3.0, 0.5, 24, 12, COLOR);
+        let offset = two.vertices.len() as u32 / 3;
+        two.vertices.append(&mut other.vertices);
+        two.indices
+            .extend(other.indices.iter().map(|&index| index + offset));
+        let analysis = two.analyze();
+        assert!(analysis.is_closed());
+        assert_eq!(analysis.components, 2);
+        assert_eq!(analysis.euler_characteristic, 2);
+        assert_eq!(analysis.genus(), Some(1));
+    }
+}
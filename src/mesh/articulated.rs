@@ -0,0 +1,369 @@
+use super::{error, Material, Mesh, MeshLoadError, Subdivision, MAX_SUBDIVISION_LEVELS};
+use crate::scene_graph::{
+    MaterialTextures, NodeId, SceneGraph, SceneGraphError, SceneNode, VertexArray,
+};
+use nalgebra_glm as glm;
+use std::path::{Path, PathBuf};
+
+// A descriptor is a small text file listing the parts of a model, one per line:
+//
+//     # The OBJ file, relative to the descriptor
+//     model helicopter.obj
+//     # part  name        object                 color (RGBA)     pivot (XYZ)     parent
+//     part    body        Body_body              0.3 0.3 0.3 1.0  0.0 0.0 0.0     -
+//     part    main_rotor  Main_Rotor_main_rotor  0.3 0.1 0.1 1.0  0.0 2.3 0.0     body
+//     # subdivide  part  scheme (loop or catmull-clark)  levels  [crease angle in degrees]
+//     subdivide    main_rotor  loop  2  45
+//
+// Parents must be listed before their children, and parts before they are subdivided.
+// Parts are subdivided at most `MAX_SUBDIVISION_LEVELS` times.
+// The color is only used if the object has no material.
+
+// The crease angle of subdivided parts that don't give one, in degrees
+const DEFAULT_CREASE_ANGLE: f32 = 60.0;
+
+/// How a part is subdivided once it is loaded
+#[derive(Clone, Copy, Debug)]
+pub struct PartSubdivision {
+    pub scheme: Subdivision,
+    pub levels: u32,
+    /// In radians
+    pub crease_angle: f32,
+}
+
+/// One part of an articulated model, as listed in a descriptor
+#[derive(Clone, Debug)]
+pub struct PartDescriptor {
+    pub name: String,
+    pub object: String,
+    pub color: [f32; 4],
+    pub pivot: glm::Vec3,
+    pub parent: Option<String>,
+    pub subdivision: Option<PartSubdivision>,
+}
+
+/// The parts of an articulated model and the OBJ file they are found in
+#[derive(Clone, Debug)]
+pub struct ModelDescriptor {
+    pub model_path: PathBuf,
+    pub parts: Vec<PartDescriptor>,
+}
+
+impl ModelDescriptor {
+    /// Parses a descriptor, with the model path relative to `base_dir`
+    pub fn parse(text: &str, base_dir: &Path) -> Result<Self, MeshLoadError> {
+        let mut model_path = None;
+        let mut parts: Vec<PartDescriptor> = Vec::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let error = |message: &str| {
+                MeshLoadError::Parse(format!("Line {}: {}", line_number + 1, message))
+            };
+            let tokens: Vec<&str> = line
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect();
+            let number = |token: &str| {
+                token
+                    .parse::<f32>()
+                    .map_err(|_| error(&format!("{} is not a number", token)))
+            };
+
+            match tokens.as_slice() {
+                [] => {}
+                ["model", path] => model_path = Some(base_dir.join(path)),
+                ["part", name, object, r, g, b, a, x, y, z, parent] => {
+                    if parts.iter().any(|p| p.name == *name) {
+                        return Err(error(&format!("The part {} is listed twice", name)));
+                    }
+                    let parent = match *parent {
+                        "-" => None,
+                        parent if parts.iter().any(|p| p.name == parent) => {
+                            Some(parent.to_string())
+                        }
+                        parent => {
+                            return Err(error(&format!(
+                                "The parent {} must be listed before {}",
+                                parent, name
+                            )))
+                        }
+                    };
+                    parts.push(PartDescriptor {
+                        name: name.to_string(),
+                        object: object.to_string(),
+                        color: [number(r)?, number(g)?, number(b)?, number(a)?],
+                        pivot: glm::vec3(number(x)?, number(y)?, number(z)?),
+                        parent,
+                        subdivision: None,
+                    });
+                }
+                ["subdivide", name, scheme, levels, rest @ ..] if rest.len() <= 1 => {
+                    let scheme = match *scheme {
+                        "loop" => Subdivision::Loop,
+                        "catmull-clark" => Subdivision::CatmullClark,
+                        scheme => {
+                            return Err(error(&format!(
+                                "Unknown subdivision scheme {}, expected loop or catmull-clark",
+                                scheme
+                            )))
+                        }
+                    };
+                    let levels = levels
+                        .parse()
+                        .map_err(|_| error(&format!("{} is not a number of levels", levels)))?;
+                    if levels > MAX_SUBDIVISION_LEVELS {
+                        return Err(error(&format!(
+                            "{} is subdivided {} times, at most {} are allowed",
+                            name, levels, MAX_SUBDIVISION_LEVELS
+                        )));
+                    }
+                    let crease_angle = match rest {
+                        [angle] => number(angle)?,
+                        _ => DEFAULT_CREASE_ANGLE,
+                    };
+                    let part = parts.iter_mut().find(|p| p.name == *name).ok_or_else(|| {
+                        error(&format!("{} is subdivided before it is listed", name))
+                    })?;
+                    part.subdivision = Some(PartSubdivision {
+                        scheme,
+                        levels,
+                        crease_angle: crease_angle.to_radians(),
+                    });
+                }
+                _ => {
+                    return Err(error(
+                        "Expected `model <path>`, a part with 10 values or a subdivided part",
+                    ))
+                }
+            }
+        }
+
+        Ok(ModelDescriptor {
+            model_path: model_path
+                .ok_or_else(|| MeshLoadError::Parse("The descriptor has no model".to_string()))?,
+            parts,
+        })
+    }
+
+    /// Reads and parses a descriptor file
+    pub fn load(path: &str) -> Result<Self, MeshLoadError> {
+        let text = std::fs::read_to_string(path)?;
+        let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        ModelDescriptor::parse(&text, base_dir)
+    }
+}
+
+/// A part of an articulated model, with its mesh loaded
+pub struct Part {
+    pub name: String,
+    pub mesh: Mesh,
+    pub pivot: glm::Vec3,
+    /// The index of the parent part, which always comes before this one
+    pub parent: Option<usize>,
+}
+
+/// A model made of named parts that move relative to each other, like a helicopter
+pub struct ArticulatedModel {
+    pub parts: Vec<Part>,
+}
+
+impl ArticulatedModel {
+    /// Loads the model described by a descriptor file
+    pub fn load(descriptor_path: &str) -> Result<Self, MeshLoadError> {
+        ArticulatedModel::from_descriptor(&ModelDescriptor::load(descriptor_path)?)
+    }
+
+    /// Loads the OBJ file of a descriptor and picks out the parts
+    pub fn from_descriptor(descriptor: &ModelDescriptor) -> Result<Self, MeshLoadError> {
+        let path = descriptor.model_path.to_string_lossy();
+        println!("Loading articulated model {}...", path);
+        let before = std::time::Instant::now();
+        let (models, materials) = tobj::load_obj(
+            path.as_ref(),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+        )?;
+        let materials = Material::load_all(materials, &path);
+        let after = std::time::Instant::now();
+        println!(
+            "Done in {:.3}ms!",
+            after.duration_since(before).as_micros() as f32 / 1e3
+        );
+
+        let mut parts: Vec<Part> = Vec::with_capacity(descriptor.parts.len());
+        for part in &descriptor.parts {
+            let model = models
+                .iter()
+                .find(|m| m.name == part.object)
+                .ok_or_else(|| MeshLoadError::MissingPart {
+                    name: part.object.clone(),
+                })?;
+            error::check_attributes(&model.name, &model.mesh)?;
+            println!(
+                "Loaded {} with {} points and {} triangles.",
+                model.name,
+                model.mesh.positions.len() / 3,
+                model.mesh.indices.len() / 3
+            );
+
+            let mut mesh = Mesh::with_materials(model.mesh.clone(), &materials, part.color);
+            if let Some(subdivision) = part.subdivision {
+                mesh.subdivide(
+                    subdivision.scheme,
+                    subdivision.levels,
+                    subdivision.crease_angle,
+                );
+                println!(
+                    "Subdivided {} {} times, to {} triangles.",
+                    part.name,
+                    subdivision.levels,
+                    mesh.indices.len() / 3
+                );
+            }
+
+            parts.push(Part {
+                name: part.name.clone(),
+                mesh,
+                pivot: part.pivot,
+                parent: part
+                    .parent
+                    .as_ref()
+                    .and_then(|parent| parts.iter().position(|p| &p.name == parent)),
+            });
+        }
+
+        Ok(ArticulatedModel { parts })
+    }
+
+    /// Finds a part by its name in the descriptor
+    #[allow(dead_code)]
+    pub fn part(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|p| p.name == name)
+    }
+
+    /// Adds the model to a scene graph below `parent`, with the children of each part in
+    /// descriptor order. `vaos` and `textures` must hold the VAO and textures of each of the parts,
+    /// in the same order. The nodes are named after their parts.
+    /// Returns the node of each part, in the same order too.
+    pub fn build_scene(
+        &self,
+        graph: &mut SceneGraph,
+        parent: NodeId,
+        vaos: &[VertexArray],
+        textures: &[MaterialTextures],
+    ) -> Result<Vec<NodeId>, SceneGraphError> {
+        let mut nodes: Vec<NodeId> = Vec::with_capacity(self.parts.len());
+        // Parents always come before their children
+        for (i, part) in self.parts.iter().enumerate() {
+            let mut node = SceneNode::new(vaos[i], part.mesh.index_count);
+            node.name = Some(part.name.clone());
+            node.reference_point = part.pivot;
+            node.material = part.mesh.material.clone();
+            node.texture_id = textures[i].diffuse;
+            node.normal_texture_id = textures[i].normal;
+
+            let parent = part.parent.map_or(parent, |p| nodes[p]);
+            nodes.push(graph.insert(parent, node)?);
+        }
+        Ok(nodes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // internal helper, the error message of a descriptor that doesn't parse
+    fn parse_error(text: &str) -> String {
+        match ModelDescriptor::parse(text, Path::new("")) {
+            Err(MeshLoadError::Parse(message)) => message,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn subdivision_levels_are_limited() {
+        let descriptor = |levels: u32| {
+            format!(
+                "model heli.obj\n\
+                 part rotor Rotor 1 1 1 1 0 0 0 -\n\
+                 subdivide rotor loop {}\n",
+                levels
+            )
+        };
+        let parsed = ModelDescriptor::parse(&descriptor(MAX_SUBDIVISION_LEVELS), Path::new(""));
+        let subdivision = parsed.unwrap().parts[0].subdivision.unwrap();
+        assert_eq!(subdivision.levels, MAX_SUBDIVISION_LEVELS);
+        assert!(matches!(
+            ModelDescriptor::parse(&descriptor(MAX_SUBDIVISION_LEVELS + 1), Path::new("")),
+            Err(MeshLoadError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn the_helicopter_is_not_subdivided_by_default() {
+        let descriptor = ModelDescriptor::load("resources/helicopter.parts").unwrap();
+        assert_eq!(descriptor.parts.len(), 4);
+        assert!(descriptor.parts.iter().all(|p| p.subdivision.is_none()));
+    }
+
+    #[test]
+    fn parts_need_ten_values() {
+        let parsed = ModelDescriptor::parse(
+            "model heli.obj\n\
+             part body Body 1 1 1 1 0 0 0 -\n\
+             part rotor Rotor 1 1 1 1 0 2 0 body # spins\n",
+            Path::new("resources"),
+        )
+        .unwrap();
+        assert_eq!(parsed.model_path, Path::new("resources/heli.obj"));
+        assert_eq!(parsed.parts[1].parent.as_deref(), Some("body"));
+        assert_eq!(parsed.parts[1].pivot, glm::vec3(0.0, 2.0, 0.0));
+
+        let expected =
+            "Line 2: Expected `model <path>`, a part with 10 values or a subdivided part";
+        for part in [
+            "part body Body 1 1 1 1 0 0 0",
+            "part body Body 1 1 1 0 0 0 -",
+            "part body Body 1 1 1 1 0 0 0 - -",
+        ] {
+            assert_eq!(
+                parse_error(&format!("model heli.obj\n{}\n", part)),
+                expected
+            );
+        }
+        assert_eq!(
+            parse_error("model heli.obj\npart body Body 1 one 1 1 0 0 0 -\n"),
+            "Line 2: one is not a number"
+        );
+    }
+
+    #[test]
+    fn parents_must_be_known() {
+        assert_eq!(
+            parse_error(
+                "model heli.obj\n\
+                 part rotor Rotor 1 1 1 1 0 2 0 body\n\
+                 part body Body 1 1 1 1 0 0 0 -\n"
+            ),
+            "Line 2: The parent body must be listed before rotor"
+        );
+        assert_eq!(
+            parse_error(
+                "model heli.obj\n\
+                 part body Body 1 1 1 1 0 0 0 -\n\
+                 part body Body 1 1 1 1 0 0 0 -\n"
+            ),
+            "Line 3: The part body is listed twice"
+        );
+        assert_eq!(
+            parse_error("model heli.obj\nsubdivide rotor loop 1\n"),
+            "Line 2: rotor is subdivided before it is listed"
+        );
+    }
+}
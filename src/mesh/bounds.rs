@@ -0,0 +1,341 @@
+use super::Mesh;
+use nalgebra_glm as glm;
+
+/// An axis-aligned bounding box. An empty box has `min` above `max`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Aabb {
+            min: glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: glm::vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Aabb {
+    /// The smallest box holding all of the points in a flat `[x, y, z, x, y, z, ...]` array
+    pub fn from_positions(positions: &[f32]) -> Self {
+        let mut aabb = Aabb::default();
+        for p in positions.chunks_exact(3) {
+            aabb.grow(&glm::vec3(p[0], p[1], p[2]));
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Grows the box to hold the point
+    pub fn grow(&mut self, point: &glm::Vec3) {
+        self.min = glm::min2(&self.min, point);
+        self.max = glm::max2(&self.max, point);
+    }
+
+    /// The smallest box holding both boxes
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: glm::min2(&self.min, &other.min),
+            max: glm::max2(&self.max, &other.max),
+        }
+    }
+
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// The size of the box along each axis, zero for an empty box
+    pub fn extents(&self) -> glm::Vec3 {
+        if self.is_empty() {
+            glm::Vec3::zeros()
+        } else {
+            self.max - self.min
+        }
+    }
+
+    pub fn contains(&self, point: &glm::Vec3) -> bool {
+        (0..3).all(|i| self.min[i] <= point[i] && point[i] <= self.max[i])
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+    }
+
+    /// The box holding this box after it is transformed, e.g. by the world matrix of a node.
+    /// It is exact for translations and scalings, and a bit loose for rotations.
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        // Each matrix element adds its smallest and largest contribution (Arvo, Graphics Gems 1990)
+        let translation = glm::vec3(matrix[(0, 3)], matrix[(1, 3)], matrix[(2, 3)]);
+        let mut transformed = Aabb {
+            min: translation,
+            max: translation,
+        };
+        for row in 0..3 {
+            for column in 0..3 {
+                let a = matrix[(row, column)] * self.min[column];
+                let b = matrix[(row, column)] * self.max[column];
+                transformed.min[row] += a.min(b);
+                transformed.max[row] += a.max(b);
+            }
+        }
+        transformed
+    }
+}
+
+/// A sphere holding every vertex of a mesh. An empty sphere has a negative radius.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: glm::Vec3,
+    pub radius: f32,
+}
+
+impl Default for BoundingSphere {
+    fn default() -> Self {
+        BoundingSphere {
+            center: glm::Vec3::zeros(),
+            radius: -1.0,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl BoundingSphere {
+    /// A sphere around the center of the bounding box of the points, with room for the farthest one.
+    /// This is not the smallest possible sphere, but never more than √3 times its radius.
+    pub fn from_positions(positions: &[f32], aabb: &Aabb) -> Self {
+        if aabb.is_empty() {
+            return BoundingSphere::default();
+        }
+        let center = aabb.center();
+        let radius = positions
+            .chunks_exact(3)
+            .map(|p| glm::distance(&center, &glm::vec3(p[0], p[1], p[2])))
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.radius < 0.0
+    }
+
+    pub fn contains(&self, point: &glm::Vec3) -> bool {
+        glm::distance(&self.center, point) <= self.radius
+    }
+
+    /// The sphere holding this sphere after it is transformed, using the largest scaling of the matrix
+    pub fn transformed(&self, matrix: &glm::Mat4) -> BoundingSphere {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = matrix * glm::vec4(self.center.x, self.center.y, self.center.z, 1.0);
+        let scale = (0..3)
+            .map(|c| glm::length(&glm::vec3(matrix[(0, c)], matrix[(1, c)], matrix[(2, c)])))
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            center: center.xyz(),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// The bounding volumes of a mesh, kept up to date by the constructors of `Mesh`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    pub fn from_positions(positions: &[f32]) -> Self {
+        let aabb = Aabb::from_positions(positions);
+        Bounds {
+            aabb,
+            sphere: BoundingSphere::from_positions(positions, &aabb),
+        }
+    }
+
+    /// The bounds after transforming the mesh, e.g. by the world matrix of a node
+    #[allow(dead_code)]
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Bounds {
+        Bounds {
+            aabb: self.aabb.transformed(matrix),
+            sphere: self.sphere.transformed(matrix),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Mesh {
+    /// Recomputes the bounds, needed after changing `vertices` by hand
+    pub fn update_bounds(&mut self) {
+        self.bounds = Bounds::from_positions(&self.vertices);
+    }
+
+    /// The average position of the vertices, or the origin for an empty mesh
+    pub fn centroid(&self) -> glm::Vec3 {
+        let num_verts = self.vertices.len() / 3;
+        if num_verts == 0 {
+            return glm::Vec3::zeros();
+        }
+        let sum = self
+            .vertices
+            .chunks_exact(3)
+            .fold(glm::Vec3::zeros(), |sum, p| {
+                sum + glm::vec3(p[0], p[1], p[2])
+            });
+        sum / num_verts as f32
+    }
+
+    /// The size of the mesh along each axis
+    pub fn extents(&self) -> glm::Vec3 {
+        self.bounds.aabb.extents()
+    }
+
+    /// Moves and uniformly scales the mesh to fit in the cube from -0.5 to 0.5, centered on the origin.
+    /// Returns the matrix that was applied, whose inverse puts the mesh back.
+    pub fn fit_to_unit_cube(&mut self) -> glm::Mat4 {
+        let largest = self.extents().max();
+        if largest <= 0.0 {
+            return glm::identity();
+        }
+        let scale = 1.0 / largest;
+        let center = self.bounds.aabb.center();
+        for p in self.vertices.chunks_exact_mut(3) {
+            for (i, value) in p.iter_mut().enumerate() {
+                *value = (*value - center[i]) * scale;
+            }
+        }
+        self.update_bounds();
+        glm::scaling(&glm::vec3(scale, scale, scale)) * glm::translation(&-center)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR: [f32; 4] = [1.0; 4];
+
+    // internal helper, a rotation about a skew axis and a translation
+    fn rotated_and_moved() -> glm::Mat4 {
+        glm::translation(&glm::vec3(3.0, -2.0, 5.0))
+            * glm::rotation(0.7, &glm::normalize(&glm::vec3(1.0, 2.0, -0.5)))
+    }
+
+    // internal helper, the mesh moved by a matrix
+    fn transformed_points(positions: &[f32], matrix: &glm::Mat4) -> Vec<glm::Vec3> {
+        positions
+            .chunks_exact(3)
+            .map(|p| (matrix * glm::vec4(p[0], p[1], p[2], 1.0)).xyz())
+            .collect()
+    }
+
+    #[test]
+    fn transformed_boxes_hold_their_corners() {
+        let aabb = Aabb {
+            min: glm::vec3(-1.0, 0.5, 2.0),
+            max: glm::vec3(2.0, 1.5, 4.0),
+        };
+        let matrix = rotated_and_moved();
+        let corners: Vec<f32> = (0..8)
+            .flat_map(|i| {
+                [0, 1, 2].map(|k| {
+                    if i >> k & 1 == 1 {
+                        aabb.max[k]
+                    } else {
+                        aabb.min[k]
+                    }
+                })
+            })
+            .collect();
+        let mut brute_force = Aabb::default();
+        for p in transformed_points(&corners, &matrix) {
+            brute_force.grow(&p);
+        }
+
+        let transformed = aabb.transformed(&matrix);
+        assert!(glm::distance(&transformed.min, &brute_force.min) < 1e-5);
+        assert!(glm::distance(&transformed.max, &brute_force.max) < 1e-5);
+        assert!(Aabb::default().transformed(&matrix).is_empty());
+    }
+
+    #[test]
+    fn bounds_hold_every_vertex() {
+        let mut torus = Mesh::torus(2.0, 0.5, 24, 12, COLOR);
+        for p in torus.vertices.chunks_exact_mut(3) {
+            p[0] = p[0] * 3.0 + 1.0;
+        }
+        torus.update_bounds();
+        let points = transformed_points(&torus.vertices, &glm::identity());
+        let Bounds { aabb, sphere } = torus.bounds;
+        assert!(points
+            .iter()
+            .all(|p| aabb.contains(p) && sphere.contains(p)));
+        assert_eq!(sphere.center, aabb.center());
+        // At most √3 times the smallest sphere, which is at least half as large as the box
+        assert!(sphere.radius <= 3f32.sqrt() * aabb.extents().max() / 2.0);
+
+        // Still so after a transform that doesn't scale evenly
+        let matrix = rotated_and_moved() * glm::scaling(&glm::vec3(1.0, 2.0, 0.5));
+        let moved = torus.bounds.transformed(&matrix);
+        for p in transformed_points(&torus.vertices, &matrix) {
+            assert!(moved.aabb.contains(&p));
+            assert!(glm::distance(&moved.sphere.center, &p) <= moved.sphere.radius + 1e-4);
+        }
+    }
+
+    #[test]
+    fn fit_to_unit_cube_fills_it() {
+        let mut capsule = Mesh::capsule(1.0, 6.0, 16, 8, COLOR);
+        for p in capsule.vertices.chunks_exact_mut(3) {
+            p[1] += 10.0;
+        }
+        capsule.update_bounds();
+        let original = capsule.vertices.clone();
+
+        let matrix = capsule.fit_to_unit_cube();
+        let aabb = capsule.bounds.aabb;
+        assert!((capsule.extents().max() - 1.0).abs() < 1e-5);
+        assert!(glm::distance(&aabb.center(), &glm::Vec3::zeros()) < 1e-5);
+        assert!(aabb.min.iter().all(|&x| x >= -0.5 - 1e-5));
+        assert!(aabb.max.iter().all(|&x| x <= 0.5 + 1e-5));
+        // The matrix does what was done, and its inverse undoes it
+        let fitted = transformed_points(&original, &matrix);
+        let restored = transformed_points(&capsule.vertices, &glm::inverse(&matrix));
+        let points = original
+            .chunks_exact(3)
+            .zip(capsule.vertices.chunks_exact(3));
+        for (v, (before, after)) in points.enumerate() {
+            assert!(glm::distance(&fitted[v], &glm::make_vec3(after)) < 1e-5);
+            assert!(glm::distance(&restored[v], &glm::make_vec3(before)) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn empty_meshes_have_empty_bounds() {
+        let mut empty = Mesh::from(tobj::Mesh::default(), COLOR);
+        assert!(empty.bounds.aabb.is_empty());
+        assert!(empty.bounds.sphere.is_empty());
+        assert_eq!(empty.extents(), glm::Vec3::zeros());
+        assert_eq!(empty.centroid(), glm::Vec3::zeros());
+        assert_eq!(empty.fit_to_unit_cube(), glm::Mat4::identity());
+        assert!(empty.bounds.aabb.is_empty());
+
+        // A single point has no size to scale up, but no NaNs either
+        let mut point = Mesh::from(tobj::Mesh::default(), COLOR);
+        point.vertices = vec![1.0, 2.0, 3.0];
+        point.update_bounds();
+        assert_eq!(point.fit_to_unit_cube(), glm::Mat4::identity());
+        assert_eq!(point.vertices, [1.0, 2.0, 3.0]);
+        assert_eq!(point.bounds.sphere.radius, 0.0);
+    }
+}
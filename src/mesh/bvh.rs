@@ -0,0 +1,729 @@
+use super::{Aabb, Mesh};
+use nalgebra_glm as glm;
+
+// A bounding volume hierarchy over the triangles of a mesh, built with binned SAH.
+//
+// The nodes are stored flat: an interior node has its two children next to each other at
+// `first` and `first + 1`, while a leaf covers `count` triangles starting at `first`.
+// The triangles are copied and sorted so every leaf is a contiguous range, which keeps the
+// queries independent of the mesh the tree was built from.
+
+const BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// The cost of visiting a node, relative to testing one triangle
+const TRAVERSAL_COST: f32 = 1.0;
+
+/// A ray, which does not need a normalised direction. Distances are in multiples of the direction.
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: glm::Vec3,
+    pub direction: glm::Vec3,
+}
+
+#[allow(dead_code)]
+impl Ray {
+    pub fn new(origin: glm::Vec3, direction: glm::Vec3) -> Self {
+        Ray { origin, direction }
+    }
+
+    pub fn at(&self, distance: f32) -> glm::Vec3 {
+        self.origin + self.direction * distance
+    }
+}
+
+/// Where a ray hit a triangle
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub struct RayHit {
+    /// The index of the triangle in the mesh, i.e. its first index is at `3 * triangle`
+    pub triangle: usize,
+    pub distance: f32,
+    pub point: glm::Vec3,
+    /// The weights of the three corners of the triangle at the hit point
+    pub barycentric: glm::Vec3,
+}
+
+/// The point of a mesh closest to some other point
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub struct ClosestPoint {
+    pub triangle: usize,
+    pub distance: f32,
+    pub point: glm::Vec3,
+    pub barycentric: glm::Vec3,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    aabb: Aabb,
+    first: u32,
+    // 0 for interior nodes
+    count: u32,
+}
+
+/// A bounding volume hierarchy for fast ray, closest point and overlap queries on a mesh
+pub struct Bvh {
+    nodes: Vec<Node>,
+    triangles: Vec<[glm::Vec3; 3]>,
+    // The index in the mesh of each of the sorted triangles
+    triangle_ids: Vec<u32>,
+}
+
+// internal helper, the bounds of a triangle
+fn triangle_aabb(triangle: &[glm::Vec3; 3]) -> Aabb {
+    let mut aabb = Aabb::default();
+    for corner in triangle {
+        aabb.grow(corner);
+    }
+    aabb
+}
+
+// internal helper
+fn surface_area(aabb: &Aabb) -> f32 {
+    let e = aabb.extents();
+    2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+}
+
+#[allow(dead_code)]
+impl Bvh {
+    /// Builds the hierarchy over the triangles of the mesh
+    pub fn build(mesh: &Mesh) -> Self {
+        let position = |i: u32| {
+            let i = i as usize * 3;
+            glm::vec3(mesh.vertices[i], mesh.vertices[i + 1], mesh.vertices[i + 2])
+        };
+        let triangles: Vec<[glm::Vec3; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|t| [position(t[0]), position(t[1]), position(t[2])])
+            .collect();
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(triangles.len().max(1) * 2),
+            triangle_ids: (0..triangles.len() as u32).collect(),
+            triangles,
+        };
+        let aabbs: Vec<Aabb> = bvh.triangles.iter().map(triangle_aabb).collect();
+        let centroids: Vec<glm::Vec3> = aabbs.iter().map(|a| a.center()).collect();
+
+        bvh.nodes.push(Node {
+            aabb: Aabb::default(),
+            first: 0,
+            count: bvh.triangles.len() as u32,
+        });
+        let mut order: Vec<usize> = (0..bvh.triangles.len()).collect();
+        bvh.subdivide(0, &mut order, 0, &aabbs, &centroids);
+
+        bvh.triangles = order.iter().map(|&i| bvh.triangles[i]).collect();
+        bvh.triangle_ids = order.iter().map(|&i| i as u32).collect();
+        bvh
+    }
+
+    // internal helper, splits the node covering `order[first..first + count]` until the leaves are small
+    fn subdivide(
+        &mut self,
+        node: usize,
+        order: &mut [usize],
+        first: usize,
+        aabbs: &[Aabb],
+        centroids: &[glm::Vec3],
+    ) {
+        let count = self.nodes[node].count as usize;
+        let range = &mut order[first..first + count];
+        let mut aabb = Aabb::default();
+        let mut centroid_bounds = Aabb::default();
+        for &i in range.iter() {
+            aabb = aabb.union(&aabbs[i]);
+            centroid_bounds.grow(&centroids[i]);
+        }
+        self.nodes[node].aabb = aabb;
+        self.nodes[node].first = first as u32;
+        if count <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        // Find the cheapest split along any axis by sorting the centroids into bins
+        let mut best: Option<(usize, usize, f32)> = None;
+        for axis in 0..3 {
+            let (low, high) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+            if high <= low {
+                continue;
+            }
+            let scale = BINS as f32 / (high - low);
+            let bin_of = |c: &glm::Vec3| (((c[axis] - low) * scale) as usize).min(BINS - 1);
+
+            let mut bins = [(Aabb::default(), 0usize); BINS];
+            for &i in range.iter() {
+                let bin = &mut bins[bin_of(&centroids[i])];
+                bin.0 = bin.0.union(&aabbs[i]);
+                bin.1 += 1;
+            }
+
+            // Sweep from the right to get the cost of every right-hand side, then from the left
+            let mut right_costs = [0.0; BINS];
+            let (mut right, mut right_count) = (Aabb::default(), 0);
+            for split in (1..BINS).rev() {
+                right = right.union(&bins[split].0);
+                right_count += bins[split].1;
+                right_costs[split] = surface_area(&right) * right_count as f32;
+            }
+            let (mut left, mut left_count) = (Aabb::default(), 0);
+            for split in 1..BINS {
+                left = left.union(&bins[split - 1].0);
+                left_count += bins[split - 1].1;
+                if left_count == 0 || left_count == count {
+                    continue;
+                }
+                let cost = surface_area(&left) * left_count as f32 + right_costs[split];
+                let better = match best {
+                    Some((_, _, best_cost)) => cost < best_cost,
+                    None => true,
+                };
+                if better {
+                    best = Some((axis, split, cost));
+                }
+            }
+        }
+
+        let leaf_cost = count as f32 * surface_area(&aabb);
+        let (axis, split_bin) = match best {
+            Some((axis, split_bin, cost))
+                if TRAVERSAL_COST * surface_area(&aabb) + cost < leaf_cost =>
+            {
+                (axis, split_bin)
+            }
+            _ => return,
+        };
+
+        // Partition the range in place, the same way the bins were filled
+        let (low, high) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+        let scale = BINS as f32 / (high - low);
+        let mut left_count = 0;
+        for i in 0..count {
+            let bin = (((centroids[range[i]][axis] - low) * scale) as usize).min(BINS - 1);
+            if bin < split_bin {
+                range.swap(i, left_count);
+                left_count += 1;
+            }
+        }
+
+        let left = self.nodes.len();
+        self.nodes[node].first = left as u32;
+        self.nodes[node].count = 0;
+        self.nodes.push(Node {
+            aabb: Aabb::default(),
+            first: 0,
+            count: left_count as u32,
+        });
+        self.nodes.push(Node {
+            aabb: Aabb::default(),
+            first: 0,
+            count: (count - left_count) as u32,
+        });
+        self.subdivide(left, order, first, aabbs, centroids);
+        self.subdivide(left + 1, order, first + left_count, aabbs, centroids);
+    }
+
+    // internal helper, the traversal stack to start a query with
+    fn root_stack(&self) -> Vec<usize> {
+        if self.triangles.is_empty() {
+            Vec::new()
+        } else {
+            vec![0]
+        }
+    }
+
+    /// The bounds of the whole mesh
+    pub fn aabb(&self) -> Aabb {
+        self.nodes[0].aabb
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// The closest hit of the ray closer than `max_distance`, from either side of the triangles
+    pub fn intersect_ray(&self, ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        let inverse = glm::vec3(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        let mut closest: Option<RayHit> = None;
+        let mut max_distance = max_distance;
+        let mut stack = self.root_stack();
+
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if ray_aabb(ray, &inverse, &node.aabb, max_distance).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for i in node.first as usize..(node.first + node.count) as usize {
+                    if let Some((distance, u, v)) = ray_triangle(ray, &self.triangles[i]) {
+                        if distance < max_distance {
+                            max_distance = distance;
+                            closest = Some(RayHit {
+                                triangle: self.triangle_ids[i] as usize,
+                                distance,
+                                point: ray.at(distance),
+                                barycentric: glm::vec3(1.0 - u - v, u, v),
+                            });
+                        }
+                    }
+                }
+            } else {
+                // Visit the nearer child first, so farther ones are more likely to be culled
+                let (left, right) = (node.first as usize, node.first as usize + 1);
+                let near = |i: usize| ray_aabb(ray, &inverse, &self.nodes[i].aabb, max_distance);
+                match (near(left), near(right)) {
+                    (Some(l), Some(r)) if l < r => stack.extend_from_slice(&[right, left]),
+                    (Some(_), Some(_)) => stack.extend_from_slice(&[left, right]),
+                    (Some(_), None) => stack.push(left),
+                    (None, Some(_)) => stack.push(right),
+                    (None, None) => {}
+                }
+            }
+        }
+        closest
+    }
+
+    /// The point on the mesh closest to `point`, or `None` for an empty mesh
+    pub fn closest_point(&self, point: &glm::Vec3) -> Option<ClosestPoint> {
+        let mut closest: Option<ClosestPoint> = None;
+        let mut best = f32::INFINITY;
+        let mut stack = self.root_stack();
+
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if aabb_distance_squared(&node.aabb, point) >= best {
+                continue;
+            }
+            if node.count > 0 {
+                for i in node.first as usize..(node.first + node.count) as usize {
+                    let (candidate, barycentric) = closest_on_triangle(point, &self.triangles[i]);
+                    let distance = glm::distance2(point, &candidate);
+                    if distance < best {
+                        best = distance;
+                        closest = Some(ClosestPoint {
+                            triangle: self.triangle_ids[i] as usize,
+                            distance: distance.sqrt(),
+                            point: candidate,
+                            barycentric,
+                        });
+                    }
+                }
+            } else {
+                let (left, right) = (node.first as usize, node.first as usize + 1);
+                let distance = |i: usize| aabb_distance_squared(&self.nodes[i].aabb, point);
+                if distance(left) < distance(right) {
+                    stack.extend_from_slice(&[right, left]);
+                } else {
+                    stack.extend_from_slice(&[left, right]);
+                }
+            }
+        }
+        closest
+    }
+
+    /// The indices of the triangles that overlap the box
+    pub fn overlapping(&self, aabb: &Aabb) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut stack = self.root_stack();
+
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if node.aabb.is_empty() || !node.aabb.intersects(aabb) {
+                continue;
+            }
+            if node.count > 0 {
+                for i in node.first as usize..(node.first + node.count) as usize {
+                    if triangle_overlaps_aabb(&self.triangles[i], aabb) {
+                        found.push(self.triangle_ids[i] as usize);
+                    }
+                }
+            } else {
+                stack.extend_from_slice(&[node.first as usize, node.first as usize + 1]);
+            }
+        }
+        found
+    }
+}
+
+impl Mesh {
+    /// Builds a bounding volume hierarchy over the triangles of the mesh
+    #[allow(dead_code)]
+    pub fn build_bvh(&self) -> Bvh {
+        Bvh::build(self)
+    }
+}
+
+// Intersection tests
+
+// internal helper, slab test returning the distance where the ray enters the box
+fn ray_aabb(ray: &Ray, inverse: &glm::Vec3, aabb: &Aabb, max_distance: f32) -> Option<f32> {
+    let (mut near, mut far) = (0.0f32, max_distance);
+    for axis in 0..3 {
+        // A ray parallel to the slab is either inside of it all the way or never
+        if ray.direction[axis] == 0.0 {
+            if ray.origin[axis] < aabb.min[axis] || ray.origin[axis] > aabb.max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t1 = (aabb.min[axis] - ray.origin[axis]) * inverse[axis];
+        let t2 = (aabb.max[axis] - ray.origin[axis]) * inverse[axis];
+        near = near.max(t1.min(t2));
+        far = far.min(t1.max(t2));
+    }
+    if near <= far {
+        Some(near)
+    } else {
+        None
+    }
+}
+
+// internal helper, Möller–Trumbore returning the distance and the weights of the second and third corner
+fn ray_triangle(ray: &Ray, triangle: &[glm::Vec3; 3]) -> Option<(f32, f32, f32)> {
+    let edge1 = triangle[1] - triangle[0];
+    let edge2 = triangle[2] - triangle[0];
+    let p = ray.direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < f32::EPSILON * edge1.norm() * edge2.norm() * ray.direction.norm() {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - triangle[0];
+    let u = s.dot(&p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(&edge1);
+    let v = ray.direction.dot(&q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(&q) * inverse;
+    if distance >= 0.0 {
+        Some((distance, u, v))
+    } else {
+        None
+    }
+}
+
+// internal helper
+fn aabb_distance_squared(aabb: &Aabb, point: &glm::Vec3) -> f32 {
+    if aabb.is_empty() {
+        return f32::INFINITY;
+    }
+    let clamped = glm::clamp_vec(point, &aabb.min, &aabb.max);
+    glm::distance2(point, &clamped)
+}
+
+// internal helper, from Ericson's Real-Time Collision Detection, returns the point and its barycentrics
+fn closest_on_triangle(p: &glm::Vec3, triangle: &[glm::Vec3; 3]) -> (glm::Vec3, glm::Vec3) {
+    let [a, b, c] = *triangle;
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (ab.dot(&ap), ac.dot(&ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return (a, glm::vec3(1.0, 0.0, 0.0));
+    }
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(&bp), ac.dot(&bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return (b, glm::vec3(0.0, 1.0, 0.0));
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return (a + ab * v, glm::vec3(1.0 - v, v, 0.0));
+    }
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(&cp), ac.dot(&cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return (c, glm::vec3(0.0, 0.0, 1.0));
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return (a + ac * w, glm::vec3(1.0 - w, 0.0, w));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return (b + (c - b) * w, glm::vec3(0.0, 1.0 - w, w));
+    }
+    let denominator = va + vb + vc;
+    if denominator <= 0.0 {
+        // A degenerate triangle that is not a point or a segment from one corner, use the nearest corner
+        return [(a, 0), (b, 1), (c, 2)]
+            .iter()
+            .map(|&(corner, i)| (corner, glm::Vec3::ith(i, 1.0)))
+            .min_by(|x, y| glm::distance2(p, &x.0).total_cmp(&glm::distance2(p, &y.0)))
+            .unwrap();
+    }
+    let (v, w) = (vb / denominator, vc / denominator);
+    (a + ab * v + ac * w, glm::vec3(1.0 - v - w, v, w))
+}
+
+// internal helper, separating axis test from Akenine-Möller's "Fast 3D Triangle-Box Overlap Testing"
+fn triangle_overlaps_aabb(triangle: &[glm::Vec3; 3], aabb: &Aabb) -> bool {
+    if !triangle_aabb(triangle).intersects(aabb) {
+        return false;
+    }
+    let center = aabb.center();
+    let half = aabb.extents() * 0.5;
+    let v = [
+        triangle[0] - center,
+        triangle[1] - center,
+        triangle[2] - center,
+    ];
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+
+    // Checks whether the triangle and the box are separated along the axis
+    let separated = |axis: glm::Vec3| {
+        let projections = [axis.dot(&v[0]), axis.dot(&v[1]), axis.dot(&v[2])];
+        let min = projections[0].min(projections[1]).min(projections[2]);
+        let max = projections[0].max(projections[1]).max(projections[2]);
+        let radius = half.x * axis.x.abs() + half.y * axis.y.abs() + half.z * axis.z.abs();
+        min > radius || max < -radius
+    };
+
+    // The box axes are covered by the bounds check above, leaving the edge cross products and the normal
+    for edge in &edges {
+        for i in 0..3 {
+            if separated(glm::Vec3::ith(i, 1.0).cross(edge)) {
+                return false;
+            }
+        }
+    }
+    !separated(edges[0].cross(&edges[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR: [f32; 4] = [1.0; 4];
+
+    fn corners(mesh: &Mesh, triangle: usize) -> [glm::Vec3; 3] {
+        let corner = |k: usize| {
+            let v = mesh.indices[triangle * 3 + k] as usize * 3;
+            glm::vec3(mesh.vertices[v], mesh.vertices[v + 1], mesh.vertices[v + 2])
+        };
+        [corner(0), corner(1), corner(2)]
+    }
+
+    // The barycentrics have to add up to one and give back the point on the reported triangle
+    fn check_barycentric(mesh: &Mesh, triangle: usize, point: &glm::Vec3, barycentric: &glm::Vec3) {
+        let [a, b, c] = corners(mesh, triangle);
+        assert!((barycentric.x + barycentric.y + barycentric.z - 1.0).abs() < 1e-5);
+        assert!(barycentric.iter().all(|&w| w >= -1e-5));
+        let rebuilt = a * barycentric.x + b * barycentric.y + c * barycentric.z;
+        assert!(
+            glm::distance(&rebuilt, point) < 1e-4,
+            "{} {}",
+            rebuilt,
+            point
+        );
+    }
+
+    // Directions spread over the sphere
+    fn directions(count: usize) -> Vec<glm::Vec3> {
+        let golden = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+        (0..count)
+            .map(|i| {
+                let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+                let r = (1.0 - y * y).sqrt();
+                let phi = golden * i as f32;
+                glm::vec3(r * phi.cos(), y, r * phi.sin())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rays_hit_the_cube() {
+        let cube = Mesh::cube(2.0, 2, COLOR);
+        let bvh = cube.build_bvh();
+        assert_eq!(bvh.triangle_count(), cube.indices.len() / 3);
+
+        let ray = Ray::new(glm::vec3(0.2, 0.3, -5.0), glm::vec3(0.0, 0.0, 1.0));
+        let hit = bvh.intersect_ray(&ray, f32::INFINITY).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert!(glm::distance(&hit.point, &glm::vec3(0.2, 0.3, -1.0)) < 1e-5);
+        check_barycentric(&cube, hit.triangle, &hit.point, &hit.barycentric);
+
+        // Distances are in multiples of the direction
+        let ray = Ray::new(glm::vec3(0.2, 0.3, -5.0), glm::vec3(0.0, 0.0, 2.0));
+        let hit = bvh.intersect_ray(&ray, f32::INFINITY).unwrap();
+        assert!((hit.distance - 2.0).abs() < 1e-5);
+
+        // A diagonal ray enters through a corner region of the +x face
+        let ray = Ray::new(glm::vec3(3.0, 0.5, 0.5), glm::vec3(-1.0, 0.1, 0.1));
+        let hit = bvh.intersect_ray(&ray, f32::INFINITY).unwrap();
+        assert!((hit.point.x - 1.0).abs() < 1e-5);
+        check_barycentric(&cube, hit.triangle, &hit.point, &hit.barycentric);
+    }
+
+    #[test]
+    fn rays_miss_the_cube() {
+        let bvh = Mesh::cube(2.0, 2, COLOR).build_bvh();
+        let z = glm::vec3(0.0, 0.0, 1.0);
+        // Passing beside it
+        assert!(bvh
+            .intersect_ray(&Ray::new(glm::vec3(1.5, 0.0, -5.0), z), f32::INFINITY)
+            .is_none());
+        // Pointing away from it
+        assert!(bvh
+            .intersect_ray(&Ray::new(glm::vec3(0.0, 0.0, -5.0), -z), f32::INFINITY)
+            .is_none());
+        // Stopping short of it
+        assert!(bvh
+            .intersect_ray(&Ray::new(glm::vec3(0.0, 0.0, -5.0), z), 3.9)
+            .is_none());
+    }
+
+    #[test]
+    fn rays_from_inside_hit_the_far_side() {
+        let cube = Mesh::cube(2.0, 3, COLOR);
+        let bvh = cube.build_bvh();
+        let ray = Ray::new(glm::vec3(0.0, 0.25, 0.0), glm::vec3(1.0, 0.0, 0.0));
+        let hit = bvh.intersect_ray(&ray, f32::INFINITY).unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-5);
+        check_barycentric(&cube, hit.triangle, &hit.point, &hit.barycentric);
+
+        let sphere = Mesh::uv_sphere(1.0, 48, 24, COLOR);
+        let bvh = sphere.build_bvh();
+        for direction in directions(200) {
+            let hit = bvh
+                .intersect_ray(&Ray::new(glm::zero(), direction), f32::INFINITY)
+                .unwrap();
+            // The facets lie a little inside the sphere
+            assert!(
+                hit.distance <= 1.0 + 1e-5 && hit.distance > 0.99,
+                "{}",
+                hit.distance
+            );
+            check_barycentric(&sphere, hit.triangle, &hit.point, &hit.barycentric);
+        }
+    }
+
+    #[test]
+    fn rays_hit_the_sphere_from_outside() {
+        let sphere = Mesh::uv_sphere(1.0, 48, 24, COLOR);
+        let bvh = sphere.build_bvh();
+        for direction in directions(200) {
+            let ray = Ray::new(direction * 5.0, -direction);
+            let hit = bvh.intersect_ray(&ray, f32::INFINITY).unwrap();
+            assert!(
+                hit.distance >= 4.0 - 1e-5 && hit.distance < 4.01,
+                "{}",
+                hit.distance
+            );
+            check_barycentric(&sphere, hit.triangle, &hit.point, &hit.barycentric);
+
+            // Just beside the sphere, the ray misses
+            let side = direction.cross(&glm::vec3(0.3, 0.5, 0.7)).normalize();
+            let ray = Ray::new(direction * 5.0 + side * 1.01, -direction);
+            assert!(bvh.intersect_ray(&ray, f32::INFINITY).is_none());
+        }
+    }
+
+    #[test]
+    fn closest_point_matches_brute_force() {
+        for mesh in [
+            Mesh::cube(2.0, 3, COLOR),
+            Mesh::uv_sphere(1.5, 24, 12, COLOR),
+        ] {
+            let bvh = mesh.build_bvh();
+            let queries = directions(50)
+                .into_iter()
+                .zip([0.0, 0.3, 1.0, 1.7, 4.0].iter().cycle())
+                .map(|(direction, &scale)| direction * scale);
+            for query in queries {
+                let found = bvh.closest_point(&query).unwrap();
+                let brute_force = (0..mesh.indices.len() / 3)
+                    .map(|t| {
+                        glm::distance(&query, &closest_on_triangle(&query, &corners(&mesh, t)).0)
+                    })
+                    .fold(f32::INFINITY, f32::min);
+                assert!((found.distance - brute_force).abs() < 1e-5);
+                assert!((glm::distance(&query, &found.point) - found.distance).abs() < 1e-5);
+                check_barycentric(&mesh, found.triangle, &found.point, &found.barycentric);
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_boxes() {
+        let cube = Mesh::cube(2.0, 2, COLOR);
+        let bvh = cube.build_bvh();
+        let brute_force = |aabb: &Aabb| {
+            let mut found: Vec<usize> = (0..cube.indices.len() / 3)
+                .filter(|&t| triangle_overlaps_aabb(&corners(&cube, t), aabb))
+                .collect();
+            found.sort_unstable();
+            found
+        };
+        let sorted = |mut found: Vec<usize>| {
+            found.sort_unstable();
+            found
+        };
+
+        // A small box on the +x face only touches triangles of that face
+        let on_face = Aabb {
+            min: glm::vec3(0.9, 0.1, 0.1),
+            max: glm::vec3(1.1, 0.4, 0.4),
+        };
+        let found = sorted(bvh.overlapping(&on_face));
+        assert!(!found.is_empty());
+        assert_eq!(found, brute_force(&on_face));
+        for &t in &found {
+            assert!(corners(&cube, t).iter().all(|c| (c.x - 1.0).abs() < 1e-6));
+        }
+
+        // A box around everything touches every triangle
+        let around = Aabb {
+            min: glm::vec3(-2.0, -2.0, -2.0),
+            max: glm::vec3(2.0, 2.0, 2.0),
+        };
+        assert_eq!(bvh.overlapping(&around).len(), cube.indices.len() / 3);
+
+        // Boxes away from the surface touch nothing, even one inside the cube
+        for separated in [
+            Aabb {
+                min: glm::vec3(3.0, 3.0, 3.0),
+                max: glm::vec3(4.0, 4.0, 4.0),
+            },
+            Aabb {
+                min: glm::vec3(-0.5, -0.5, -0.5),
+                max: glm::vec3(0.5, 0.5, 0.5),
+            },
+        ] {
+            assert!(bvh.overlapping(&separated).is_empty());
+            assert!(brute_force(&separated).is_empty());
+        }
+    }
+
+    #[test]
+    fn rays_along_shared_edges_hit() {
+        // Straight down every inner line of a tilted grid, exactly between the triangles
+        let mut plane = Mesh::plane(20.0, 20.0, 8, 8, COLOR);
+        for p in plane.vertices.chunks_exact_mut(3) {
+            p[1] = 0.5 * p[0] + 3.0;
+        }
+        let bvh = plane.build_bvh();
+        for i in 1..8 {
+            let line = i as f32 * 2.5 - 10.0;
+            for j in 0..40 {
+                let along = j as f32 * 0.49 - 9.6;
+                for (x, z) in [(line, along), (along, line)] {
+                    let ray = Ray::new(glm::vec3(x, 20.0, z), glm::vec3(0.0, -1.0, 0.0));
+                    let hit = bvh.intersect_ray(&ray, 40.0);
+                    assert!(hit.is_some(), "{} {}", x, z);
+                    assert!((hit.unwrap().point.y - (0.5 * x + 3.0)).abs() < 1e-4);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,559 @@
+// A compact binary format for meshes, so large OBJ files only have to be parsed once.
+//
+// Layout, all little-endian:
+// - header: magic `GMSH`, format version (u32), FNV-1a checksum of the body (u64), body length (u64)
+// - body: vertex count, index count and attribute flags (u32 each), then the positions,
+//   normals, colors, texture coordinates and tangents present (f32), the indices (u32),
+//   the sub-meshes, the material and finally the other files the mesh was built from, like
+//   material libraries, with their lengths and modification times
+
+use super::{Bounds, Material, Mesh, MeshLoadError, SubMesh};
+use std::{
+    fs,
+    io::{self, BufRead, ErrorKind},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const MAGIC: &[u8; 4] = b"GMSH";
+const VERSION: u32 = 3;
+const HEADER_LEN: usize = 4 + 4 + 8 + 8;
+
+const HAS_NORMALS: u32 = 1 << 0;
+const HAS_COLORS: u32 = 1 << 1;
+const HAS_TEXCOORDS: u32 = 1 << 2;
+const HAS_TANGENTS: u32 = 1 << 3;
+
+// internal helper
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// internal helper, 64-bit FNV-1a
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// internal helper for writing the body
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.0.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    fn path(&mut self, value: &Option<PathBuf>) {
+        match value {
+            Some(path) => {
+                self.0.push(1);
+                self.string(&path.to_string_lossy());
+            }
+            None => self.0.push(0),
+        }
+    }
+}
+
+// internal helper for reading the body, every read fails cleanly on truncated data
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(invalid_data("Mesh cache is truncated"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn f32s(&mut self, count: usize) -> io::Result<Vec<f32>> {
+        Ok(self
+            .bytes(
+                count
+                    .checked_mul(4)
+                    .ok_or_else(|| invalid_data("Mesh cache is corrupt"))?,
+            )?
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+
+    fn u32s(&mut self, count: usize) -> io::Result<Vec<u32>> {
+        Ok(self
+            .bytes(
+                count
+                    .checked_mul(4)
+                    .ok_or_else(|| invalid_data("Mesh cache is corrupt"))?,
+            )?
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| invalid_data("Mesh cache has an invalid string"))
+    }
+
+    fn path(&mut self) -> io::Result<Option<PathBuf>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(PathBuf::from(self.string()?))),
+        }
+    }
+}
+
+// internal helper, a file a cached mesh was built from besides its source, and how it was then
+struct Dependency {
+    path: PathBuf,
+    stamp: Option<Stamp>,
+}
+
+// internal helper, the length and modification time of a file, in seconds and nanoseconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Stamp {
+    len: u64,
+    seconds: u64,
+    nanoseconds: u32,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Stamp> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp {
+            len: metadata.len(),
+            seconds: modified.as_secs(),
+            nanoseconds: modified.subsec_nanos(),
+        })
+    }
+}
+
+// internal helper, the material libraries an OBJ file refers to, relative to its directory.
+// Other files have none.
+fn material_libraries(source: &Path) -> Vec<PathBuf> {
+    let dir = source.parent().unwrap_or_else(|| Path::new(""));
+    let file = match fs::File::open(source) {
+        Ok(file) => io::BufReader::new(file),
+        Err(_) => return Vec::new(),
+    };
+    file.split(b'\n')
+        .map_while(Result::ok)
+        .filter_map(|line| {
+            let line = String::from_utf8_lossy(&line).into_owned();
+            let names = line.trim().strip_prefix("mtllib ")?.to_string();
+            Some(names)
+        })
+        .flat_map(|names| {
+            names
+                .split_whitespace()
+                .map(|name| dir.join(name))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Where the cache of a model file is stored, e.g. `lunarsurface.obj.meshcache`
+pub fn cache_path(source: &Path) -> PathBuf {
+    let mut name = source.as_os_str().to_owned();
+    name.push(".meshcache");
+    PathBuf::from(name)
+}
+
+// internal helper, replaces a file in one go, so a crash while writing never leaves half of it
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, bytes)?;
+    fs::rename(&temporary, path)
+}
+
+// internal helper, true if the cache exists and was written after the source was last changed
+fn is_fresh(cache: &Path, source: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified());
+    match (modified(cache), modified(source)) {
+        (Ok(cache), Ok(source)) => cache >= source,
+        _ => false,
+    }
+}
+
+impl Mesh {
+    /// Serializes the mesh into the binary cache format
+    #[allow(dead_code)]
+    pub fn to_cache_bytes(&self) -> Vec<u8> {
+        self.encode_cache(&[])
+    }
+
+    // internal helper, serializes the mesh along with the files it was built from
+    fn encode_cache(&self, dependencies: &[Dependency]) -> Vec<u8> {
+        let num_verts = self.vertices.len() / 3;
+        let mut flags = 0;
+        if self.has_normals() {
+            flags |= HAS_NORMALS;
+        }
+        if self.colors.len() == num_verts * 4 {
+            flags |= HAS_COLORS;
+        }
+        if self.has_texcoords() {
+            flags |= HAS_TEXCOORDS;
+        }
+        if self.has_tangents() {
+            flags |= HAS_TANGENTS;
+        }
+
+        let mut body = Writer(Vec::new());
+        body.u32(num_verts as u32);
+        body.u32(self.indices.len() as u32);
+        body.u32(flags);
+        body.f32s(&self.vertices[..num_verts * 3]);
+        if flags & HAS_NORMALS != 0 {
+            body.f32s(&self.normals);
+        }
+        if flags & HAS_COLORS != 0 {
+            body.f32s(&self.colors);
+        }
+        if flags & HAS_TEXCOORDS != 0 {
+            body.f32s(&self.texcoords);
+        }
+        if flags & HAS_TANGENTS != 0 {
+            body.f32s(&self.tangents);
+        }
+        for &index in &self.indices {
+            body.u32(index);
+        }
+
+        body.u32(self.sub_meshes.len() as u32);
+        for sub_mesh in &self.sub_meshes {
+            body.string(&sub_mesh.name);
+            body.u32(sub_mesh.first_index as u32);
+            body.u32(sub_mesh.index_count as u32);
+        }
+
+        let material = &self.material;
+        body.string(&material.name);
+        body.f32s(&material.diffuse);
+        body.f32s(&material.specular);
+        body.f32s(&[material.shininess, material.dissolve]);
+        body.path(&material.diffuse_texture);
+        body.path(&material.specular_texture);
+        body.path(&material.normal_texture);
+
+        body.u32(dependencies.len() as u32);
+        for dependency in dependencies {
+            body.path(&Some(dependency.path.clone()));
+            match dependency.stamp {
+                Some(stamp) => {
+                    body.0.push(1);
+                    body.u64(stamp.len);
+                    body.u64(stamp.seconds);
+                    body.u32(stamp.nanoseconds);
+                }
+                None => body.0.push(0),
+            }
+        }
+
+        let body = body.0;
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&checksum(&body).to_le_bytes());
+        bytes.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    /// Deserializes a mesh from the binary cache format, checking the version and checksum
+    #[allow(dead_code)]
+    pub fn from_cache_bytes(bytes: &[u8]) -> io::Result<Mesh> {
+        Ok(Mesh::decode_cache(bytes)?.0)
+    }
+
+    // internal helper, deserializes the mesh along with the files it was built from
+    fn decode_cache(bytes: &[u8]) -> io::Result<(Mesh, Vec<Dependency>)> {
+        if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+            return Err(invalid_data("Not a mesh cache"));
+        }
+        let mut header = Reader {
+            data: &bytes[4..HEADER_LEN],
+        };
+        if header.u32()? != VERSION {
+            return Err(invalid_data("Mesh cache has an unsupported version"));
+        }
+        let expected_checksum = header.u64()?;
+        let body_len = header.u64()?;
+
+        let body = &bytes[HEADER_LEN..];
+        if body.len() as u64 != body_len || checksum(body) != expected_checksum {
+            return Err(invalid_data("Mesh cache is corrupt"));
+        }
+
+        let mut body = Reader { data: body };
+        let num_verts = body.u32()? as usize;
+        let num_indices = body.u32()? as usize;
+        let flags = body.u32()?;
+
+        let vertices = body.f32s(num_verts * 3)?;
+        let mut attribute = |flag: u32, width: usize| {
+            if flags & flag != 0 {
+                body.f32s(num_verts * width)
+            } else {
+                Ok(Vec::new())
+            }
+        };
+        let normals = attribute(HAS_NORMALS, 3)?;
+        let colors = attribute(HAS_COLORS, 4)?;
+        let texcoords = attribute(HAS_TEXCOORDS, 2)?;
+        let tangents = attribute(HAS_TANGENTS, 4)?;
+        let indices = body.u32s(num_indices)?;
+        if indices.iter().any(|&i| i as usize >= num_verts) {
+            return Err(invalid_data("Mesh cache has indices out of range"));
+        }
+
+        let sub_mesh_count = body.u32()?;
+        let mut sub_meshes = Vec::new();
+        for _ in 0..sub_mesh_count {
+            sub_meshes.push(SubMesh {
+                name: body.string()?,
+                first_index: body.u32()? as i32,
+                index_count: body.u32()? as i32,
+            });
+        }
+
+        let material = Material {
+            name: body.string()?,
+            diffuse: [body.f32()?, body.f32()?, body.f32()?],
+            specular: [body.f32()?, body.f32()?, body.f32()?],
+            shininess: body.f32()?,
+            dissolve: body.f32()?,
+            diffuse_texture: body.path()?,
+            specular_texture: body.path()?,
+            normal_texture: body.path()?,
+        };
+
+        let dependency_count = body.u32()?;
+        let mut dependencies = Vec::new();
+        for _ in 0..dependency_count {
+            let path = body
+                .path()?
+                .ok_or_else(|| invalid_data("Mesh cache is corrupt"))?;
+            let stamp = match body.u8()? {
+                0 => None,
+                _ => Some(Stamp {
+                    len: body.u64()?,
+                    seconds: body.u64()?,
+                    nanoseconds: body.u32()?,
+                }),
+            };
+            dependencies.push(Dependency { path, stamp });
+        }
+
+        let mesh = Mesh {
+            bounds: Bounds::from_positions(&vertices),
+            vertices,
+            normals,
+            colors,
+            texcoords,
+            tangents,
+            index_count: indices.len() as i32,
+            indices,
+            sub_meshes,
+            material,
+        };
+        Ok((mesh, dependencies))
+    }
+
+    /// Writes the mesh to a cache file. The file is replaced in one go,
+    /// so a crash while writing never leaves a half written cache behind.
+    #[allow(dead_code)]
+    pub fn write_cache(&self, path: &Path) -> io::Result<()> {
+        write_atomically(path, &self.to_cache_bytes())
+    }
+
+    /// Reads a mesh from a cache file
+    #[allow(dead_code)]
+    pub fn read_cache(path: &Path) -> io::Result<Mesh> {
+        Mesh::from_cache_bytes(&fs::read(path)?)
+    }
+
+    /// Uses the cache next to `source` if it is newer than the source and the material libraries
+    /// it refers to are unchanged, otherwise calls `load` and writes a new cache.
+    /// Stale or broken caches are just replaced.
+    pub fn load_cached(
+        source: &str,
+        load: impl FnOnce() -> Result<Mesh, MeshLoadError>,
+    ) -> Result<Mesh, MeshLoadError> {
+        let source = Path::new(source);
+        let cache = cache_path(source);
+
+        if is_fresh(&cache, source) {
+            let before = std::time::Instant::now();
+            match fs::read(&cache).and_then(|bytes| Mesh::decode_cache(&bytes)) {
+                Ok((_, dependencies))
+                    if dependencies.iter().any(|d| Stamp::of(&d.path) != d.stamp) =>
+                {
+                    println!(
+                        "Ignoring cache {}: a material library changed",
+                        cache.display()
+                    )
+                }
+                Ok((mesh, _)) => {
+                    let after = std::time::Instant::now();
+                    println!(
+                        "Loaded {} from cache in {:.3}ms.",
+                        source.display(),
+                        after.duration_since(before).as_micros() as f32 / 1e3
+                    );
+                    return Ok(mesh);
+                }
+                Err(e) => println!("Ignoring cache {}: {}", cache.display(), e),
+            }
+        }
+
+        let mesh = load()?;
+        let dependencies: Vec<Dependency> = material_libraries(source)
+            .into_iter()
+            .map(|path| Dependency {
+                stamp: Stamp::of(&path),
+                path,
+            })
+            .collect();
+        if let Err(e) = write_atomically(&cache, &mesh.encode_cache(&dependencies)) {
+            println!("Failed to write cache {}: {}", cache.display(), e);
+        }
+        Ok(mesh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Terrain;
+
+    #[test]
+    fn round_trip() {
+        let mut mesh = Mesh::cube(2.0, 2, [0.5, 0.25, 1.0, 1.0]);
+        mesh.material.name = "cube".to_string();
+        mesh.material.diffuse_texture = Some(PathBuf::from("cube.png"));
+        let read = Mesh::from_cache_bytes(&mesh.to_cache_bytes()).unwrap();
+        assert_eq!(read.vertices, mesh.vertices);
+        assert_eq!(read.normals, mesh.normals);
+        assert_eq!(read.colors, mesh.colors);
+        assert_eq!(read.texcoords, mesh.texcoords);
+        assert_eq!(read.indices, mesh.indices);
+        assert_eq!(read.material.name, "cube");
+        assert_eq!(read.material.diffuse_texture, mesh.material.diffuse_texture);
+
+        let mut corrupt = mesh.to_cache_bytes();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(Mesh::from_cache_bytes(&corrupt).is_err());
+    }
+
+    #[test]
+    fn changed_material_library_invalidates_the_cache() {
+        let dir = std::env::temp_dir().join("mesh_cache_material_library");
+        fs::create_dir_all(&dir).unwrap();
+        let obj = dir.join("tile.obj");
+        fs::write(
+            &obj,
+            "mtllib tile.mtl\no Tile\nusemtl rock\nv 0 0 0\nv 1 0 0\nv 0 0 1\nf 1 2 3\n",
+        )
+        .unwrap();
+        let _ = fs::remove_file(cache_path(&obj));
+        let path = obj.to_str().unwrap();
+
+        fs::write(dir.join("tile.mtl"), "newmtl rock\nKd 1 0 0\n").unwrap();
+        assert_eq!(
+            Terrain::load(path).unwrap().material.diffuse,
+            [1.0, 0.0, 0.0]
+        );
+        assert!(cache_path(&obj).exists());
+        assert_eq!(
+            Terrain::load(path).unwrap().material.diffuse,
+            [1.0, 0.0, 0.0]
+        );
+
+        fs::write(dir.join("tile.mtl"), "newmtl rock\nKd 0 0.5 0\n").unwrap();
+        assert_eq!(
+            Terrain::load(path).unwrap().material.diffuse,
+            [0.0, 0.5, 0.0]
+        );
+    }
+
+    #[test]
+    fn missing_material_library_keeps_the_cache_until_it_appears() {
+        let dir = std::env::temp_dir().join("mesh_cache_missing_material_library");
+        fs::create_dir_all(&dir).unwrap();
+        let obj = dir.join("tile.obj");
+        fs::write(
+            &obj,
+            "mtllib tile.mtl\no Tile\nv 0 0 0\nv 1 0 0\nv 0 0 1\nf 1 2 3\n",
+        )
+        .unwrap();
+        let _ = fs::remove_file(cache_path(&obj));
+        let _ = fs::remove_file(dir.join("tile.mtl"));
+        let path = obj.to_str().unwrap();
+
+        let loads = std::cell::Cell::new(0);
+        let load = || {
+            Mesh::load_cached(path, || {
+                loads.set(loads.get() + 1);
+                Ok(Mesh::cube(1.0, 1, [1.0; 4]))
+            })
+            .unwrap()
+        };
+        load();
+        assert_eq!(loads.get(), 1);
+        load();
+        assert_eq!(loads.get(), 1, "the second load should come from the cache");
+
+        fs::write(dir.join("tile.mtl"), "newmtl rock\nKd 1 0 0\n").unwrap();
+        load();
+        assert_eq!(
+            loads.get(),
+            2,
+            "a new material library should invalidate the cache"
+        );
+        load();
+        assert_eq!(loads.get(), 2);
+    }
+}
@@ -0,0 +1,193 @@
+use std::{error::Error, fmt, io};
+
+/// Everything that can go wrong when loading a model
+#[derive(Debug)]
+pub enum MeshLoadError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file could be read, but not understood
+    Parse(String),
+    /// An articulated model lacks one of its parts
+    MissingPart { name: String },
+    /// The file has too few objects
+    WrongObjectCount { expected: usize, found: usize },
+    /// An object has attributes that don't fit together, e.g. indices out of range
+    InconsistentAttributes { object: String, reason: String },
+}
+
+impl fmt::Display for MeshLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshLoadError::Io(e) => write!(f, "I/O error: {}", e),
+            MeshLoadError::Parse(message) => write!(f, "Parse error: {}", message),
+            MeshLoadError::MissingPart { name } => {
+                write!(f, "The model has no object named {}", name)
+            }
+            MeshLoadError::WrongObjectCount { expected, found } => write!(
+                f,
+                "Expected at least {} objects in the model, found {}",
+                expected, found
+            ),
+            MeshLoadError::InconsistentAttributes { object, reason } => {
+                write!(f, "Object {} is inconsistent: {}", object, reason)
+            }
+        }
+    }
+}
+
+impl Error for MeshLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MeshLoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MeshLoadError {
+    fn from(e: io::Error) -> Self {
+        MeshLoadError::Io(e)
+    }
+}
+
+impl From<tobj::LoadError> for MeshLoadError {
+    fn from(e: tobj::LoadError) -> Self {
+        match e {
+            tobj::LoadError::OpenFileFailed => {
+                MeshLoadError::Io(io::Error::new(io::ErrorKind::NotFound, e.to_string()))
+            }
+            tobj::LoadError::ReadError => {
+                MeshLoadError::Io(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+            }
+            _ => MeshLoadError::Parse(e.to_string()),
+        }
+    }
+}
+
+impl From<image::ImageError> for MeshLoadError {
+    fn from(e: image::ImageError) -> Self {
+        match e {
+            image::ImageError::IoError(e) => MeshLoadError::Io(e),
+            _ => MeshLoadError::Parse(e.to_string()),
+        }
+    }
+}
+
+impl From<gltf::Error> for MeshLoadError {
+    fn from(e: gltf::Error) -> Self {
+        match e {
+            gltf::Error::Io(e) => MeshLoadError::Io(e),
+            _ => MeshLoadError::Parse(e.to_string()),
+        }
+    }
+}
+
+/// Checks that the attributes of an OBJ object fit together, before it is turned into a `Mesh`
+pub fn check_attributes(name: &str, mesh: &tobj::Mesh) -> Result<(), MeshLoadError> {
+    let inconsistent = |reason: String| {
+        Err(MeshLoadError::InconsistentAttributes {
+            object: name.to_string(),
+            reason,
+        })
+    };
+    let num_verts = mesh.positions.len() / 3;
+
+    if !mesh.positions.chunks_exact(3).remainder().is_empty() {
+        return inconsistent(format!(
+            "{} position values is not a multiple of 3",
+            mesh.positions.len()
+        ));
+    }
+    if !mesh.indices.chunks_exact(3).remainder().is_empty() {
+        return inconsistent(format!(
+            "{} indices is not a whole number of triangles",
+            mesh.indices.len()
+        ));
+    }
+    if let Some(&index) = mesh.indices.iter().find(|&&i| i as usize >= num_verts) {
+        return inconsistent(format!(
+            "index {} is out of range for {} vertices",
+            index, num_verts
+        ));
+    }
+    if !mesh.texcoords.is_empty() && mesh.texcoords.len() != num_verts * 2 {
+        return inconsistent(format!(
+            "{} texture coordinates for {} vertices",
+            mesh.texcoords.len() / 2,
+            num_verts
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // internal helper, a single triangle with texture coordinates
+    fn triangle() -> tobj::Mesh {
+        tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            texcoords: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        }
+    }
+
+    // internal helper, the reason the triangle is rejected after `change`
+    fn rejection(change: impl FnOnce(&mut tobj::Mesh)) -> String {
+        let mut mesh = triangle();
+        change(&mut mesh);
+        match check_attributes("Tile", &mesh) {
+            Err(MeshLoadError::InconsistentAttributes { object, reason }) => {
+                assert_eq!(object, "Tile");
+                reason
+            }
+            other => panic!("expected inconsistent attributes, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn consistent_attributes_pass() {
+        assert!(check_attributes("Tile", &triangle()).is_ok());
+        let mut without_texcoords = triangle();
+        without_texcoords.texcoords.clear();
+        assert!(check_attributes("Tile", &without_texcoords).is_ok());
+    }
+
+    #[test]
+    fn out_of_range_index() {
+        let reason = rejection(|mesh| mesh.indices[2] = 3);
+        assert_eq!(reason, "index 3 is out of range for 3 vertices");
+    }
+
+    #[test]
+    fn partial_position() {
+        let reason = rejection(|mesh| mesh.positions.push(1.0));
+        assert_eq!(reason, "10 position values is not a multiple of 3");
+    }
+
+    #[test]
+    fn partial_triangle() {
+        let reason = rejection(|mesh| mesh.indices.push(0));
+        assert_eq!(reason, "4 indices is not a whole number of triangles");
+    }
+
+    #[test]
+    fn mismatched_texcoords() {
+        let reason = rejection(|mesh| mesh.texcoords.truncate(4));
+        assert_eq!(reason, "2 texture coordinates for 3 vertices");
+    }
+
+    #[test]
+    fn tobj_errors_keep_their_kind() {
+        match MeshLoadError::from(tobj::LoadError::OpenFileFailed) {
+            MeshLoadError::Io(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("expected an I/O error, got {:?}", other),
+        }
+        assert!(matches!(
+            MeshLoadError::from(tobj::LoadError::FaceParseError),
+            MeshLoadError::Parse(_)
+        ));
+    }
+}
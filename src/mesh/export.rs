@@ -0,0 +1,644 @@
+// Writers for a few common interchange formats, mostly to look at generated or posed meshes
+// in other tools:
+// - OBJ with an MTL file next to it, one object per sub-mesh and vertex colors as the
+//   widespread `v x y z r g b` extension
+// - STL, binary or ASCII, with face normals and nothing else
+// - PLY, binary little-endian, with every attribute the mesh has and colors as u8
+
+use super::{generate_color_vec, Bounds, Material, Mesh, SubMesh};
+use crate::scene_graph::{NodeId, SceneGraph};
+use nalgebra_glm as glm;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, ErrorKind, Write},
+    path::Path,
+};
+
+/// The two flavours of STL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StlFormat {
+    Binary,
+    #[allow(dead_code)]
+    Ascii,
+}
+
+// internal helper
+fn create(path: &Path) -> io::Result<BufWriter<File>> {
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+// internal helper
+fn face_normal(mesh: &Mesh, tri: &[u32]) -> glm::Vec3 {
+    let [a, b, c] = [0, 1, 2].map(|k| {
+        let v = tri[k] as usize * 3;
+        glm::vec3(mesh.vertices[v], mesh.vertices[v + 1], mesh.vertices[v + 2])
+    });
+    let normal = (b - a).cross(&(c - a));
+    if normal.norm() > 0.0 {
+        normal.normalize()
+    } else {
+        normal
+    }
+}
+
+#[allow(dead_code)]
+impl Mesh {
+    /// Writes the mesh as OBJ, with its material in an MTL file of the same name next to it
+    pub fn write_obj(&self, path: &Path) -> io::Result<()> {
+        let mtl_path = path.with_extension("mtl");
+        let mtl_name = mtl_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        self.write_obj_to(&mut create(path)?, mtl_name.as_deref())?;
+        self.write_mtl_to(&mut create(&mtl_path)?)
+    }
+
+    /// Writes the mesh as OBJ, referring to the material library `mtl_name` if there is one
+    pub fn write_obj_to(&self, out: &mut impl Write, mtl_name: Option<&str>) -> io::Result<()> {
+        let material = obj_name(&self.material.name);
+        let has_colors = self.colors.len() / 4 == self.vertices.len() / 3;
+        writeln!(
+            out,
+            "# {} vertices, {} triangles",
+            self.vertices.len() / 3,
+            self.indices.len() / 3
+        )?;
+        if let Some(mtl_name) = mtl_name {
+            writeln!(out, "mtllib {}", mtl_name)?;
+        }
+
+        for (v, p) in self.vertices.chunks_exact(3).enumerate() {
+            if has_colors {
+                let c = &self.colors[v * 4..v * 4 + 3];
+                writeln!(
+                    out,
+                    "v {} {} {} {} {} {}",
+                    p[0], p[1], p[2], c[0], c[1], c[2]
+                )?;
+            } else {
+                writeln!(out, "v {} {} {}", p[0], p[1], p[2])?;
+            }
+        }
+        if self.has_texcoords() {
+            for t in self.texcoords.chunks_exact(2) {
+                writeln!(out, "vt {} {}", t[0], t[1])?;
+            }
+        }
+        if self.has_normals() {
+            for n in self.normals.chunks_exact(3) {
+                writeln!(out, "vn {} {} {}", n[0], n[1], n[2])?;
+            }
+        }
+
+        let names: Vec<String> = if self.sub_meshes.is_empty() {
+            vec![material.clone()]
+        } else {
+            self.sub_meshes.iter().map(|s| obj_name(&s.name)).collect()
+        };
+        for (range, name) in self.triangle_ranges().into_iter().zip(names) {
+            writeln!(out, "o {}", name)?;
+            writeln!(out, "usemtl {}", material)?;
+            for tri in self.indices[range].chunks_exact(3) {
+                write!(out, "f")?;
+                for &v in tri {
+                    // OBJ counts from one, and every attribute shares the vertex index
+                    let v = v + 1;
+                    match (self.has_texcoords(), self.has_normals()) {
+                        (true, true) => write!(out, " {}/{}/{}", v, v, v)?,
+                        (true, false) => write!(out, " {}/{}", v, v)?,
+                        (false, true) => write!(out, " {}//{}", v, v)?,
+                        (false, false) => write!(out, " {}", v)?,
+                    }
+                }
+                writeln!(out)?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Writes the material of the mesh as an MTL file
+    pub fn write_mtl_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let m = &self.material;
+        writeln!(out, "newmtl {}", obj_name(&m.name))?;
+        writeln!(out, "Kd {} {} {}", m.diffuse[0], m.diffuse[1], m.diffuse[2])?;
+        writeln!(
+            out,
+            "Ks {} {} {}",
+            m.specular[0], m.specular[1], m.specular[2]
+        )?;
+        writeln!(out, "Ns {}", m.shininess)?;
+        writeln!(out, "d {}", m.dissolve)?;
+        if let Some(texture) = &m.diffuse_texture {
+            writeln!(out, "map_Kd {}", texture.display())?;
+        }
+        if let Some(texture) = &m.specular_texture {
+            writeln!(out, "map_Ks {}", texture.display())?;
+        }
+        if let Some(texture) = &m.normal_texture {
+            writeln!(out, "norm {}", texture.display())?;
+        }
+        out.flush()
+    }
+
+    /// Writes the triangles of the mesh as STL
+    pub fn write_stl(&self, path: &Path, format: StlFormat) -> io::Result<()> {
+        self.write_stl_to(&mut create(path)?, format)
+    }
+
+    /// Writes the triangles of the mesh as STL
+    pub fn write_stl_to(&self, out: &mut impl Write, format: StlFormat) -> io::Result<()> {
+        let name = obj_name(&self.material.name);
+        let triangles = self.indices.chunks_exact(3);
+        match format {
+            StlFormat::Binary => {
+                // An 80 byte header that must not start with "solid", then the triangle count
+                let mut header = [0u8; 80];
+                let title = format!("binary STL {}", name);
+                let length = title.len().min(80);
+                header[..length].copy_from_slice(&title.as_bytes()[..length]);
+                out.write_all(&header)?;
+                out.write_all(&(triangles.len() as u32).to_le_bytes())?;
+                for tri in triangles {
+                    let normal = face_normal(self, tri);
+                    for value in normal.iter() {
+                        out.write_all(&value.to_le_bytes())?;
+                    }
+                    for &v in tri {
+                        for value in &self.vertices[v as usize * 3..v as usize * 3 + 3] {
+                            out.write_all(&value.to_le_bytes())?;
+                        }
+                    }
+                    // The attribute byte count, unused
+                    out.write_all(&[0, 0])?;
+                }
+            }
+            StlFormat::Ascii => {
+                writeln!(out, "solid {}", name)?;
+                for tri in triangles {
+                    let n = face_normal(self, tri);
+                    writeln!(out, "  facet normal {:e} {:e} {:e}", n.x, n.y, n.z)?;
+                    writeln!(out, "    outer loop")?;
+                    for &v in tri {
+                        let p = &self.vertices[v as usize * 3..v as usize * 3 + 3];
+                        writeln!(out, "      vertex {:e} {:e} {:e}", p[0], p[1], p[2])?;
+                    }
+                    writeln!(out, "    endloop")?;
+                    writeln!(out, "  endfacet")?;
+                }
+                writeln!(out, "endsolid {}", name)?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Writes the mesh as binary PLY
+    pub fn write_ply(&self, path: &Path) -> io::Result<()> {
+        self.write_ply_to(&mut create(path)?)
+    }
+
+    /// Writes the mesh as binary PLY
+    pub fn write_ply_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let num_verts = self.vertices.len() / 3;
+        let has_colors = num_verts > 0 && self.colors.len() / 4 == num_verts;
+
+        writeln!(out, "ply")?;
+        writeln!(out, "format binary_little_endian 1.0")?;
+        writeln!(out, "element vertex {}", num_verts)?;
+        for axis in ["x", "y", "z"] {
+            writeln!(out, "property float {}", axis)?;
+        }
+        if self.has_normals() {
+            for axis in ["nx", "ny", "nz"] {
+                writeln!(out, "property float {}", axis)?;
+            }
+        }
+        if has_colors {
+            for channel in ["red", "green", "blue", "alpha"] {
+                writeln!(out, "property uchar {}", channel)?;
+            }
+        }
+        if self.has_texcoords() {
+            for axis in ["s", "t"] {
+                writeln!(out, "property float {}", axis)?;
+            }
+        }
+        writeln!(out, "element face {}", self.indices.len() / 3)?;
+        writeln!(out, "property list uchar uint vertex_indices")?;
+        writeln!(out, "end_header")?;
+
+        for v in 0..num_verts {
+            let mut floats = self.vertices[v * 3..v * 3 + 3].to_vec();
+            if self.has_normals() {
+                floats.extend(&self.normals[v * 3..v * 3 + 3]);
+            }
+            for value in floats {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            if has_colors {
+                for c in &self.colors[v * 4..v * 4 + 4] {
+                    out.write_all(&[(c.clamp(0.0, 1.0) * 255.0).round() as u8])?;
+                }
+            }
+            if self.has_texcoords() {
+                for value in &self.texcoords[v * 2..v * 2 + 2] {
+                    out.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        for tri in self.indices.chunks_exact(3) {
+            out.write_all(&[3])?;
+            for v in tri {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Writes the mesh in the format matching the extension of `path`: obj, stl (binary) or ply
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("obj") => self.write_obj(path),
+            Some("stl") => self.write_stl(path, StlFormat::Binary),
+            Some("ply") => self.write_ply(path),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Can't export to {}, use .obj, .stl or .ply", path.display()),
+            )),
+        }
+    }
+
+    /// Bakes the world transforms of a node of a scene graph and its descendants into a single
+    /// mesh, with one sub-mesh per drawn node. `meshes` holds the mesh uploaded to each VAO.
+    /// Nodes draw their finest level of detail, and nodes whose VAO isn't in `meshes` are skipped.
+    pub fn bake_scene(graph: &SceneGraph, root: NodeId, meshes: &HashMap<u32, Mesh>) -> Mesh {
+        let mut baked = Mesh {
+            vertices: Vec::new(),
+            normals: Vec::new(),
+            colors: Vec::new(),
+            texcoords: Vec::new(),
+            tangents: Vec::new(),
+            indices: Vec::new(),
+            index_count: 0,
+            sub_meshes: Vec::new(),
+            material: Material {
+                name: "scene".to_string(),
+                ..Material::default()
+            },
+            bounds: Bounds::default(),
+        };
+        // Attributes only some of the meshes have are dropped at the end
+        let mut all_normals = true;
+        let mut all_texcoords = true;
+        let parent_model = graph
+            .parent(root)
+            .map_or_else(glm::identity, |parent| graph.world_transform(parent));
+        bake_node(
+            graph,
+            root,
+            &parent_model,
+            meshes,
+            &mut baked,
+            &mut all_normals,
+            &mut all_texcoords,
+        );
+
+        if !all_normals {
+            baked.normals.clear();
+        }
+        if !all_texcoords {
+            baked.texcoords.clear();
+        }
+        baked.index_count = baked.indices.len() as i32;
+        baked.update_bounds();
+        baked
+    }
+}
+
+// internal helper, OBJ names can't hold whitespace
+fn obj_name(name: &str) -> String {
+    if name.trim().is_empty() {
+        "default".to_string()
+    } else {
+        name.split_whitespace().collect::<Vec<_>>().join("_")
+    }
+}
+
+// internal helper
+fn bake_node(
+    graph: &SceneGraph,
+    id: NodeId,
+    parent_model: &glm::Mat4,
+    meshes: &HashMap<u32, Mesh>,
+    baked: &mut Mesh,
+    all_normals: &mut bool,
+    all_texcoords: &mut bool,
+) {
+    let node = &graph[id];
+    let model = parent_model * node.local_transform();
+
+    if let Some(mesh) = meshes.get(&node.vao_id).filter(|_| node.index_count > 0) {
+        let start = (node.first_index.max(0) as usize).min(mesh.indices.len());
+        let end = (start + node.index_count as usize).min(mesh.indices.len());
+        let end = start + (end - start) / 3 * 3;
+        let normal_matrix = glm::transpose(&glm::inverse(&glm::mat4_to_mat3(&model)));
+        // Mirroring transforms turn the triangles inside out
+        let mirrored = glm::determinant(&glm::mat4_to_mat3(&model)) < 0.0;
+        let colors = if mesh.colors.len() / 4 == mesh.vertices.len() / 3 {
+            mesh.colors.clone()
+        } else {
+            generate_color_vec([1.0; 4], mesh.vertices.len() / 3)
+        };
+        *all_normals &= mesh.has_normals();
+        *all_texcoords &= mesh.has_texcoords();
+
+        // Only the vertices the node draws are copied
+        let first_index = baked.indices.len() as i32;
+        let mut copied: HashMap<u32, u32> = HashMap::new();
+        for tri in mesh.indices[start..end].chunks_exact(3) {
+            let mut corners = [0; 3];
+            for (corner, &v) in corners.iter_mut().zip(tri) {
+                *corner = *copied.entry(v).or_insert_with(|| {
+                    let i = v as usize;
+                    let p = glm::vec4(
+                        mesh.vertices[i * 3],
+                        mesh.vertices[i * 3 + 1],
+                        mesh.vertices[i * 3 + 2],
+                        1.0,
+                    );
+                    baked.vertices.extend((model * p).xyz().iter());
+                    if mesh.has_normals() {
+                        let n = glm::vec3(
+                            mesh.normals[i * 3],
+                            mesh.normals[i * 3 + 1],
+                            mesh.normals[i * 3 + 2],
+                        );
+                        let n = normal_matrix * n;
+                        let n = if n.norm() > 0.0 { n.normalize() } else { n };
+                        baked.normals.extend(n.iter());
+                    } else {
+                        baked.normals.extend([0.0; 3]);
+                    }
+                    baked.colors.extend(&colors[i * 4..i * 4 + 4]);
+                    if mesh.has_texcoords() {
+                        baked.texcoords.extend(&mesh.texcoords[i * 2..i * 2 + 2]);
+                    } else {
+                        baked.texcoords.extend([0.0; 2]);
+                    }
+                    (baked.vertices.len() / 3 - 1) as u32
+                });
+            }
+            if mirrored {
+                corners.swap(1, 2);
+            }
+            baked.indices.extend(corners);
+        }
+
+        baked.sub_meshes.push(SubMesh {
+            name: format!(
+                "{}_{}",
+                obj_name(&mesh.material.name),
+                baked.sub_meshes.len()
+            ),
+            first_index,
+            index_count: baked.indices.len() as i32 - first_index,
+        });
+    }
+
+    for &child in graph.children(id) {
+        bake_node(
+            graph,
+            child,
+            &model,
+            meshes,
+            baked,
+            all_normals,
+            all_texcoords,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::IndexFormat;
+    use crate::scene_graph::{SceneNode, VertexArray};
+    use std::io::BufReader;
+
+    const COLOR: [f32; 4] = [0.25, 0.5, 0.75, 1.0];
+
+    // internal helper, a box and a ball as two sub-meshes
+    fn two_parts() -> Mesh {
+        let mut ball = Mesh::uv_sphere(0.5, 8, 4, [1.0, 0.0, 0.0, 1.0]);
+        for x in ball.vertices.iter_mut().step_by(3) {
+            *x += 2.0;
+        }
+        let mut mesh = Mesh::merge(vec![
+            ("box".to_string(), Mesh::cube(1.0, 2, COLOR)),
+            ("a ball".to_string(), ball),
+        ]);
+        mesh.material.name = "painted metal".to_string();
+        mesh
+    }
+
+    // internal helper, the face normal of an STL triangle and its corners
+    fn stl_triangle(bytes: &[u8]) -> [glm::Vec3; 4] {
+        let float =
+            |i: usize| f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        [0, 1, 2, 3].map(|k| glm::vec3(float(k * 12), float(k * 12 + 4), float(k * 12 + 8)))
+    }
+
+    #[test]
+    fn obj_reads_back() {
+        let mesh = two_parts();
+        assert!(mesh.has_normals() && mesh.has_texcoords());
+        let (mut obj, mut mtl) = (Vec::new(), Vec::new());
+        mesh.write_obj_to(&mut obj, Some("parts.mtl")).unwrap();
+        mesh.write_mtl_to(&mut mtl).unwrap();
+
+        let (models, materials) = tobj::load_obj_buf(
+            &mut BufReader::new(&obj[..]),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+            |path| {
+                assert_eq!(path, Path::new("parts.mtl"));
+                tobj::load_mtl_buf(&mut BufReader::new(&mtl[..]))
+            },
+        )
+        .unwrap();
+        let materials = materials.unwrap();
+        assert_eq!(materials.len(), 1);
+        assert_eq!(materials[0].name, "painted_metal");
+        assert_eq!(materials[0].diffuse, mesh.material.diffuse);
+
+        let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["box", "a_ball"]);
+        for (model, range) in models.iter().zip(mesh.triangle_ranges()) {
+            let read = &model.mesh;
+            assert_eq!(read.material_id, Some(0));
+            assert_eq!(read.indices.len(), range.len());
+            for (&r, &v) in read.indices.iter().zip(&mesh.indices[range]) {
+                let (r, v) = (r as usize, v as usize);
+                assert_eq!(
+                    read.positions[r * 3..r * 3 + 3],
+                    mesh.vertices[v * 3..v * 3 + 3]
+                );
+                assert_eq!(
+                    read.normals[r * 3..r * 3 + 3],
+                    mesh.normals[v * 3..v * 3 + 3]
+                );
+                assert_eq!(
+                    read.texcoords[r * 2..r * 2 + 2],
+                    mesh.texcoords[v * 2..v * 2 + 2]
+                );
+                assert_eq!(
+                    read.vertex_color[r * 3..r * 3 + 3],
+                    mesh.colors[v * 4..v * 4 + 3]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn binary_stl_layout() {
+        let mesh = two_parts();
+        let triangles = mesh.indices.len() / 3;
+        let mut stl = Vec::new();
+        mesh.write_stl_to(&mut stl, StlFormat::Binary).unwrap();
+
+        assert_eq!(stl.len(), 84 + 50 * triangles);
+        assert!(!stl.starts_with(b"solid"));
+        assert_eq!(stl[80..84], (triangles as u32).to_le_bytes());
+        for (record, tri) in stl[84..].chunks_exact(50).zip(mesh.indices.chunks_exact(3)) {
+            let [normal, a, b, c] = stl_triangle(record);
+            assert_eq!(normal, face_normal(&mesh, tri));
+            for (corner, &v) in [a, b, c].iter().zip(tri) {
+                assert_eq!(
+                    corner.as_slice(),
+                    &mesh.vertices[v as usize * 3..v as usize * 3 + 3]
+                );
+            }
+            assert_eq!(record[48..], [0, 0]);
+        }
+
+        let mut ascii = Vec::new();
+        mesh.write_stl_to(&mut ascii, StlFormat::Ascii).unwrap();
+        let ascii = String::from_utf8(ascii).unwrap();
+        assert!(ascii.starts_with("solid painted_metal\n"));
+        assert_eq!(ascii.matches("facet normal").count(), triangles);
+    }
+
+    #[test]
+    fn ply_header_matches_the_data() {
+        let full = two_parts();
+        let mut bare = two_parts();
+        bare.normals.clear();
+        bare.colors.clear();
+        bare.texcoords.clear();
+
+        for (mesh, properties, vertex_size) in [
+            (
+                &full,
+                &[
+                    "x", "y", "z", "nx", "ny", "nz", "red", "green", "blue", "alpha", "s", "t",
+                ][..],
+                6 * 4 + 4 + 2 * 4,
+            ),
+            (&bare, &["x", "y", "z"][..], 3 * 4),
+        ] {
+            let mut ply = Vec::new();
+            mesh.write_ply_to(&mut ply).unwrap();
+            let end = b"end_header\n";
+            let header_len = ply.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+            let header = std::str::from_utf8(&ply[..header_len]).unwrap();
+            let (num_verts, num_tris) = (mesh.vertices.len() / 3, mesh.indices.len() / 3);
+
+            let lines: Vec<&str> = header.lines().collect();
+            assert_eq!(
+                lines[..3],
+                [
+                    "ply",
+                    "format binary_little_endian 1.0",
+                    &format!("element vertex {}", num_verts)
+                ]
+            );
+            let written: Vec<&str> = lines[3..]
+                .iter()
+                .take_while(|line| line.starts_with("property "))
+                .map(|line| line.rsplit(' ').next().unwrap())
+                .collect();
+            assert_eq!(written, properties);
+            assert_eq!(
+                lines[3 + written.len()..],
+                [
+                    &format!("element face {}", num_tris)[..],
+                    "property list uchar uint vertex_indices",
+                    "end_header"
+                ]
+            );
+            assert_eq!(
+                ply.len(),
+                header_len + num_verts * vertex_size + num_tris * 13
+            );
+
+            // The faces are at the end, each a count and three indices
+            let faces = &ply[ply.len() - num_tris * 13..];
+            for (face, tri) in faces.chunks_exact(13).zip(mesh.indices.chunks_exact(3)) {
+                assert_eq!(face[0], 3);
+                for (bytes, v) in face[1..].chunks_exact(4).zip(tri) {
+                    assert_eq!(bytes, v.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bake_scene_applies_the_transforms() {
+        // One triangle facing +Z, drawn below a moved parent, once as is and once mirrored
+        let mut triangle = Mesh::from(
+            tobj::Mesh {
+                positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+                indices: vec![0, 1, 2],
+                ..Default::default()
+            },
+            COLOR,
+        );
+        triangle.ensure_normals();
+        let vao = VertexArray {
+            id: 1,
+            index_format: IndexFormat::U32,
+        };
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let mut moved = SceneNode::group("moved");
+        moved.position = glm::vec3(10.0, 0.0, 0.0);
+        let moved = graph.insert(root, moved).unwrap();
+        graph.insert(moved, SceneNode::new(vao, 3)).unwrap();
+        let mut mirrored = SceneNode::new(vao, 3);
+        mirrored.scale = glm::vec3(-1.0, 1.0, 1.0);
+        graph.insert(moved, mirrored).unwrap();
+        let meshes: HashMap<u32, Mesh> = vec![(1, triangle)].into_iter().collect();
+
+        let baked = Mesh::bake_scene(&graph, root, &meshes);
+        assert_eq!(baked.sub_meshes.len(), 2);
+        assert_eq!(
+            baked.vertices,
+            [
+                10.0, 0.0, 0.0, 11.0, 0.0, 0.0, 10.0, 1.0, 0.0, //
+                10.0, 0.0, 0.0, 9.0, 0.0, 0.0, 10.0, 1.0, 0.0,
+            ]
+        );
+        assert_eq!(baked.indices, [0, 1, 2, 3, 5, 4]);
+        for tri in baked.indices.chunks_exact(3) {
+            assert_eq!(face_normal(&baked, tri), glm::vec3(0.0, 0.0, 1.0));
+        }
+        for n in baked.normals.chunks_exact(3) {
+            assert_eq!(n, [0.0, 0.0, 1.0]);
+        }
+        assert_eq!(baked.bounds.aabb.min, glm::vec3(9.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,231 @@
+use super::{Bounds, Material, Mesh, MeshLoadError, Terrain};
+use nalgebra_glm as glm;
+
+/// How a grid of heights is turned into a terrain mesh
+#[derive(Clone, Debug)]
+pub struct HeightmapOptions {
+    /// The distance between neighbouring samples along X and Z
+    pub spacing: f32,
+    /// The height of a sample with the value 1.0
+    pub vertical_scale: f32,
+    /// Colors at increasing heights between 0.0 and 1.0, blended linearly
+    pub color_ramp: Vec<(f32, [f32; 4])>,
+}
+
+impl Default for HeightmapOptions {
+    fn default() -> Self {
+        Self {
+            spacing: 1.0,
+            vertical_scale: 50.0,
+            // Dark maria in the valleys, bright highlands on top
+            color_ramp: vec![
+                (0.0, [0.25, 0.25, 0.27, 1.0]),
+                (0.5, [0.55, 0.55, 0.55, 1.0]),
+                (1.0, [0.9, 0.9, 0.88, 1.0]),
+            ],
+        }
+    }
+}
+
+impl HeightmapOptions {
+    /// The color of the ramp at the given height
+    pub fn color_at(&self, height: f32) -> [f32; 4] {
+        let ramp = &self.color_ramp;
+        match ramp.iter().position(|&(h, _)| h > height) {
+            None => ramp.last().map_or([1.0; 4], |&(_, c)| c),
+            Some(0) => ramp[0].1,
+            Some(i) => {
+                let (h0, c0) = ramp[i - 1];
+                let (h1, c1) = ramp[i];
+                let t = (height - h0) / (h1 - h0);
+                let mut color = [0.0; 4];
+                for k in 0..4 {
+                    color[k] = c0[k] + (c1[k] - c0[k]) * t;
+                }
+                color
+            }
+        }
+    }
+}
+
+impl Mesh {
+    /// Builds a terrain from a row-major grid of `width` × `depth` heights between 0.0 and 1.0,
+    /// centered at the origin in the XZ plane. Fails if either side has fewer than 2 samples,
+    /// or if there aren't exactly `width` × `depth` heights.
+    pub fn from_heights(
+        heights: &[f32],
+        width: usize,
+        depth: usize,
+        options: &HeightmapOptions,
+    ) -> Result<Self, MeshLoadError> {
+        if width < 2 || depth < 2 {
+            return Err(MeshLoadError::Parse(format!(
+                "A {}x{} heightmap is too small for a terrain",
+                width, depth
+            )));
+        }
+        if width.checked_mul(depth) != Some(heights.len()) {
+            return Err(MeshLoadError::Parse(format!(
+                "A {}x{} heightmap can't have {} heights",
+                width,
+                depth,
+                heights.len()
+            )));
+        }
+
+        let height = |i: usize, j: usize| heights[j * width + i] * options.vertical_scale;
+        let mut vertices = Vec::with_capacity(heights.len() * 3);
+        let mut normals = Vec::with_capacity(heights.len() * 3);
+        let mut colors = Vec::with_capacity(heights.len() * 4);
+        let mut texcoords = Vec::with_capacity(heights.len() * 2);
+
+        for j in 0..depth {
+            for i in 0..width {
+                vertices.extend_from_slice(&[
+                    (i as f32 - (width - 1) as f32 / 2.0) * options.spacing,
+                    height(i, j),
+                    (j as f32 - (depth - 1) as f32 / 2.0) * options.spacing,
+                ]);
+
+                // Central differences, or one-sided ones along the edges
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(width - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(depth - 1));
+                let dx = (height(i1, j) - height(i0, j)) / ((i1 - i0) as f32 * options.spacing);
+                let dz = (height(i, j1) - height(i, j0)) / ((j1 - j0) as f32 * options.spacing);
+                normals.extend_from_slice(glm::normalize(&glm::vec3(-dx, 1.0, -dz)).as_slice());
+
+                colors.extend_from_slice(&options.color_at(heights[j * width + i]));
+                texcoords.extend_from_slice(&[
+                    i as f32 / (width - 1) as f32,
+                    j as f32 / (depth - 1) as f32,
+                ]);
+            }
+        }
+
+        let mut indices = Vec::with_capacity((width - 1) * (depth - 1) * 6);
+        for j in 0..depth - 1 {
+            for i in 0..width - 1 {
+                let a = (j * width + i) as u32;
+                let (b, c, d) = (a + width as u32, a + width as u32 + 1, a + 1);
+                indices.extend_from_slice(&[a, b, c, a, c, d]);
+            }
+        }
+
+        Ok(Mesh {
+            bounds: Bounds::from_positions(&vertices),
+            vertices,
+            normals,
+            colors,
+            texcoords,
+            tangents: Vec::new(),
+            index_count: indices.len() as i32,
+            indices,
+            sub_meshes: Vec::new(),
+            material: Material::default(),
+        })
+    }
+}
+
+impl Terrain {
+    /// Generates a terrain from a grayscale 8 or 16 bit heightmap image, where white is the highest
+    #[allow(dead_code)]
+    pub fn from_heightmap(path: &str, options: &HeightmapOptions) -> Result<Mesh, MeshLoadError> {
+        println!("Generating terrain from heightmap...");
+        let before = std::time::Instant::now();
+
+        // 8 bit images are scaled up, so both kinds end up in the same range
+        let image = image::open(path)?.into_luma16();
+        let (width, depth) = image.dimensions();
+        let heights: Vec<f32> = image.pixels().map(|p| p.0[0] as f32 / 65535.0).collect();
+        let terrain = Mesh::from_heights(&heights, width as usize, depth as usize, options)?;
+
+        let after = std::time::Instant::now();
+        println!(
+            "Generated {}x{} terrain with {} triangles in {:.3}ms.",
+            width,
+            depth,
+            terrain.indices.len() / 3,
+            after.duration_since(before).as_micros() as f32 / 1e3
+        );
+        Ok(terrain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_by_two_heights() {
+        let options = HeightmapOptions {
+            spacing: 2.0,
+            vertical_scale: 4.0,
+            ..HeightmapOptions::default()
+        };
+        // Rising along X, level along Z
+        let terrain = Mesh::from_heights(&[0.0, 1.0, 0.0, 1.0], 2, 2, &options).unwrap();
+
+        assert_eq!(
+            terrain.vertices,
+            [-1.0, 0.0, -1.0, 1.0, 4.0, -1.0, -1.0, 0.0, 1.0, 1.0, 4.0, 1.0]
+        );
+        assert_eq!(terrain.indices, [0, 2, 3, 0, 3, 1]);
+        assert_eq!(terrain.index_count, 6);
+        let normal = glm::normalize(&glm::vec3(-2.0, 1.0, 0.0));
+        for n in terrain.normals.chunks_exact(3) {
+            assert!(glm::distance(&glm::make_vec3(n), &normal) < 1e-6);
+        }
+        // The faces point up, the same way as the normals
+        let [a, b, c] = [0, 2, 3].map(|v| glm::make_vec3(&terrain.vertices[v * 3..v * 3 + 3]));
+        assert!((b - a).cross(&(c - a)).dot(&normal) > 0.0);
+
+        let (low, high) = (options.color_ramp[0].1, options.color_ramp[2].1);
+        assert_eq!(terrain.colors, [low, high, low, high].concat());
+        assert_eq!(terrain.texcoords, [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn colors_are_blended() {
+        let options = HeightmapOptions::default();
+        let [low, middle] = [options.color_ramp[0].1, options.color_ramp[1].1];
+        let blended = options.color_at(0.25);
+        for k in 0..4 {
+            assert!((blended[k] - (low[k] + middle[k]) / 2.0).abs() < 1e-6);
+        }
+        assert_eq!(options.color_at(-1.0), low);
+        assert_eq!(options.color_at(2.0), options.color_ramp[2].1);
+    }
+
+    #[test]
+    fn too_few_heights_are_an_error() {
+        let options = HeightmapOptions::default();
+        for (width, depth) in [(1, 5), (5, 1), (0, 0)] {
+            let heights = vec![0.5; width * depth];
+            match Mesh::from_heights(&heights, width, depth, &options) {
+                Err(MeshLoadError::Parse(message)) => assert_eq!(
+                    message,
+                    format!("A {}x{} heightmap is too small for a terrain", width, depth)
+                ),
+                other => panic!("{:?}", other.map(|_| ())),
+            }
+        }
+        match Mesh::from_heights(&[0.5; 5], 2, 2, &options) {
+            Err(MeshLoadError::Parse(message)) => {
+                assert_eq!(message, "A 2x2 heightmap can't have 5 heights")
+            }
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        assert!(Mesh::from_heights(&[], usize::MAX, 2, &options).is_err());
+    }
+
+    #[test]
+    fn one_pixel_wide_images_are_an_error() {
+        let dir = std::env::temp_dir().join("heightmap_one_pixel_wide");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("strip.png");
+        image::GrayImage::new(1, 16).save(&path).unwrap();
+
+        let result = Terrain::from_heightmap(path.to_str().unwrap(), &HeightmapOptions::default());
+        assert!(matches!(result, Err(MeshLoadError::Parse(_))));
+    }
+}
@@ -0,0 +1,440 @@
+use super::{generate_color_vec, Bounds, Material, Mesh};
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+
+// Isosurfaces by marching cubes (Lorensen and Cline 1987) over a grid of samples:
+// - instead of the usual hand written tables, the polygons of each of the 256 cases are found
+//   by walking the edges where the sign changes around the faces of the cube into loops.
+//   A loop that can't be fanned without a triangle lying on a face gets a vertex in its middle
+// - on a face with two opposite inside corners, the inside corners are cut off on their own.
+//   Neighbouring cells see the same face, so the surface has no cracks
+// - vertices are shared between the cells around an edge, and their normals come from the
+//   gradient of the samples, by central differences
+// - values below the iso level are inside, like with signed distance functions
+
+/// A box split into a regular grid of cells, sampled at the corners of the cells
+#[derive(Clone, Copy, Debug)]
+pub struct SampleGrid {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+    /// The number of cells along each axis
+    pub cells: [usize; 3],
+}
+
+impl SampleGrid {
+    /// A grid of `cells` cells along each side of a cube centered at the origin
+    #[allow(dead_code)]
+    pub fn cube(size: f32, cells: usize) -> Self {
+        SampleGrid {
+            min: glm::vec3(-size / 2.0, -size / 2.0, -size / 2.0),
+            max: glm::vec3(size / 2.0, size / 2.0, size / 2.0),
+            cells: [cells; 3],
+        }
+    }
+
+    /// The number of samples along each axis
+    pub fn points(&self) -> [usize; 3] {
+        self.cells.map(|cells| cells + 1)
+    }
+
+    /// The size of a cell
+    pub fn cell_size(&self) -> glm::Vec3 {
+        let size = self.max - self.min;
+        glm::vec3(
+            size.x / self.cells[0].max(1) as f32,
+            size.y / self.cells[1].max(1) as f32,
+            size.z / self.cells[2].max(1) as f32,
+        )
+    }
+
+    /// The position of a sample
+    pub fn position(&self, x: usize, y: usize, z: usize) -> glm::Vec3 {
+        let offset = glm::vec3(x as f32, y as f32, z as f32);
+        self.min + self.cell_size().component_mul(&offset)
+    }
+
+    /// Samples a field at every point of the grid, with x changing fastest and z slowest
+    pub fn sample(&self, field: impl Fn(glm::Vec3) -> f32) -> Vec<f32> {
+        let [nx, ny, nz] = self.points();
+        let mut samples = Vec::with_capacity(nx * ny * nz);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    samples.push(field(self.position(x, y, z)));
+                }
+            }
+        }
+        samples
+    }
+}
+
+// The corners of a cube are numbered by their offset, x + 2y + 4z,
+// and an edge is the pair of corners it joins
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+// The corners around each face of a cube, in order
+const FACES: [[usize; 4]; 6] = [
+    [0, 2, 6, 4],
+    [1, 3, 7, 5],
+    [0, 1, 5, 4],
+    [2, 3, 7, 6],
+    [0, 1, 3, 2],
+    [4, 5, 7, 6],
+];
+
+// internal helper
+fn edge_between(a: usize, b: usize) -> usize {
+    EDGES
+        .iter()
+        .position(|&(c, d)| (c, d) == (a.min(b), a.max(b)))
+        .unwrap()
+}
+
+// internal helper, true if two edges of the cube are on the same face
+fn share_face(a: usize, b: usize) -> bool {
+    FACES.iter().any(|face| {
+        let on_face = |(c, d): (usize, usize)| face.contains(&c) && face.contains(&d);
+        on_face(EDGES[a]) && on_face(EDGES[b])
+    })
+}
+
+// internal helper, a loop of crossed edges cutting through a cube
+struct Polygon {
+    edges: Vec<usize>,
+    // Whether it can be fanned from its first edge. Otherwise some of the fan would lie on a face
+    // of the cube, on top of the neighbouring cell's triangles, and it is fanned around its center.
+    fan: bool,
+}
+
+// internal helper, the polygons of each case. Bit `c` of the case is set if corner `c` is inside.
+// The polygons are not wound consistently yet.
+fn case_table() -> Vec<Vec<Polygon>> {
+    (0..256usize)
+        .map(|case| {
+            let inside = |c: usize| case & (1 << c) != 0;
+
+            // The two edges each crossed edge is joined to, on the two faces it borders
+            let mut links: [Vec<usize>; 12] = Default::default();
+            for face in FACES.iter() {
+                let edge = |k: usize| edge_between(face[k], face[(k + 1) % 4]);
+                let crossed: Vec<usize> = (0..4)
+                    .filter(|&k| inside(face[k]) != inside(face[(k + 1) % 4]))
+                    .collect();
+                let pairs = match crossed.len() {
+                    2 => vec![(edge(crossed[0]), edge(crossed[1]))],
+                    // Two opposite corners are inside, cut each of them off
+                    4 => (0..4)
+                        .filter(|&k| inside(face[k]))
+                        .map(|k| (edge((k + 3) % 4), edge(k)))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                for (a, b) in pairs {
+                    links[a].push(b);
+                    links[b].push(a);
+                }
+            }
+
+            // Walk the links into loops
+            let mut polygons = Vec::new();
+            let mut visited = [false; 12];
+            for start in 0..12 {
+                if visited[start] || links[start].is_empty() {
+                    continue;
+                }
+                let mut edges = vec![start];
+                visited[start] = true;
+                let mut current = start;
+                while let Some(&next) = links[current].iter().find(|&&e| !visited[e]) {
+                    visited[next] = true;
+                    edges.push(next);
+                    current = next;
+                }
+
+                // Start the fan where none of its diagonals lie on a face
+                let n = edges.len();
+                let first =
+                    (0..n).find(|&s| (2..n - 1).all(|k| !share_face(edges[s], edges[(s + k) % n])));
+                if let Some(first) = first {
+                    edges.rotate_left(first);
+                }
+                polygons.push(Polygon {
+                    edges,
+                    fan: first.is_some(),
+                });
+            }
+            polygons
+        })
+        .collect()
+}
+
+// internal helper
+fn vec3(data: &[f32], i: u32) -> glm::Vec3 {
+    let i = i as usize * 3;
+    glm::vec3(data[i], data[i + 1], data[i + 2])
+}
+
+// internal helper, the average of some of the vectors in `data`
+fn average(data: &[f32], of: &[u32]) -> glm::Vec3 {
+    of.iter().map(|&v| vec3(data, v)).sum::<glm::Vec3>() / of.len() as f32
+}
+
+// internal helper, adds a vertex and returns its index
+fn push_vertex(
+    vertices: &mut Vec<f32>,
+    normals: &mut Vec<f32>,
+    position: glm::Vec3,
+    normal: glm::Vec3,
+) -> u32 {
+    let normal = if normal.norm() > 0.0 {
+        normal.normalize()
+    } else {
+        normal
+    };
+    vertices.extend_from_slice(position.as_slice());
+    normals.extend_from_slice(normal.as_slice());
+    (vertices.len() / 3 - 1) as u32
+}
+
+#[allow(dead_code)]
+impl Mesh {
+    /// Samples `field` over the grid and builds the surface where it equals `iso_level`,
+    /// with the normals pointing towards larger values. Signed distance functions give their
+    /// surface at an iso level of zero.
+    pub fn isosurface(
+        field: impl Fn(glm::Vec3) -> f32,
+        grid: &SampleGrid,
+        iso_level: f32,
+        color: [f32; 4],
+    ) -> Self {
+        Mesh::from_samples(&grid.sample(field), grid, iso_level, color)
+            .expect("the grid takes one sample per point")
+    }
+
+    /// Builds the surface where samples already taken over the grid, like a voxel volume,
+    /// equal `iso_level`. The samples are ordered like `SampleGrid::sample` returns them.
+    /// Returns `None` if there isn't exactly one sample per point of the grid.
+    pub fn from_samples(
+        samples: &[f32],
+        grid: &SampleGrid,
+        iso_level: f32,
+        color: [f32; 4],
+    ) -> Option<Self> {
+        let [nx, ny, nz] = grid.points();
+        if samples.len() != nx * ny * nz {
+            return None;
+        }
+        let index = |x: usize, y: usize, z: usize| x + nx * (y + ny * z);
+        let cell_size = grid.cell_size();
+
+        // Central differences inside the grid, one-sided ones on its sides
+        let gradient = |x: usize, y: usize, z: usize| {
+            let difference = |axis: usize, n: usize, step: f32| {
+                let p = [x, y, z];
+                let mut lo = p;
+                let mut hi = p;
+                lo[axis] = p[axis].saturating_sub(1);
+                hi[axis] = (p[axis] + 1).min(n - 1);
+                let distance = (hi[axis] - lo[axis]) as f32 * step;
+                if distance > 0.0 {
+                    (samples[index(hi[0], hi[1], hi[2])] - samples[index(lo[0], lo[1], lo[2])])
+                        / distance
+                } else {
+                    0.0
+                }
+            };
+            glm::vec3(
+                difference(0, nx, cell_size.x),
+                difference(1, ny, cell_size.y),
+                difference(2, nz, cell_size.z),
+            )
+        };
+
+        let table = case_table();
+        let mut vertices = Vec::new();
+        let mut normals = Vec::new();
+        let mut indices = Vec::new();
+        // The vertex on each crossed grid edge, keyed by its first point and axis
+        let mut edge_vertices: HashMap<(usize, usize), u32> = HashMap::new();
+
+        for z in 0..grid.cells[2] {
+            for y in 0..grid.cells[1] {
+                for x in 0..grid.cells[0] {
+                    let corner = |c: usize| (x + (c & 1), y + ((c >> 1) & 1), z + (c >> 2));
+                    let value = |c: usize| {
+                        let (x, y, z) = corner(c);
+                        samples[index(x, y, z)]
+                    };
+                    let case = (0..8)
+                        .filter(|&c| value(c) < iso_level)
+                        .fold(0, |case, c| case | 1 << c);
+
+                    for polygon in &table[case] {
+                        let mut corners: Vec<u32> = polygon
+                            .edges
+                            .iter()
+                            .map(|&edge| {
+                                let (a, b) = EDGES[edge];
+                                let (pa, pb) = (corner(a), corner(b));
+                                let key = (index(pa.0, pa.1, pa.2), b - a);
+                                *edge_vertices.entry(key).or_insert_with(|| {
+                                    let t = (iso_level - value(a)) / (value(b) - value(a));
+                                    let position = glm::lerp(
+                                        &grid.position(pa.0, pa.1, pa.2),
+                                        &grid.position(pb.0, pb.1, pb.2),
+                                        t,
+                                    );
+                                    let normal = glm::lerp(
+                                        &gradient(pa.0, pa.1, pa.2),
+                                        &gradient(pb.0, pb.1, pb.2),
+                                        t,
+                                    );
+                                    push_vertex(&mut vertices, &mut normals, position, normal)
+                                })
+                            })
+                            .collect();
+
+                        let center = if polygon.fan {
+                            corners[0]
+                        } else {
+                            let center = average(&vertices, &corners);
+                            let normal = average(&normals, &corners);
+                            corners.push(corners[0]);
+                            push_vertex(&mut vertices, &mut normals, center, normal)
+                        };
+                        let first = if polygon.fan { 1 } else { 0 };
+                        for k in first..corners.len() - 1 {
+                            let triangle = [center, corners[k], corners[k + 1]];
+
+                            // Wind the triangle to face along the gradient
+                            let p = triangle.map(|v| vec3(&vertices, v));
+                            let n: glm::Vec3 = triangle.iter().map(|&v| vec3(&normals, v)).sum();
+                            let [a, b, c] = triangle;
+                            if (p[1] - p[0]).cross(&(p[2] - p[0])).dot(&n) < 0.0 {
+                                indices.extend_from_slice(&[a, c, b]);
+                            } else {
+                                indices.extend_from_slice(&[a, b, c]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let num_verts = vertices.len() / 3;
+        Some(Mesh {
+            bounds: Bounds::from_positions(&vertices),
+            vertices,
+            normals,
+            colors: generate_color_vec(color, num_verts),
+            texcoords: Vec::new(),
+            tangents: Vec::new(),
+            index_count: indices.len() as i32,
+            indices,
+            sub_meshes: Vec::new(),
+            material: Material::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const COLOR: [f32; 4] = [1.0; 4];
+
+    fn sphere(p: glm::Vec3) -> f32 {
+        p.norm() - 1.0
+    }
+
+    // internal helper, checks that every edge is used once each way by the triangles
+    fn assert_consistently_wound(mesh: &Mesh) {
+        let mut directed = HashSet::new();
+        for triangle in mesh.indices.chunks(3) {
+            for k in 0..3 {
+                let edge = (triangle[k], triangle[(k + 1) % 3]);
+                assert!(directed.insert(edge), "edge {:?} is used twice", edge);
+            }
+        }
+        for &(a, b) in &directed {
+            assert!(directed.contains(&(b, a)), "edge {:?} has one side", (a, b));
+        }
+    }
+
+    #[test]
+    fn a_sphere_is_closed_and_consistently_wound() {
+        for cells in [7, 16, 25] {
+            let mesh = Mesh::isosurface(sphere, &SampleGrid::cube(3.0, cells), 0.0, COLOR);
+            let analysis = mesh.analyze();
+            assert_eq!(analysis.boundary_edges, 0);
+            assert_eq!(analysis.non_manifold_edges, 0);
+            assert_eq!(analysis.components, 1);
+            assert_eq!(analysis.euler_characteristic, 2);
+            assert_eq!(analysis.genus(), Some(0));
+            assert_consistently_wound(&mesh);
+
+            // Outward facing, and close to the real sphere
+            let volume = 4.0 / 3.0 * std::f32::consts::PI;
+            assert!(
+                (analysis.volume - volume).abs() < 0.15 * volume,
+                "{}",
+                analysis.volume
+            );
+            for v in mesh.vertices.chunks(3) {
+                assert!(sphere(glm::vec3(v[0], v[1], v[2])).abs() < 3.0 / cells as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn a_torus_has_one_handle() {
+        let torus = |p: glm::Vec3| glm::vec2(glm::vec2(p.x, p.z).norm() - 1.0, p.y).norm() - 0.4;
+        let mesh = Mesh::isosurface(torus, &SampleGrid::cube(3.0, 29), 0.0, COLOR);
+        assert_eq!(mesh.analyze().genus(), Some(1));
+        assert_consistently_wound(&mesh);
+    }
+
+    #[test]
+    fn normals_follow_the_gradient() {
+        let mesh = Mesh::isosurface(sphere, &SampleGrid::cube(3.0, 20), 0.0, COLOR);
+        assert_eq!(mesh.normals.len(), mesh.vertices.len());
+        for (v, n) in mesh.vertices.chunks(3).zip(mesh.normals.chunks(3)) {
+            let gradient = glm::vec3(v[0], v[1], v[2]).normalize();
+            let normal = glm::vec3(n[0], n[1], n[2]);
+            assert!((normal.norm() - 1.0).abs() < 1e-4);
+            assert!(normal.dot(&gradient) > 0.98, "{} at {:?}", normal, v);
+        }
+
+        // Each triangle faces the way its normals do
+        for triangle in mesh.indices.chunks(3) {
+            let p = [0, 1, 2].map(|k| vec3(&mesh.vertices, triangle[k]));
+            let face = (p[1] - p[0]).cross(&(p[2] - p[0]));
+            assert!(face.dot(&vec3(&mesh.normals, triangle[0])) > 0.0);
+        }
+    }
+
+    #[test]
+    fn samples_must_match_the_grid() {
+        let grid = SampleGrid::cube(2.0, 4);
+        let samples = grid.sample(sphere);
+        assert_eq!(samples.len(), 125);
+        assert!(Mesh::from_samples(&samples[1..], &grid, 0.0, COLOR).is_none());
+        let mesh = Mesh::from_samples(&samples, &grid, 0.0, COLOR).unwrap();
+        assert_eq!(mesh.index_count as usize, mesh.indices.len());
+        assert!(!mesh.indices.is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use super::Mesh;
+use nalgebra_glm as glm;
+
+/// How `Mesh::generate_normals` should shade a mesh
+#[derive(Clone, Copy, Debug)]
+pub enum Shading {
+    /// Angle-weighted vertex normals. Faces meeting at a sharper angle than the crease angle
+    /// (in radians) are not smoothed together, which splits the vertices along the crease.
+    Smooth { crease_angle: f32 },
+    /// One normal per face, every triangle gets its own vertices
+    #[allow(dead_code)]
+    Flat,
+}
+
+impl Default for Shading {
+    fn default() -> Self {
+        Shading::Smooth {
+            crease_angle: 60f32.to_radians(),
+        }
+    }
+}
+
+// internal helper
+fn position(mesh: &Mesh, i: u32) -> glm::Vec3 {
+    let i = i as usize * 3;
+    glm::vec3(mesh.vertices[i], mesh.vertices[i + 1], mesh.vertices[i + 2])
+}
+
+// internal helper, the unit normal of each triangle and the angle at each of its corners
+fn face_normals_and_angles(mesh: &Mesh) -> (Vec<glm::Vec3>, Vec<f32>) {
+    let mut normals = Vec::with_capacity(mesh.indices.len() / 3);
+    let mut angles = Vec::with_capacity(mesh.indices.len());
+
+    for tri in mesh.indices.chunks_exact(3) {
+        let p = [
+            position(mesh, tri[0]),
+            position(mesh, tri[1]),
+            position(mesh, tri[2]),
+        ];
+        let n = glm::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+        let length = glm::length(&n);
+        normals.push(if length > 0.0 {
+            n / length
+        } else {
+            glm::zero()
+        });
+
+        for k in 0..3 {
+            let a = p[(k + 1) % 3] - p[k];
+            let b = p[(k + 2) % 3] - p[k];
+            let angle = if glm::length(&a) > 0.0 && glm::length(&b) > 0.0 {
+                glm::angle(&a, &b)
+            } else {
+                0.0
+            };
+            angles.push(angle);
+        }
+    }
+
+    (normals, angles)
+}
+
+impl Mesh {
+    /// Returns true if there is exactly one normal per vertex
+    pub fn has_normals(&self) -> bool {
+        !self.normals.is_empty() && self.normals.len() == self.vertices.len()
+    }
+
+    /// Generates smooth normals if the mesh has none, or if they don't match the vertices
+    pub fn ensure_normals(&mut self) {
+        if !self.has_normals() {
+            self.generate_normals(Shading::default());
+        }
+    }
+
+    /// Replaces the normals of the mesh, splitting vertices where needed
+    pub fn generate_normals(&mut self, shading: Shading) {
+        let (face_normals, angles) = face_normals_and_angles(self);
+
+        let mut source = Vec::with_capacity(self.indices.len());
+        let mut normals = Vec::with_capacity(self.indices.len() * 3);
+        let mut indices = Vec::with_capacity(self.indices.len());
+
+        match shading {
+            Shading::Flat => {
+                for (c, &v) in self.indices.iter().enumerate() {
+                    source.push(v);
+                    normals.extend_from_slice(face_normals[c / 3].as_slice());
+                    indices.push(c as u32);
+                }
+            }
+            Shading::Smooth { crease_angle } => {
+                let cos_crease = crease_angle.cos();
+
+                // The corners touching each vertex
+                let mut corners = vec![Vec::new(); self.vertices.len() / 3];
+                for (c, &v) in self.indices.iter().enumerate() {
+                    corners[v as usize].push(c);
+                }
+
+                // The normals already emitted for each vertex, with their new index
+                let mut emitted: Vec<Vec<(glm::Vec3, u32)>> = vec![Vec::new(); corners.len()];
+
+                for (c, &v) in self.indices.iter().enumerate() {
+                    let face_normal = face_normals[c / 3];
+                    let mut n: glm::Vec3 = corners[v as usize]
+                        .iter()
+                        .filter(|&&other| {
+                            glm::dot(&face_normals[other / 3], &face_normal) >= cos_crease
+                        })
+                        .map(|&other| face_normals[other / 3] * angles[other])
+                        .sum();
+                    let length = glm::length(&n);
+                    n = if length > 0.0 {
+                        n / length
+                    } else {
+                        face_normal
+                    };
+
+                    let existing = emitted[v as usize]
+                        .iter()
+                        .find(|(other, _)| glm::distance2(other, &n) < 1e-8);
+                    let index = match existing {
+                        Some(&(_, index)) => index,
+                        None => {
+                            let index = source.len() as u32;
+                            source.push(v);
+                            normals.extend_from_slice(n.as_slice());
+                            emitted[v as usize].push((n, index));
+                            index
+                        }
+                    };
+                    indices.push(index);
+                }
+            }
+        }
+
+        self.remap_vertices(&source);
+        self.normals = normals;
+        // The tangents were made for the old normals
+        self.tangents.clear();
+        self.indices = indices;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR: [f32; 4] = [1.0; 4];
+
+    // internal helper, a cube with one vertex per corner, shared by its three faces
+    fn shared_cube() -> Mesh {
+        let positions = (0..8)
+            .flat_map(|i| [0, 1, 2].map(|k| if i >> k & 1 == 1 { 1.0 } else { -1.0 }))
+            .collect();
+        #[rustfmt::skip]
+        let indices = vec![
+            0, 2, 1, 1, 2, 3, // -Z
+            4, 5, 6, 5, 7, 6, // +Z
+            0, 1, 4, 1, 5, 4, // -Y
+            2, 6, 3, 3, 6, 7, // +Y
+            0, 4, 2, 2, 4, 6, // -X
+            1, 3, 5, 3, 7, 5, // +X
+        ];
+        let mut cube = Mesh::from(
+            tobj::Mesh {
+                positions,
+                indices,
+                ..Default::default()
+            },
+            COLOR,
+        );
+        // Loading split the corners for their normals, join them up again
+        cube.normals.clear();
+        cube.weld(1e-6);
+        cube
+    }
+
+    // internal helper
+    fn normal(mesh: &Mesh, v: u32) -> glm::Vec3 {
+        let i = v as usize * 3;
+        glm::vec3(mesh.normals[i], mesh.normals[i + 1], mesh.normals[i + 2])
+    }
+
+    #[test]
+    fn smooth_sphere_normals_point_outwards() {
+        let mut sphere = Mesh::uv_sphere(2.0, 32, 16, COLOR);
+        sphere.normals.clear();
+        sphere.ensure_normals();
+        assert!(sphere.has_normals());
+        for v in 0..sphere.vertices.len() as u32 / 3 {
+            let radial = position(&sphere, v).normalize();
+            assert!(normal(&sphere, v).dot(&radial) > 0.99, "{}", v);
+            assert!((normal(&sphere, v).norm() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn creases_split_the_corners() {
+        let mut cube = shared_cube();
+        assert_eq!(cube.vertices.len() / 3, 8);
+        cube.generate_normals(Shading::Smooth {
+            crease_angle: 30f32.to_radians(),
+        });
+        // Every corner is split into one vertex per face, each with the face normal
+        assert_eq!(cube.vertices.len() / 3, 24);
+        for (tri, face_normal) in cube
+            .indices
+            .chunks_exact(3)
+            .zip(face_normals_and_angles(&cube).0)
+        {
+            for &v in tri {
+                assert_eq!(normal(&cube, v), face_normal);
+            }
+        }
+
+        // Without the crease the corners stay whole, their normals halfway between the faces
+        let mut cube = shared_cube();
+        cube.generate_normals(Shading::Smooth {
+            crease_angle: 120f32.to_radians(),
+        });
+        assert_eq!(cube.vertices.len() / 3, 8);
+        for v in 0..8 {
+            let diagonal = position(&cube, v).normalize();
+            assert!(glm::distance(&normal(&cube, v), &diagonal) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn flat_shading_splits_every_triangle() {
+        let mut sphere = Mesh::icosphere(1.0, 2, COLOR);
+        let triangles = sphere.indices.len() / 3;
+        sphere.generate_normals(Shading::Flat);
+
+        assert_eq!(sphere.vertices.len() / 3, triangles * 3);
+        assert_eq!(sphere.colors.len() / 4, triangles * 3);
+        let mut seen = vec![false; triangles * 3];
+        let face_normals = face_normals_and_angles(&sphere).0;
+        for (tri, face_normal) in sphere.indices.chunks_exact(3).zip(face_normals) {
+            for &v in tri {
+                assert!(!seen[v as usize]);
+                seen[v as usize] = true;
+                assert_eq!(normal(&sphere, v), face_normal);
+            }
+            // Outwards, as the triangles wind counter-clockwise
+            assert!(face_normal.dot(&position(&sphere, tri[0])) > 0.0);
+        }
+    }
+
+    #[test]
+    fn ensure_normals_keeps_fitting_normals() {
+        let mut cube = Mesh::cube(2.0, 1, COLOR);
+        // Not what would be generated, but one per vertex
+        let normals = [0.0, 1.0, 0.0].repeat(cube.vertices.len() / 3);
+        cube.normals = normals.clone();
+        cube.ensure_normals();
+        assert_eq!(cube.normals, normals);
+
+        cube.normals.truncate(normals.len() - 3);
+        cube.ensure_normals();
+        assert!(cube.has_normals());
+        assert_ne!(
+            cube.normals[..normals.len() - 3],
+            normals[..normals.len() - 3]
+        );
+    }
+}
@@ -0,0 +1,572 @@
+use super::Mesh;
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+// Mesh optimisation for the GPU: welding, triangle order for the post-transform vertex cache
+// (Forsyth's "Linear-Speed Vertex Cache Optimisation"), cluster order against overdraw and
+// vertex order for fetching. Sub-meshes are reordered within their own ranges, so they can
+// still be drawn on their own.
+
+// The cache the ACMR is measured with, a FIFO like most hardware
+const FIFO_CACHE_SIZE: usize = 16;
+
+// Tuning of the Forsyth scoring, the values from the paper
+const LRU_CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+// How much worse the ACMR may get when reordering for overdraw
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+/// Numbers for measuring how well a mesh suits the GPU
+#[derive(Clone, Copy, Debug)]
+pub struct MeshStats {
+    pub vertex_count: usize,
+    pub triangle_count: usize,
+    /// Average cache miss ratio, the vertex shader runs per triangle. 0.5 is the best possible.
+    pub acmr: f32,
+    /// Average transformed vertex ratio, the vertex shader runs per vertex. 1.0 is the best possible.
+    pub atvr: f32,
+}
+
+impl fmt::Display for MeshStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} vertices, {} triangles, ACMR {:.3}, ATVR {:.3}",
+            self.vertex_count, self.triangle_count, self.acmr, self.atvr
+        )
+    }
+}
+
+// internal helper, the number of vertex cache misses of each triangle with a FIFO cache
+fn cache_misses(indices: &[u32], num_verts: usize) -> Vec<u8> {
+    // The time each vertex entered the cache, it is still there if that is recent enough
+    let mut entered = vec![usize::MAX; num_verts];
+    let mut time = 0;
+    indices
+        .chunks_exact(3)
+        .map(|tri| {
+            let mut misses = 0;
+            for &v in tri {
+                let v = v as usize;
+                if entered[v] == usize::MAX || time - entered[v] >= FIFO_CACHE_SIZE {
+                    time += 1;
+                    entered[v] = time;
+                    misses += 1;
+                }
+            }
+            misses
+        })
+        .collect()
+}
+
+// internal helper, the Forsyth score of a vertex at a position in the LRU cache
+fn vertex_score(cache_position: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The vertices of the last triangle get a fixed score, so it isn't repeated right away
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            (1.0 - (position - 3) as f32 / (LRU_CACHE_SIZE - 3) as f32).powf(CACHE_DECAY_POWER)
+        }
+    };
+    cache_score + VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+// internal helper, Forsyth's greedy triangle order
+fn cache_order(indices: &[u32], num_verts: usize) -> Vec<u32> {
+    let num_tris = indices.len() / 3;
+
+    // The triangles using each vertex, packed into one array
+    let mut offsets = vec![0; num_verts + 1];
+    for &v in indices {
+        offsets[v as usize + 1] += 1;
+    }
+    for v in 0..num_verts {
+        offsets[v + 1] += offsets[v];
+    }
+    let mut adjacency = vec![0; indices.len()];
+    let mut filled = offsets.clone();
+    for (i, &v) in indices.iter().enumerate() {
+        adjacency[filled[v as usize]] = i / 3;
+        filled[v as usize] += 1;
+    }
+    let triangles_of = |v: u32| &adjacency[offsets[v as usize]..offsets[v as usize + 1]];
+
+    let mut remaining: Vec<u32> = (0..num_verts)
+        .map(|v| (offsets[v + 1] - offsets[v]) as u32)
+        .collect();
+    let mut cache_position: Vec<Option<usize>> = vec![None; num_verts];
+    let mut vertex_scores: Vec<f32> = (0..num_verts)
+        .map(|v| vertex_score(None, remaining[v]))
+        .collect();
+    let mut triangle_scores: Vec<f32> = indices
+        .chunks_exact(3)
+        .map(|tri| tri.iter().map(|&v| vertex_scores[v as usize]).sum())
+        .collect();
+    let mut emitted = vec![false; num_tris];
+
+    let mut order = Vec::with_capacity(indices.len());
+    let mut cache: Vec<u32> = Vec::with_capacity(LRU_CACHE_SIZE + 3);
+    let mut best = (0..num_tris).max_by(|&a, &b| triangle_scores[a].total_cmp(&triangle_scores[b]));
+    // Where to look for a triangle when none of the cached vertices has any left
+    let mut next_unemitted = 0;
+
+    while let Some(triangle) = best {
+        let tri = &indices[triangle * 3..triangle * 3 + 3];
+        emitted[triangle] = true;
+        order.extend_from_slice(tri);
+        for &v in tri {
+            remaining[v as usize] -= 1;
+        }
+
+        // Move the vertices of the triangle to the front of the cache
+        let mut updated: Vec<u32> = tri.to_vec();
+        updated.extend(cache.iter().filter(|v| !tri.contains(v)));
+        for (position, &v) in updated.iter().enumerate() {
+            cache_position[v as usize] = if position < LRU_CACHE_SIZE {
+                Some(position)
+            } else {
+                None
+            };
+            let score = vertex_score(cache_position[v as usize], remaining[v as usize]);
+            let delta = score - vertex_scores[v as usize];
+            vertex_scores[v as usize] = score;
+            for &t in triangles_of(v) {
+                triangle_scores[t] += delta;
+            }
+        }
+        updated.truncate(LRU_CACHE_SIZE);
+        cache = updated;
+
+        best = None;
+        let mut best_score = f32::NEG_INFINITY;
+        for &v in &cache {
+            for &t in triangles_of(v) {
+                if !emitted[t] && triangle_scores[t] > best_score {
+                    best = Some(t);
+                    best_score = triangle_scores[t];
+                }
+            }
+        }
+        if best.is_none() {
+            while next_unemitted < num_tris && emitted[next_unemitted] {
+                next_unemitted += 1;
+            }
+            if next_unemitted < num_tris {
+                best = Some(next_unemitted);
+            }
+        }
+    }
+    order
+}
+
+// internal helper, reorders clusters of triangles so the outward facing ones are drawn first,
+// only splitting where it costs at most `threshold` times the ACMR
+fn overdraw_order(indices: &[u32], vertices: &[f32], threshold: f32) -> Vec<u32> {
+    let num_verts = vertices.len() / 3;
+    let misses = cache_misses(indices, num_verts);
+    if misses.is_empty() {
+        return indices.to_vec();
+    }
+    let total_misses: usize = misses.iter().map(|&m| m as usize).sum();
+    let target = threshold * total_misses as f32 / misses.len() as f32;
+
+    // A cluster can start wherever the cache is cold anyway, or where its ACMR is good enough
+    let mut starts = vec![0];
+    let mut cluster_misses = 0;
+    for (t, &m) in misses.iter().enumerate() {
+        let start = *starts.last().unwrap();
+        let cluster_acmr = cluster_misses as f32 / (t - start).max(1) as f32;
+        if t > start && (m == 3 || (m >= 2 && cluster_acmr <= target)) {
+            starts.push(t);
+            cluster_misses = 0;
+        }
+        cluster_misses += m as usize;
+    }
+    starts.push(misses.len());
+
+    let position = |i: u32| {
+        let i = i as usize * 3;
+        glm::vec3(vertices[i], vertices[i + 1], vertices[i + 2])
+    };
+    let mut mesh_centroid = glm::Vec3::zeros();
+    for &i in indices {
+        mesh_centroid += position(i);
+    }
+    mesh_centroid /= indices.len() as f32;
+
+    // The further a cluster is out along its own normal, the more it tends to hide
+    let mut clusters: Vec<(f32, Range<usize>)> = starts
+        .windows(2)
+        .map(|w| {
+            let (mut centroid, mut normal, mut area) =
+                (glm::Vec3::zeros(), glm::Vec3::zeros(), 0.0);
+            for tri in indices[w[0] * 3..w[1] * 3].chunks_exact(3) {
+                let (a, b, c) = (position(tri[0]), position(tri[1]), position(tri[2]));
+                let cross = (b - a).cross(&(c - a));
+                let tri_area = cross.norm();
+                centroid += (a + b + c) / 3.0 * tri_area;
+                normal += cross;
+                area += tri_area;
+            }
+            let score = if area > 0.0 && normal.norm() > 0.0 {
+                (centroid / area - mesh_centroid).dot(&normal.normalize())
+            } else {
+                0.0
+            };
+            (score, w[0]..w[1])
+        })
+        .collect();
+    clusters.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    clusters
+        .iter()
+        .flat_map(|(_, range)| indices[range.start * 3..range.end * 3].iter().cloned())
+        .collect()
+}
+
+#[allow(dead_code)]
+impl Mesh {
+    /// Measures the mesh with a FIFO vertex cache of 16 entries
+    pub fn stats(&self) -> MeshStats {
+        let vertex_count = self.vertices.len() / 3;
+        let triangle_count = self.indices.len() / 3;
+        let misses: usize = cache_misses(&self.indices, vertex_count)
+            .iter()
+            .map(|&m| m as usize)
+            .sum();
+        MeshStats {
+            vertex_count,
+            triangle_count,
+            acmr: misses as f32 / triangle_count.max(1) as f32,
+            atvr: misses as f32 / vertex_count.max(1) as f32,
+        }
+    }
+
+    /// Merges vertices whose positions are within `epsilon` of each other, and whose
+    /// other attributes differ by at most `epsilon`. Returns the number of removed vertices.
+    /// Triangles that collapse are kept, `repair` drops them.
+    pub fn weld(&mut self, epsilon: f32) -> usize {
+        let num_verts = self.vertices.len() / 3;
+        let cell_size = epsilon.max(f32::MIN_POSITIVE);
+        let cell = |v: usize| {
+            let p = &self.vertices[v * 3..v * 3 + 3];
+            [0, 1, 2].map(|k| (p[k] / cell_size).floor() as i64)
+        };
+        let attributes: Vec<(&[f32], usize)> = [
+            (&self.normals, 3),
+            (&self.colors, 4),
+            (&self.texcoords, 2),
+            (&self.tangents, 4),
+        ]
+        .iter()
+        .filter(|(data, width)| data.len() == num_verts * width)
+        .map(|&(data, width)| (data.as_slice(), width))
+        .collect();
+        let matches = |a: usize, b: usize| {
+            let (pa, pb) = (
+                &self.vertices[a * 3..a * 3 + 3],
+                &self.vertices[b * 3..b * 3 + 3],
+            );
+            glm::distance(
+                &glm::vec3(pa[0], pa[1], pa[2]),
+                &glm::vec3(pb[0], pb[1], pb[2]),
+            ) <= epsilon
+                && attributes.iter().all(|&(data, width)| {
+                    (0..width).all(|k| (data[a * width + k] - data[b * width + k]).abs() <= epsilon)
+                })
+        };
+
+        // The vertices that are kept, by the cell they are in
+        let mut grid: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        let mut target: Vec<u32> = Vec::with_capacity(num_verts);
+        for v in 0..num_verts {
+            let [x, y, z] = cell(v);
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        if let Some(kept) = grid.get(&[x + dx, y + dy, z + dz]) {
+                            if let Some(&w) = kept.iter().find(|&&w| matches(v, w as usize)) {
+                                found = Some(w);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            target.push(found.unwrap_or_else(|| {
+                grid.entry([x, y, z]).or_default().push(v as u32);
+                v as u32
+            }));
+        }
+
+        for index in self.indices.iter_mut() {
+            *index = target[*index as usize];
+        }
+        self.optimize_vertex_fetch();
+        num_verts - self.vertices.len() / 3
+    }
+
+    /// Reorders the triangles so the vertex cache is hit as often as possible
+    pub fn optimize_vertex_cache(&mut self) {
+        let num_verts = self.vertices.len() / 3;
+        for range in self.triangle_ranges() {
+            let order = cache_order(&self.indices[range.clone()], num_verts);
+            self.indices[range].copy_from_slice(&order);
+        }
+    }
+
+    /// Reorders clusters of triangles so the outward facing ones are drawn first, which lets the
+    /// depth test skip more of the hidden ones. Run this after `optimize_vertex_cache`,
+    /// as it keeps its clusters and makes the ACMR at most `threshold` times worse, e.g. 1.05.
+    pub fn optimize_overdraw(&mut self, threshold: f32) {
+        for range in self.triangle_ranges() {
+            let order = overdraw_order(&self.indices[range.clone()], &self.vertices, threshold);
+            self.indices[range].copy_from_slice(&order);
+        }
+    }
+
+    /// Reorders the vertices in the order the triangles first use them, which makes fetching
+    /// them more cache friendly. Vertices no triangle uses are dropped.
+    pub fn optimize_vertex_fetch(&mut self) {
+        let mut target = vec![u32::MAX; self.vertices.len() / 3];
+        let mut source = Vec::with_capacity(target.len());
+        for index in self.indices.iter_mut() {
+            let v = *index as usize;
+            if target[v] == u32::MAX {
+                target[v] = source.len() as u32;
+                source.push(v as u32);
+            }
+            *index = target[v];
+        }
+        self.remap_vertices(&source);
+        self.update_bounds();
+    }
+
+    /// Runs all of the optimisations, returning the stats from before and after
+    pub fn optimize(&mut self, weld_epsilon: f32) -> (MeshStats, MeshStats) {
+        let before = self.stats();
+        self.weld(weld_epsilon);
+        self.optimize_vertex_cache();
+        self.optimize_overdraw(OVERDRAW_THRESHOLD);
+        self.optimize_vertex_fetch();
+        (before, self.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::SubMesh;
+    use std::collections::VecDeque;
+
+    const COLOR: [f32; 4] = [1.0; 4];
+
+    // internal helper, a mesh from positions and indices alone
+    fn mesh(positions: &[[f32; 3]], indices: &[u32]) -> Mesh {
+        Mesh::from(
+            tobj::Mesh {
+                positions: positions.iter().flatten().cloned().collect(),
+                indices: indices.to_vec(),
+                ..Default::default()
+            },
+            COLOR,
+        )
+    }
+
+    // internal helper, shuffles the triangles of each range with a fixed seed
+    fn shuffle_triangles(mesh: &mut Mesh) {
+        let mut state: u64 = 12345;
+        for range in mesh.triangle_ranges() {
+            let triangles = mesh.indices[range.clone()].chunks_exact(3).count();
+            for i in (1..triangles).rev() {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let j = (state >> 33) as usize % (i + 1);
+                for k in 0..3 {
+                    mesh.indices
+                        .swap(range.start + i * 3 + k, range.start + j * 3 + k);
+                }
+            }
+        }
+    }
+
+    // internal helper, the triangles of each range by the positions of their corners,
+    // starting from the smallest corner so the winding is kept
+    fn triangles(mesh: &Mesh) -> Vec<Vec<[[u32; 3]; 3]>> {
+        let corner = |v: u32| [0, 1, 2].map(|k| mesh.vertices[v as usize * 3 + k].to_bits());
+        mesh.triangle_ranges()
+            .into_iter()
+            .map(|range| {
+                let mut triangles: Vec<[[u32; 3]; 3]> = mesh.indices[range]
+                    .chunks_exact(3)
+                    .map(|tri| {
+                        let mut corners = [corner(tri[0]), corner(tri[1]), corner(tri[2])];
+                        let first = (0..3).min_by_key(|&k| corners[k]).unwrap();
+                        corners.rotate_left(first);
+                        corners
+                    })
+                    .collect();
+                triangles.sort_unstable();
+                triangles
+            })
+            .collect()
+    }
+
+    // internal helper, counts the misses of a 16 entry FIFO cache one vertex at a time
+    fn recount_misses(indices: &[u32]) -> usize {
+        let mut cache = VecDeque::new();
+        let mut misses = 0;
+        for &v in indices {
+            if !cache.contains(&v) {
+                misses += 1;
+                cache.push_back(v);
+                if cache.len() > 16 {
+                    cache.pop_front();
+                }
+            }
+        }
+        misses
+    }
+
+    // internal helper, a sphere and a box as two sub-meshes, shuffled
+    fn two_parts() -> Mesh {
+        let mut mesh = Mesh::merge(vec![
+            ("sphere".to_string(), Mesh::uv_sphere(1.0, 24, 12, COLOR)),
+            ("box".to_string(), Mesh::cube(1.0, 6, COLOR)),
+        ]);
+        shuffle_triangles(&mut mesh);
+        mesh
+    }
+
+    #[test]
+    fn weld_merges_within_epsilon() {
+        let epsilon = 0.01;
+        let mut mesh = mesh(
+            &[
+                [0.0, 0.0, 0.0],
+                [0.999, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                // Within epsilon of the two above, the first one across a cell boundary
+                [1.0005, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.005, 0.0],
+                // Just beyond epsilon of the three above
+                [0.999, -0.011, 0.0],
+                [1.0, -1.0, 0.0],
+                [0.0, -0.015, 0.0],
+            ],
+            &[0, 1, 2, 3, 4, 5, 6, 7, 8],
+        );
+        assert_eq!(
+            ((0.999f32 / epsilon).floor(), (1.0005f32 / epsilon).floor()),
+            (99.0, 100.0)
+        );
+
+        assert_eq!(mesh.weld(epsilon), 2);
+        assert_eq!(mesh.vertices.len() / 3, 7);
+        assert_eq!(mesh.normals.len(), 7 * 3);
+        assert_eq!(mesh.colors.len(), 7 * 4);
+        assert_eq!(mesh.indices[..3], [0, 1, 2]);
+        assert_eq!(mesh.indices[3], 1);
+        assert_eq!(mesh.indices[5], 2);
+        assert_eq!(mesh.indices[6..], [4, 5, 6]);
+    }
+
+    #[test]
+    fn weld_keeps_vertices_with_other_attributes() {
+        // The same triangle twice, seen from both sides
+        let corners = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let mut mesh = mesh(&[corners, corners].concat(), &[0, 1, 2, 3, 5, 4]);
+        assert_eq!(mesh.weld(0.01), 0);
+        assert_eq!(mesh.vertices.len() / 3, 6);
+    }
+
+    #[test]
+    fn cache_order_lowers_the_acmr() {
+        let mut plane = Mesh::plane(1.0, 1.0, 32, 32, COLOR);
+        shuffle_triangles(&mut plane);
+        let shuffled = plane.stats();
+        let before = triangles(&plane);
+
+        plane.optimize_vertex_cache();
+        let ordered = plane.stats();
+        assert_eq!(triangles(&plane), before);
+        assert!(shuffled.acmr > 2.0, "{}", shuffled);
+        assert!(ordered.acmr < 0.8, "{}", ordered);
+    }
+
+    #[test]
+    fn reordering_keeps_the_sub_meshes() {
+        let mut mesh = two_parts();
+        let ranges: Vec<(i32, i32)> = mesh
+            .sub_meshes
+            .iter()
+            .map(|s| (s.first_index, s.index_count))
+            .collect();
+        let before = triangles(&mesh);
+
+        mesh.optimize_vertex_cache();
+        mesh.optimize_overdraw(OVERDRAW_THRESHOLD);
+        assert_eq!(triangles(&mesh), before);
+        mesh.optimize_vertex_fetch();
+        assert_eq!(triangles(&mesh), before);
+
+        let after: Vec<(i32, i32)> = mesh
+            .sub_meshes
+            .iter()
+            .map(
+                |SubMesh {
+                     first_index,
+                     index_count,
+                     ..
+                 }| (*first_index, *index_count),
+            )
+            .collect();
+        assert_eq!(after, ranges);
+        // The vertices are fetched in the order they are first used
+        let mut next = 0;
+        for &index in &mesh.indices {
+            assert!(index <= next);
+            if index == next {
+                next += 1;
+            }
+        }
+        assert_eq!(next as usize, mesh.vertices.len() / 3);
+    }
+
+    #[test]
+    fn optimize_reports_the_stats() {
+        let mut mesh = two_parts();
+        let count = |mesh: &Mesh| {
+            let misses = recount_misses(&mesh.indices) as f32;
+            let (verts, tris) = (mesh.vertices.len() / 3, mesh.indices.len() / 3);
+            (verts, tris, misses / tris as f32, misses / verts as f32)
+        };
+        let expected_before = count(&mesh);
+
+        let (before, after) = mesh.optimize(1e-6);
+        let expected_after = count(&mesh);
+        for (stats, expected) in [(before, expected_before), (after, expected_after)] {
+            assert_eq!(stats.vertex_count, expected.0);
+            assert_eq!(stats.triangle_count, expected.1);
+            assert!((stats.acmr - expected.2).abs() < 1e-6);
+            assert!((stats.atvr - expected.3).abs() < 1e-6);
+        }
+        // The seams of the sphere are welded, and the order is much better
+        assert!(after.vertex_count < before.vertex_count);
+        assert_eq!(after.triangle_count, before.triangle_count);
+        assert!(after.acmr < before.acmr / 2.0, "{} {}", before, after);
+    }
+}
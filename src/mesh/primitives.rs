@@ -0,0 +1,395 @@
+use super::{generate_color_vec, Bounds, Material, Mesh};
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+// Segment and ring counts below the fewest that make a proper shape are raised to it,
+// e.g. a ring of less than three slices has no area.
+const MIN_GRID_SEGMENTS: u32 = 1;
+const MIN_RING_SEGMENTS: u32 = 3;
+const MIN_SPHERE_RINGS: u32 = 2;
+
+// internal helper for building meshes vertex by vertex
+#[derive(Default)]
+struct Builder {
+    vertices: Vec<f32>,
+    normals: Vec<f32>,
+    texcoords: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+impl Builder {
+    fn vertex(&mut self, position: glm::Vec3, normal: glm::Vec3, uv: [f32; 2]) -> u32 {
+        self.vertices.extend_from_slice(position.as_slice());
+        self.normals.extend_from_slice(normal.as_slice());
+        self.texcoords.extend_from_slice(&uv);
+        (self.vertices.len() / 3 - 1) as u32
+    }
+
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.extend_from_slice(&[a, b, c]);
+    }
+
+    /// A grid of quads spanning `center ± u_axis ± v_axis`, facing `u_axis × v_axis`
+    fn grid(
+        &mut self,
+        center: glm::Vec3,
+        u_axis: glm::Vec3,
+        v_axis: glm::Vec3,
+        u_segments: u32,
+        v_segments: u32,
+    ) {
+        let u_segments = u_segments.max(MIN_GRID_SEGMENTS);
+        let v_segments = v_segments.max(MIN_GRID_SEGMENTS);
+        let normal = glm::normalize(&glm::cross(&u_axis, &v_axis));
+        let first = self.vertices.len() as u32 / 3;
+        let row = u_segments + 1;
+
+        for j in 0..=v_segments {
+            for i in 0..=u_segments {
+                let s = i as f32 / u_segments as f32;
+                let t = j as f32 / v_segments as f32;
+                let position = center + u_axis * (2.0 * s - 1.0) + v_axis * (2.0 * t - 1.0);
+                self.vertex(position, normal, [s, t]);
+            }
+        }
+        for j in 0..v_segments {
+            for i in 0..u_segments {
+                let a = first + j * row + i;
+                self.triangle(a, a + 1, a + row + 1);
+                self.triangle(a, a + row + 1, a + row);
+            }
+        }
+    }
+
+    /// A surface of revolution around the Y axis, from the top row down to the bottom row.
+    /// Each row is `(radius, y, radial normal, vertical normal)`.
+    fn lathe(&mut self, rows: &[(f32, f32, f32, f32)], segments: u32) {
+        let segments = segments.max(MIN_RING_SEGMENTS);
+        let first = self.vertices.len() as u32 / 3;
+        let ring = segments + 1;
+
+        for (i, &(radius, y, normal_radial, normal_y)) in rows.iter().enumerate() {
+            for j in 0..=segments {
+                let phi = 2.0 * PI * j as f32 / segments as f32;
+                let (sin, cos) = phi.sin_cos();
+                self.vertex(
+                    glm::vec3(radius * sin, y, radius * cos),
+                    glm::normalize(&glm::vec3(
+                        normal_radial * sin,
+                        normal_y,
+                        normal_radial * cos,
+                    )),
+                    [
+                        j as f32 / segments as f32,
+                        1.0 - i as f32 / (rows.len() - 1) as f32,
+                    ],
+                );
+            }
+        }
+        for i in 0..rows.len() as u32 - 1 {
+            for j in 0..segments {
+                let a = first + i * ring + j;
+                let (b, c, d) = (a + ring, a + ring + 1, a + 1);
+                // Rows with a radius of zero collapse into a point
+                if rows[i as usize + 1].0 > 0.0 {
+                    self.triangle(a, b, c);
+                }
+                if rows[i as usize].0 > 0.0 {
+                    self.triangle(a, c, d);
+                }
+            }
+        }
+    }
+
+    /// A flat disc at height `y`, facing up or down
+    fn cap(&mut self, radius: f32, y: f32, up: bool, segments: u32) {
+        let segments = segments.max(MIN_RING_SEGMENTS);
+        let normal = glm::vec3(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+        let center = self.vertex(glm::vec3(0.0, y, 0.0), normal, [0.5, 0.5]);
+        for j in 0..=segments {
+            let phi = 2.0 * PI * j as f32 / segments as f32;
+            let (sin, cos) = phi.sin_cos();
+            self.vertex(
+                glm::vec3(radius * sin, y, radius * cos),
+                normal,
+                [0.5 + 0.5 * sin, 0.5 + 0.5 * cos],
+            );
+        }
+        for j in 0..segments {
+            let (a, b) = (center + 1 + j, center + 2 + j);
+            if up {
+                self.triangle(center, a, b);
+            } else {
+                self.triangle(center, b, a);
+            }
+        }
+    }
+
+    fn build(self, color: [f32; 4]) -> Mesh {
+        let num_verts = self.vertices.len() / 3;
+        Mesh {
+            colors: generate_color_vec(color, num_verts),
+            bounds: Bounds::from_positions(&self.vertices),
+            vertices: self.vertices,
+            normals: self.normals,
+            texcoords: self.texcoords,
+            tangents: Vec::new(),
+            index_count: self.indices.len() as i32,
+            indices: self.indices,
+            sub_meshes: Vec::new(),
+            material: Material::default(),
+        }
+    }
+}
+
+// internal helper, the rows of a sphere split at the equator, with the halves `offset` above and below it
+fn hemisphere_rows(radius: f32, rings: u32, offset: f32) -> Vec<(f32, f32, f32, f32)> {
+    let rings = rings.max(MIN_SPHERE_RINGS / 2);
+    let mut rows = Vec::new();
+    for i in 0..=rings {
+        let (sin, cos) = (PI * 0.5 * i as f32 / rings as f32).sin_cos();
+        rows.push((radius * sin, radius * cos + offset, sin, cos));
+    }
+    for i in 0..=rings {
+        let (sin, cos) = (PI * 0.5 * (1.0 + i as f32 / rings as f32)).sin_cos();
+        rows.push((radius * sin, radius * cos - offset, sin, cos));
+    }
+    rows
+}
+
+// internal helper, a cone cut off at `top_radius`, centered at the origin
+fn frustum(
+    bottom_radius: f32,
+    top_radius: f32,
+    height: f32,
+    segments: u32,
+    color: [f32; 4],
+) -> Mesh {
+    let mut builder = Builder::default();
+    let slope = bottom_radius - top_radius;
+    builder.lathe(
+        &[
+            (top_radius, height / 2.0, height, slope),
+            (bottom_radius, -height / 2.0, height, slope),
+        ],
+        segments,
+    );
+    if top_radius > 0.0 {
+        builder.cap(top_radius, height / 2.0, true, segments);
+    }
+    builder.cap(bottom_radius, -height / 2.0, false, segments);
+    builder.build(color)
+}
+
+/// Procedurally generated meshes, centered at the origin and with outward facing normals
+impl Mesh {
+    /// An axis aligned cube, with each face split into `segments` × `segments` quads
+    #[allow(dead_code)]
+    pub fn cube(size: f32, segments: u32, color: [f32; 4]) -> Self {
+        let mut builder = Builder::default();
+        let h = size / 2.0;
+        let faces = [
+            (glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0)),
+            (glm::vec3(-1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0)),
+            (glm::vec3(0.0, 0.0, 1.0), glm::vec3(0.0, 1.0, 0.0)),
+            (glm::vec3(0.0, 0.0, -1.0), glm::vec3(0.0, 1.0, 0.0)),
+            (glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, -1.0)),
+            (glm::vec3(0.0, -1.0, 0.0), glm::vec3(0.0, 0.0, 1.0)),
+        ];
+        for (normal, up) in faces.iter() {
+            let right = glm::cross(up, normal);
+            builder.grid(normal * h, right * h, up * h, segments, segments);
+        }
+        builder.build(color)
+    }
+
+    /// A flat grid in the XZ plane, facing up
+    #[allow(dead_code)]
+    pub fn plane(
+        width: f32,
+        depth: f32,
+        segments_x: u32,
+        segments_z: u32,
+        color: [f32; 4],
+    ) -> Self {
+        let mut builder = Builder::default();
+        builder.grid(
+            glm::zero(),
+            glm::vec3(width / 2.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, -depth / 2.0),
+            segments_x,
+            segments_z,
+        );
+        builder.build(color)
+    }
+
+    /// A sphere made of `segments` slices around the Y axis and `rings` stacks from pole to pole
+    #[allow(dead_code)]
+    pub fn uv_sphere(radius: f32, segments: u32, rings: u32, color: [f32; 4]) -> Self {
+        let rings = rings.max(MIN_SPHERE_RINGS);
+        let rows: Vec<_> = (0..=rings)
+            .map(|i| {
+                let (sin, cos) = (PI * i as f32 / rings as f32).sin_cos();
+                (radius * sin, radius * cos, sin, cos)
+            })
+            .collect();
+        let mut builder = Builder::default();
+        builder.lathe(&rows, segments);
+        builder.build(color)
+    }
+
+    /// A sphere made by splitting each triangle of an icosahedron into four, `subdivisions` times.
+    /// The triangles are evenly sized, so there are no texture coordinates.
+    #[allow(dead_code)]
+    pub fn icosphere(radius: f32, subdivisions: u32, color: [f32; 4]) -> Self {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut points: Vec<glm::Vec3> = [
+            (-1.0, t, 0.0),
+            (1.0, t, 0.0),
+            (-1.0, -t, 0.0),
+            (1.0, -t, 0.0),
+            (0.0, -1.0, t),
+            (0.0, 1.0, t),
+            (0.0, -1.0, -t),
+            (0.0, 1.0, -t),
+            (t, 0.0, -1.0),
+            (t, 0.0, 1.0),
+            (-t, 0.0, -1.0),
+            (-t, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|&(x, y, z)| glm::normalize(&glm::vec3(x, y, z)))
+        .collect();
+        let mut triangles: Vec<[u32; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: u32, b: u32| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    let p = (points[a as usize] + points[b as usize]) / 2.0;
+                    points.push(glm::normalize(&p));
+                    points.len() as u32 - 1
+                })
+            };
+            triangles = triangles
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        let mut builder = Builder::default();
+        for p in &points {
+            builder.vertex(p * radius, *p, [0.0, 0.0]);
+        }
+        for &[a, b, c] in &triangles {
+            builder.triangle(a, b, c);
+        }
+        let mut mesh = builder.build(color);
+        mesh.texcoords.clear();
+        mesh
+    }
+
+    /// A capped cylinder along the Y axis
+    #[allow(dead_code)]
+    pub fn cylinder(radius: f32, height: f32, segments: u32, color: [f32; 4]) -> Self {
+        frustum(radius, radius, height, segments, color)
+    }
+
+    /// A cone along the Y axis, with the tip pointing up
+    #[allow(dead_code)]
+    pub fn cone(radius: f32, height: f32, segments: u32, color: [f32; 4]) -> Self {
+        frustum(radius, 0.0, height, segments, color)
+    }
+
+    /// A ring in the XZ plane, with `segments` slices around the Y axis and `sides` around the tube
+    #[allow(dead_code)]
+    pub fn torus(
+        major_radius: f32,
+        minor_radius: f32,
+        segments: u32,
+        sides: u32,
+        color: [f32; 4],
+    ) -> Self {
+        let sides = sides.max(MIN_RING_SEGMENTS);
+        // Walk around the tube downwards along the outside, like the rows of a sphere
+        let rows: Vec<_> = (0..=sides)
+            .map(|i| {
+                let (sin, cos) = (-2.0 * PI * i as f32 / sides as f32).sin_cos();
+                (
+                    major_radius + minor_radius * cos,
+                    minor_radius * sin,
+                    cos,
+                    sin,
+                )
+            })
+            .collect();
+        let mut builder = Builder::default();
+        builder.lathe(&rows, segments);
+        builder.build(color)
+    }
+
+    /// A cylinder of the given height with hemispheres on both ends, along the Y axis
+    #[allow(dead_code)]
+    pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32, color: [f32; 4]) -> Self {
+        let mut builder = Builder::default();
+        builder.lathe(&hemisphere_rows(radius, rings, height / 2.0), segments);
+        builder.build(color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_segments_are_raised_to_the_minimum() {
+        let color = [1.0; 4];
+        let meshes = [
+            Mesh::cube(1.0, 0, color),
+            Mesh::plane(1.0, 1.0, 0, 0, color),
+            Mesh::uv_sphere(1.0, 0, 0, color),
+            Mesh::cylinder(1.0, 1.0, 0, color),
+            Mesh::cone(1.0, 1.0, 0, color),
+            Mesh::torus(1.0, 0.25, 0, 0, color),
+            Mesh::capsule(1.0, 1.0, 0, 0, color),
+        ];
+        for mesh in &meshes {
+            assert!(!mesh.indices.is_empty());
+            assert!(mesh.vertices.iter().all(|x| x.is_finite()));
+            assert!(mesh.normals.iter().all(|x| x.is_finite()));
+            assert!(mesh.texcoords.iter().all(|x| x.is_finite()));
+        }
+        assert_eq!(Mesh::cube(1.0, 0, color).indices.len(), 6 * 2 * 3);
+        assert_eq!(
+            Mesh::uv_sphere(1.0, 0, 0, color).indices.len(),
+            Mesh::uv_sphere(1.0, MIN_RING_SEGMENTS, MIN_SPHERE_RINGS, color)
+                .indices
+                .len()
+        );
+    }
+}
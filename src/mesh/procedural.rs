@@ -0,0 +1,309 @@
+use super::{HeightmapOptions, Mesh, Terrain};
+
+/// The kind of fractal noise used for the base landscape
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    /// Fractional Brownian motion, gently rolling hills
+    Fbm,
+    /// Inverted absolute noise, sharp ridges and mountain chains
+    #[allow(dead_code)]
+    Ridged,
+}
+
+/// Settings for `Terrain::generate`. The same settings and seed always give the same mesh.
+#[derive(Clone, Debug)]
+pub struct TerrainGenerator {
+    pub seed: u64,
+    /// The number of samples along each side of the terrain, at least 2 are used
+    pub resolution: usize,
+    pub noise: NoiseKind,
+    pub octaves: u32,
+    /// How many times the base noise repeats across the terrain
+    pub frequency: f32,
+    /// How much the frequency grows for each octave
+    pub lacunarity: f32,
+    /// How much the amplitude shrinks for each octave
+    pub gain: f32,
+    pub crater_count: u32,
+    /// The smallest and largest crater radius, as fractions of the terrain size
+    pub crater_radius: (f32, f32),
+    /// The depth of the largest craters, relative to the height of the noise
+    pub crater_depth: f32,
+    pub heightmap: HeightmapOptions,
+}
+
+impl Default for TerrainGenerator {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            resolution: 256,
+            noise: NoiseKind::Fbm,
+            octaves: 6,
+            frequency: 4.0,
+            lacunarity: 2.0,
+            gain: 0.5,
+            crater_count: 40,
+            crater_radius: (0.01, 0.12),
+            crater_depth: 0.6,
+            heightmap: HeightmapOptions::default(),
+        }
+    }
+}
+
+// A terrain needs at least two samples along each side to have any triangles
+const MIN_RESOLUTION: usize = 2;
+
+// internal helper, the SplitMix64 generator. It is written out here instead of taken from `rand`,
+// whose generators may change between versions, so that a seed always gives the same terrain.
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in [0, 1), from the top 24 bits
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u32 << 24) as f32
+    }
+
+    /// A number in [0, n)
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+// internal helper, seeded 2D gradient noise in the style of Perlin's improved noise
+struct Noise {
+    permutation: Vec<usize>,
+}
+
+impl Noise {
+    fn new(rng: &mut SplitMix64) -> Self {
+        // Fisher-Yates shuffle
+        let mut permutation: Vec<usize> = (0..256).collect();
+        for i in (1..permutation.len()).rev() {
+            permutation.swap(i, rng.below(i + 1));
+        }
+        permutation.extend_from_within(..);
+        Noise { permutation }
+    }
+
+    fn gradient(&self, x: i32, y: i32, dx: f32, dy: f32) -> f32 {
+        let hash = self.permutation[self.permutation[(x & 255) as usize] + (y & 255) as usize];
+        match hash & 7 {
+            0 => dx + dy,
+            1 => dx - dy,
+            2 => -dx + dy,
+            3 => -dx - dy,
+            4 => dx,
+            5 => -dx,
+            6 => dy,
+            _ => -dy,
+        }
+    }
+
+    /// Noise roughly in the range [-1, 1]
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (xi, yi) = (x0 as i32, y0 as i32);
+        let (u, v) = (fade(dx), fade(dy));
+
+        lerp(
+            lerp(
+                self.gradient(xi, yi, dx, dy),
+                self.gradient(xi + 1, yi, dx - 1.0, dy),
+                u,
+            ),
+            lerp(
+                self.gradient(xi, yi + 1, dx, dy - 1.0),
+                self.gradient(xi + 1, yi + 1, dx - 1.0, dy - 1.0),
+                u,
+            ),
+            v,
+        )
+    }
+}
+
+// internal helper, the height added by a crater at `r` crater radii from its center
+fn crater_profile(r: f32, depth: f32) -> f32 {
+    let rim = depth * 0.25;
+    if r < 1.0 {
+        depth * (r * r - 1.0) + rim * r.powi(6)
+    } else {
+        rim * (-((r - 1.0) / 0.3).powi(2)).exp()
+    }
+}
+
+impl TerrainGenerator {
+    /// The number of samples along each side of the terrain that is actually generated
+    pub fn samples(&self) -> usize {
+        self.resolution.max(MIN_RESOLUTION)
+    }
+
+    /// The heights of the terrain, row by row, between 0.0 and 1.0
+    pub fn heights(&self) -> Vec<f32> {
+        let mut rng = SplitMix64::new(self.seed);
+        let noise = Noise::new(&mut rng);
+        let n = self.samples();
+
+        let mut heights = Vec::with_capacity(n * n);
+        for j in 0..n {
+            for i in 0..n {
+                let (x, y) = (i as f32 / n as f32, j as f32 / n as f32);
+                let (mut frequency, mut amplitude, mut height) = (self.frequency, 1.0, 0.0);
+                for _ in 0..self.octaves {
+                    let value = noise.sample(x * frequency, y * frequency);
+                    height += amplitude
+                        * match self.noise {
+                            NoiseKind::Fbm => value,
+                            NoiseKind::Ridged => (1.0 - value.abs()).powi(2),
+                        };
+                    frequency *= self.lacunarity;
+                    amplitude *= self.gain;
+                }
+                heights.push(height);
+            }
+        }
+        normalize(&mut heights);
+
+        // Small craters are a lot more common than large ones
+        let (min_radius, max_radius) = self.crater_radius;
+        for _ in 0..self.crater_count {
+            let center = (rng.next_f32(), rng.next_f32());
+            let radius = min_radius + (max_radius - min_radius) * rng.next_f32().powi(3);
+            let depth = self.crater_depth * radius / max_radius;
+
+            // Only the samples within reach of the rim are affected
+            let reach = radius * 2.0;
+            let to_index = |t: f32| ((t * n as f32).max(0.0) as usize).min(n - 1);
+            for j in to_index(center.1 - reach)..=to_index(center.1 + reach) {
+                for i in to_index(center.0 - reach)..=to_index(center.0 + reach) {
+                    let (x, y) = (i as f32 / n as f32, j as f32 / n as f32);
+                    let r = ((x - center.0).powi(2) + (y - center.1).powi(2)).sqrt() / radius;
+                    heights[j * n + i] += crater_profile(r, depth);
+                }
+            }
+        }
+        normalize(&mut heights);
+
+        heights
+    }
+}
+
+// internal helper, scales the heights to the range [0, 1]
+fn normalize(heights: &mut [f32]) {
+    let min = heights.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let range = if max > min { max - min } else { 1.0 };
+    for h in heights.iter_mut() {
+        *h = (*h - min) / range;
+    }
+}
+
+impl Terrain {
+    /// Generates a cratered lunar landscape from fractal noise
+    pub fn generate(generator: &TerrainGenerator) -> Mesh {
+        println!("Generating terrain with seed {}...", generator.seed);
+        let before = std::time::Instant::now();
+        let terrain = Mesh::from_heights(
+            &generator.heights(),
+            generator.samples(),
+            generator.samples(),
+            &generator.heightmap,
+        )
+        .expect("The generator makes enough heights for its samples");
+        let after = std::time::Instant::now();
+        println!(
+            "Generated terrain with {} triangles in {:.3}ms.",
+            terrain.indices.len() / 3,
+            after.duration_since(before).as_micros() as f32 / 1e3
+        );
+        terrain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_generator(seed: u64) -> TerrainGenerator {
+        TerrainGenerator {
+            seed,
+            resolution: 32,
+            crater_count: 8,
+            ..TerrainGenerator::default()
+        }
+    }
+
+    #[test]
+    fn splitmix_matches_the_reference_sequence() {
+        let mut rng = SplitMix64::new(1234567);
+        let first: Vec<u64> = (0..5).map(|_| rng.next_u64()).collect();
+        assert_eq!(
+            first,
+            [
+                6457827717110365317,
+                3203168211198807973,
+                9817491932198370423,
+                4593380528125082431,
+                16408922859458223821,
+            ]
+        );
+    }
+
+    #[test]
+    fn same_seed_same_terrain() {
+        let a = Terrain::generate(&small_generator(7));
+        let b = Terrain::generate(&small_generator(7));
+        assert_eq!(a.vertices, b.vertices);
+        assert_eq!(a.normals, b.normals);
+        assert_eq!(a.colors, b.colors);
+        assert_eq!(a.indices, b.indices);
+        assert_ne!(small_generator(7).heights(), small_generator(8).heights());
+    }
+
+    #[test]
+    fn tiny_resolutions_still_give_a_terrain() {
+        for resolution in [0, 1, 2] {
+            let generator = TerrainGenerator {
+                resolution,
+                ..small_generator(3)
+            };
+            assert_eq!(generator.samples(), 2);
+            assert_eq!(generator.heights().len(), 4);
+            let terrain = Terrain::generate(&generator);
+            assert_eq!(terrain.vertices.len(), 4 * 3);
+            assert_eq!(terrain.indices.len(), 2 * 3);
+            assert!(terrain.vertices.iter().all(|x| x.is_finite()));
+        }
+    }
+
+    #[test]
+    fn heights_match_the_stored_fingerprint() {
+        let heights = small_generator(42).heights();
+        assert_eq!(heights.len(), 32 * 32);
+        let sum: f32 = heights.iter().sum();
+        let samples = [heights[0], heights[100], heights[517], heights[1023]];
+        // Taken from a run, allowing for rounding differences in the float functions
+        let expected = [0.553_445, 0.315_645_9, 0.385_769_6, 0.507_411_1];
+        assert!((sum - 562.185_7).abs() < 1e-2, "sum {}", sum);
+        for (sample, expected) in samples.iter().zip(&expected) {
+            assert!((sample - expected).abs() < 1e-5, "{:?}", samples);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::{Bvh, Mesh, Ray};
+use nalgebra_glm as glm;
+
+/// The ground at some point of a terrain
+#[derive(Clone, Copy, Debug)]
+pub struct GroundSample {
+    pub height: f32,
+    /// The interpolated vertex normal, or the face normal if the mesh has none. Always points up.
+    pub normal: glm::Vec3,
+}
+
+/// Answers height and normal queries at any (x, z) of a terrain mesh, using a BVH over its triangles.
+/// Where the terrain overhangs, the topmost surface is used.
+pub struct TerrainSampler {
+    bvh: Bvh,
+    vertices: Vec<f32>,
+    normals: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+impl TerrainSampler {
+    pub fn new(terrain: &Mesh) -> Self {
+        println!("Building terrain sampler...");
+        let before = std::time::Instant::now();
+        let bvh = terrain.build_bvh();
+        let after = std::time::Instant::now();
+        println!(
+            "Done in {:.3}ms.",
+            after.duration_since(before).as_micros() as f32 / 1e3
+        );
+
+        TerrainSampler {
+            bvh,
+            vertices: terrain.vertices.clone(),
+            normals: if terrain.has_normals() {
+                terrain.normals.clone()
+            } else {
+                Vec::new()
+            },
+            indices: terrain.indices.clone(),
+        }
+    }
+
+    /// The ground straight below or above (x, z), or `None` outside of the terrain
+    pub fn sample(&self, x: f32, z: f32) -> Option<GroundSample> {
+        let aabb = self.bvh.aabb();
+        let top = aabb.max.y + 1.0;
+        let ray = Ray::new(glm::vec3(x, top, z), glm::vec3(0.0, -1.0, 0.0));
+        let hit = self.bvh.intersect_ray(&ray, top - aabb.min.y + 1.0)?;
+
+        let corners = &self.indices[hit.triangle * 3..hit.triangle * 3 + 3];
+        let attribute = |data: &[f32], i: u32| {
+            let i = i as usize * 3;
+            glm::vec3(data[i], data[i + 1], data[i + 2])
+        };
+        let normal = if self.normals.is_empty() {
+            let [a, b, c] = [0, 1, 2].map(|k| attribute(&self.vertices, corners[k]));
+            (b - a).cross(&(c - a))
+        } else {
+            (0..3).fold(glm::Vec3::zeros(), |sum, k| {
+                sum + attribute(&self.normals, corners[k]) * hit.barycentric[k]
+            })
+        };
+        let normal = if normal.y < 0.0 { -normal } else { normal };
+
+        Some(GroundSample {
+            height: hit.point.y,
+            normal: if normal.norm() > 0.0 {
+                normal.normalize()
+            } else {
+                glm::vec3(0.0, 1.0, 0.0)
+            },
+        })
+    }
+
+    /// The height of the ground at (x, z), or `None` outside of the terrain
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.sample(x, z).map(|ground| ground.height)
+    }
+
+    /// The normal of the ground at (x, z), or `None` outside of the terrain
+    #[allow(dead_code)]
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<glm::Vec3> {
+        self.sample(x, z).map(|ground| ground.normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Shading;
+
+    const SLOPE: f32 = 0.5;
+
+    // internal helper, a 20 by 20 plane rising along X
+    fn slope() -> Mesh {
+        let mut plane = Mesh::plane(20.0, 20.0, 8, 8, [1.0; 4]);
+        for p in plane.vertices.chunks_exact_mut(3) {
+            p[1] = SLOPE * p[0] + 3.0;
+        }
+        plane.update_bounds();
+        plane.generate_normals(Shading::default());
+        plane
+    }
+
+    // internal helper, points inside the plane, a few of them on the lines of its grid
+    fn inside() -> Vec<(f32, f32)> {
+        (0..7)
+            .flat_map(|i| (0..7).map(move |j| (i as f32 * 2.9 - 9.1, j as f32 * 2.7 - 8.3)))
+            .collect()
+    }
+
+    #[test]
+    fn samples_follow_the_slope() {
+        let expected_normal = glm::normalize(&glm::vec3(-SLOPE, 1.0, 0.0));
+        let mut without_normals = slope();
+        without_normals.normals.clear();
+
+        for terrain in [slope(), without_normals] {
+            let sampler = TerrainSampler::new(&terrain);
+            for (x, z) in inside() {
+                let ground = sampler.sample(x, z).unwrap();
+                assert!(
+                    (ground.height - (SLOPE * x + 3.0)).abs() < 1e-4,
+                    "{} {}",
+                    x,
+                    z
+                );
+                assert!(glm::distance(&ground.normal, &expected_normal) < 1e-4);
+                assert_eq!(sampler.height_at(x, z), Some(ground.height));
+                assert_eq!(sampler.normal_at(x, z), Some(ground.normal));
+            }
+        }
+    }
+
+    #[test]
+    fn normals_point_up() {
+        // The same slope, wound the other way round so its faces point down
+        let mut terrain = slope();
+        for tri in terrain.indices.chunks_exact_mut(3) {
+            tri.swap(1, 2);
+        }
+        terrain.normals.clear();
+        let sampler = TerrainSampler::new(&terrain);
+        let normal = sampler.normal_at(1.0, 1.0).unwrap();
+        assert!(glm::distance(&normal, &glm::normalize(&glm::vec3(-SLOPE, 1.0, 0.0))) < 1e-4);
+    }
+
+    #[test]
+    fn outside_is_none() {
+        let sampler = TerrainSampler::new(&slope());
+        for (x, z) in [
+            (10.5, 0.0),
+            (-10.5, 0.0),
+            (0.0, 10.5),
+            (0.0, -10.5),
+            (30.0, 30.0),
+        ] {
+            assert!(sampler.sample(x, z).is_none(), "{} {}", x, z);
+            assert_eq!(sampler.height_at(x, z), None);
+            assert_eq!(sampler.normal_at(x, z), None);
+        }
+    }
+}
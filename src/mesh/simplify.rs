@@ -0,0 +1,601 @@
+use super::{BoundingSphere, Bounds, Mesh};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// Mesh simplification by quadric error metrics (Garland and Heckbert 1997), using half-edge
+// collapses so every remaining vertex keeps its original attributes. Vertices on borders,
+// non-manifold edges and attribute seams (several vertices sharing a position) never move,
+// which keeps holes, the edges of terrain tiles and the UV and normal splits in place.
+
+// How many pixels of error a level of detail may show on screen before a finer one is used
+const MAX_PIXEL_ERROR: f32 = 1.0;
+
+// internal helper, a symmetric 4x4 matrix stored as its upper triangle
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // The squared distance to the plane through `p` with unit normal `n`
+    fn plane(n: [f64; 3], p: [f64; 3]) -> Self {
+        let [a, b, c] = n;
+        let d = -(a * p[0] + b * p[1] + c * p[2]);
+        Quadric([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.0.iter_mut().zip(&other.0) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, p: [f64; 3]) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = p;
+        let error = q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9];
+        error.max(0.0)
+    }
+}
+
+// internal helper, a possible collapse of `from` onto `to` in the priority queue
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    // The versions of both vertices when the collapse was queued, it is stale if they changed
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed, so the cheapest collapse is on top of the heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+// internal helper, the working state of one simplification
+struct Simplifier {
+    positions: Vec<[f64; 3]>,
+    indices: Vec<u32>,
+    alive: Vec<bool>,
+    // The triangles using each vertex, including some that died since
+    triangles_of: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    locked: Vec<bool>,
+    // Seam vertices can't be collapsed onto either, as the other copies would stay behind
+    seam: Vec<bool>,
+    versions: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(vertices: &[f32], indices: &[u32]) -> Self {
+        let num_verts = vertices.len() / 3;
+        let positions: Vec<[f64; 3]> = vertices
+            .chunks_exact(3)
+            .map(|p| [p[0] as f64, p[1] as f64, p[2] as f64])
+            .collect();
+
+        // Vertices sharing a position, like the two sides of a UV seam, are one corner of the surface
+        let mut corners = HashMap::new();
+        let mut copies = vec![0u32; num_verts];
+        let corner: Vec<u32> = (0..num_verts)
+            .map(|v| {
+                let key = [0, 1, 2].map(|k| vertices[v * 3 + k].to_bits());
+                let next = corners.len() as u32;
+                *corners.entry(key).or_insert(next)
+            })
+            .collect();
+        let mut used = vec![false; num_verts];
+        for &v in indices {
+            used[v as usize] = true;
+        }
+        for v in (0..num_verts).filter(|&v| used[v]) {
+            copies[corner[v] as usize] += 1;
+        }
+        let seam: Vec<bool> = (0..num_verts)
+            .map(|v| copies[corner[v] as usize] > 1)
+            .collect();
+
+        // Edges with one triangle are borders, edges with more than two are non-manifold
+        let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+        for tri in indices.chunks_exact(3) {
+            for k in 0..3 {
+                let (a, b) = (corner[tri[k] as usize], corner[tri[(k + 1) % 3] as usize]);
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+        let mut locked_corner = vec![false; corners.len()];
+        for (&(a, b), &count) in &edges {
+            if count != 2 {
+                locked_corner[a as usize] = true;
+                locked_corner[b as usize] = true;
+            }
+        }
+        let locked: Vec<bool> = (0..num_verts)
+            .map(|v| seam[v] || locked_corner[corner[v] as usize])
+            .collect();
+
+        let mut triangles_of = vec![Vec::new(); num_verts];
+        let mut quadrics = vec![Quadric::default(); num_verts];
+        for (t, tri) in indices.chunks_exact(3).enumerate() {
+            for &v in tri {
+                triangles_of[v as usize].push(t);
+            }
+            if let Some(n) = normal(&positions, tri) {
+                let plane = Quadric::plane(n, positions[tri[0] as usize]);
+                for &v in tri {
+                    quadrics[v as usize].add(&plane);
+                }
+            }
+        }
+
+        let mut simplifier = Simplifier {
+            positions,
+            alive: vec![true; indices.len() / 3],
+            indices: indices.to_vec(),
+            triangles_of,
+            quadrics,
+            locked,
+            seam,
+            versions: vec![0; num_verts],
+            heap: BinaryHeap::new(),
+        };
+        for tri in indices.chunks_exact(3) {
+            for k in 0..3 {
+                simplifier.queue(tri[k], tri[(k + 1) % 3]);
+                simplifier.queue(tri[(k + 1) % 3], tri[k]);
+            }
+        }
+        simplifier
+    }
+
+    // Queues the collapse of `from` onto `to`, if it is allowed at all
+    fn queue(&mut self, from: u32, to: u32) {
+        if self.locked[from as usize] || self.seam[to as usize] || from == to {
+            return;
+        }
+        let mut quadric = self.quadrics[from as usize];
+        quadric.add(&self.quadrics[to as usize]);
+        self.heap.push(Collapse {
+            cost: quadric.error(self.positions[to as usize]),
+            from,
+            to,
+            versions: (self.versions[from as usize], self.versions[to as usize]),
+        });
+    }
+
+    // The vertices sharing a live triangle with `v`
+    fn neighbours(&self, v: u32) -> Vec<u32> {
+        let mut neighbours: Vec<u32> = self.triangles_of[v as usize]
+            .iter()
+            .filter(|&&t| self.alive[t])
+            .flat_map(|&t| self.indices[t * 3..t * 3 + 3].iter().cloned())
+            .filter(|&w| w != v)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    // True if the collapse keeps the surface manifold and flips no triangles
+    fn is_valid(&self, from: u32, to: u32) -> bool {
+        // The link condition: an interior edge is shared by exactly two triangles, whose
+        // third corners must be the only common neighbours of its ends
+        let (a, b) = (self.neighbours(from), self.neighbours(to));
+        let common = a.iter().filter(|w| b.binary_search(w).is_ok()).count();
+        if common != 2 {
+            return false;
+        }
+
+        for &t in &self.triangles_of[from as usize] {
+            let tri = &self.indices[t * 3..t * 3 + 3];
+            if !self.alive[t] || tri.contains(&to) {
+                continue;
+            }
+            let moved: Vec<u32> = tri
+                .iter()
+                .map(|&v| if v == from { to } else { v })
+                .collect();
+            match (
+                normal(&self.positions, tri),
+                normal(&self.positions, &moved),
+            ) {
+                (Some(before), Some(after)) if dot(before, after) > 0.0 => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    // Moves `from` onto `to`, returning how many triangles were removed
+    fn collapse(&mut self, from: u32, to: u32) -> usize {
+        let mut removed = 0;
+        let triangles = std::mem::take(&mut self.triangles_of[from as usize]);
+        for t in triangles {
+            if !self.alive[t] {
+                continue;
+            }
+            let tri = &mut self.indices[t * 3..t * 3 + 3];
+            if tri.contains(&to) {
+                self.alive[t] = false;
+                removed += 1;
+            } else {
+                for v in tri.iter_mut().filter(|v| **v == from) {
+                    *v = to;
+                }
+                self.triangles_of[to as usize].push(t);
+            }
+        }
+        let quadric = self.quadrics[from as usize];
+        self.quadrics[to as usize].add(&quadric);
+        self.versions[from as usize] += 1;
+        self.versions[to as usize] += 1;
+
+        for w in self.neighbours(to) {
+            self.queue(w, to);
+            self.queue(to, w);
+        }
+        removed
+    }
+
+    // Collapses edges until there are `target_triangles` left, or the next would cost more than
+    // `max_error`. Returns the largest error of the collapses made.
+    fn run(&mut self, target_triangles: usize, max_error: f32) -> f32 {
+        let max_cost = (max_error as f64) * (max_error as f64);
+        let mut triangle_count = self.alive.iter().filter(|&&a| a).count();
+        let mut largest = 0.0f64;
+
+        while triangle_count > target_triangles {
+            let collapse = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let (from, to) = (collapse.from, collapse.to);
+            if collapse.versions != (self.versions[from as usize], self.versions[to as usize]) {
+                continue;
+            }
+            if collapse.cost > max_cost {
+                break;
+            }
+            if !self.is_valid(from, to) {
+                continue;
+            }
+            triangle_count -= self.collapse(from, to);
+            largest = largest.max(collapse.cost);
+        }
+        largest.sqrt() as f32
+    }
+}
+
+// internal helper, the unit normal of a triangle, or `None` if it has no area
+fn normal(positions: &[[f64; 3]], tri: &[u32]) -> Option<[f64; 3]> {
+    let [a, b, c] = [0, 1, 2].map(|k| positions[tri[k] as usize]);
+    let (u, v) = (sub(b, a), sub(c, a));
+    let n = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let length = dot(n, n).sqrt();
+    if length > 0.0 {
+        Some([n[0] / length, n[1] / length, n[2] / length])
+    } else {
+        None
+    }
+}
+
+// internal helper
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+// internal helper
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// One level of detail of a part of a mesh, as a range of the indices
+#[derive(Clone, Copy, Debug)]
+pub struct Lod {
+    pub first_index: i32,
+    pub index_count: i32,
+    /// How far, in world units, the surface may be from the full resolution one
+    pub error: f32,
+}
+
+/// The levels of detail of a part of a mesh, from the finest to the coarsest
+#[derive(Clone, Debug)]
+pub struct LodSet {
+    pub levels: Vec<Lod>,
+    /// Bounds of the full resolution part, to find how far away it is
+    pub sphere: BoundingSphere,
+}
+
+impl LodSet {
+    /// Picks the coarsest level whose error is at most a pixel on screen.
+    /// `distance` is from the camera to the part, `pixels_per_unit` is how many pixels one
+    /// world unit covers at a distance of one, i.e. the viewport height / (2 tan(fovy / 2)).
+    pub fn select(&self, distance: f32, pixels_per_unit: f32) -> &Lod {
+        let distance = distance.max(f32::EPSILON);
+        self.levels
+            .iter()
+            .rev()
+            .find(|lod| lod.error * pixels_per_unit / distance <= MAX_PIXEL_ERROR)
+            .unwrap_or(&self.levels[0])
+    }
+}
+
+/// A mesh with every level of detail in its indices, sharing the vertices of the finest level
+pub struct LodChain {
+    /// The sub-meshes are those of the finest level
+    pub mesh: Mesh,
+    /// One set per sub-mesh, or a single set if the mesh has no sub-meshes
+    pub sets: Vec<LodSet>,
+}
+
+#[allow(dead_code)]
+impl Mesh {
+    /// A simplified copy of the mesh with at most `target_triangles` triangles, unless that would
+    /// move the surface more than `max_error`. Borders and attribute seams are kept as they are.
+    /// Returns the mesh and the largest error actually made.
+    pub fn simplified(&self, target_triangles: usize, max_error: f32) -> (Mesh, f32) {
+        let mut simplifier = Simplifier::new(&self.vertices, &self.indices);
+        let error = simplifier.run(target_triangles, max_error);
+
+        let mut mesh = Mesh {
+            vertices: self.vertices.clone(),
+            normals: self.normals.clone(),
+            colors: self.colors.clone(),
+            texcoords: self.texcoords.clone(),
+            tangents: self.tangents.clone(),
+            indices: simplifier.indices,
+            index_count: self.index_count,
+            sub_meshes: self.sub_meshes.clone(),
+            material: self.material.clone(),
+            bounds: self.bounds,
+        };
+        mesh.retain_triangles(&simplifier.alive);
+        mesh.optimize_vertex_fetch();
+        (mesh, error)
+    }
+
+    /// Builds `levels` levels of detail, each with about `ratio` times the triangles of the one before.
+    /// Each sub-mesh is simplified on its own, so they can still be drawn separately.
+    pub fn lod_chain(&self, levels: usize, ratio: f32) -> LodChain {
+        let ranges = self.triangle_ranges();
+        let mut indices = self.indices.clone();
+        let mut sets: Vec<LodSet> = ranges
+            .iter()
+            .map(|range| {
+                let positions: Vec<f32> = self.indices[range.clone()]
+                    .iter()
+                    .flat_map(|&v| self.vertices[v as usize * 3..v as usize * 3 + 3].iter())
+                    .cloned()
+                    .collect();
+                LodSet {
+                    levels: vec![Lod {
+                        first_index: range.start as i32,
+                        index_count: range.len() as i32,
+                        error: 0.0,
+                    }],
+                    sphere: Bounds::from_positions(&positions).sphere,
+                }
+            })
+            .collect();
+
+        for set in &mut sets {
+            for _ in 1..levels {
+                let finer = *set.levels.last().unwrap();
+                let start = finer.first_index as usize;
+                let finer_indices = &indices[start..start + finer.index_count as usize];
+                let target = (finer_indices.len() / 3) as f32 * ratio;
+
+                let mut simplifier = Simplifier::new(&self.vertices, finer_indices);
+                let error = simplifier.run(target as usize, f32::INFINITY);
+                let coarser: Vec<u32> = simplifier
+                    .indices
+                    .chunks_exact(3)
+                    .zip(&simplifier.alive)
+                    .filter(|(_, &alive)| alive)
+                    .flat_map(|(tri, _)| tri.iter().cloned())
+                    .collect();
+                // Stop once the simplifier gets stuck on locked vertices
+                if coarser.len() == finer_indices.len() {
+                    break;
+                }
+
+                set.levels.push(Lod {
+                    first_index: indices.len() as i32,
+                    index_count: coarser.len() as i32,
+                    // Each level is simplified from the one before, so the errors add up
+                    error: finer.error + error,
+                });
+                indices.extend(coarser);
+            }
+        }
+
+        let mesh = Mesh {
+            vertices: self.vertices.clone(),
+            normals: self.normals.clone(),
+            colors: self.colors.clone(),
+            texcoords: self.texcoords.clone(),
+            tangents: self.tangents.clone(),
+            index_count: indices.len() as i32,
+            indices,
+            sub_meshes: self.sub_meshes.clone(),
+            material: self.material.clone(),
+            bounds: self.bounds,
+        };
+        LodChain { mesh, sets }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+    // internal helper, the positions of the vertices the triangles use
+    fn used_positions(mesh: &Mesh) -> HashSet<[u32; 3]> {
+        mesh.indices
+            .iter()
+            .map(|&v| [0, 1, 2].map(|k| mesh.vertices[v as usize * 3 + k].to_bits()))
+            .collect()
+    }
+
+    #[test]
+    fn simplified_reaches_the_target() {
+        let sphere = Mesh::icosphere(1.0, 4, COLOR);
+        assert_eq!(sphere.indices.len() / 3, 5120);
+
+        let (coarse, error) = sphere.simplified(500, f32::INFINITY);
+        assert!(coarse.indices.len() / 3 <= 500);
+        assert!(coarse.indices.len() / 3 > 400);
+        assert!(error > 0.0 && error < 0.2, "{}", error);
+        assert!(coarse.analyze().is_closed());
+        // The vertices that are left are where they were
+        assert!(used_positions(&coarse).is_subset(&used_positions(&sphere)));
+        assert_eq!(coarse.vertices.len() / 3, used_positions(&coarse).len());
+    }
+
+    #[test]
+    fn simplified_respects_the_max_error() {
+        let sphere = Mesh::icosphere(1.0, 4, COLOR);
+        let (_, unlimited) = sphere.simplified(500, f32::INFINITY);
+        let max_error = unlimited / 4.0;
+
+        let (limited, error) = sphere.simplified(500, max_error);
+        assert!(error <= max_error);
+        assert!(limited.indices.len() / 3 > 500);
+        assert!(limited.indices.len() < sphere.indices.len());
+        // Nothing is collapsed if even the cheapest collapse is too much
+        let (same, error) = sphere.simplified(500, 0.0);
+        assert_eq!(same.indices.len(), sphere.indices.len());
+        assert_eq!(error, 0.0);
+    }
+
+    #[test]
+    fn borders_never_move() {
+        let plane = Mesh::plane(2.0, 2.0, 16, 16, COLOR);
+        let border: HashSet<[u32; 3]> = plane
+            .vertices
+            .chunks_exact(3)
+            .filter(|p| p[0].abs() == 1.0 || p[2].abs() == 1.0)
+            .map(|p| [p[0].to_bits(), p[1].to_bits(), p[2].to_bits()])
+            .collect();
+        assert_eq!(border.len(), 16 * 4);
+
+        let (coarse, _) = plane.simplified(0, f32::INFINITY);
+        assert!(coarse.indices.len() < plane.indices.len() / 2);
+        // Every border vertex is still there, and hardly anything of the inside
+        let used = used_positions(&coarse);
+        assert!(border.is_subset(&used));
+        assert!(used.len() - border.len() < 8, "{}", used.len());
+    }
+
+    #[test]
+    fn uv_seams_never_move() {
+        let sphere = Mesh::uv_sphere(1.0, 32, 16, COLOR);
+        let seam: HashSet<[u32; 3]> = (0..sphere.vertices.len() / 3)
+            .filter(|&v| sphere.texcoords[v * 2] == 0.0 || sphere.texcoords[v * 2] == 1.0)
+            .filter(|&v| sphere.texcoords[v * 2 + 1] != 0.0 && sphere.texcoords[v * 2 + 1] != 1.0)
+            .map(|v| [0, 1, 2].map(|k| sphere.vertices[v * 3 + k].to_bits()))
+            .collect();
+        assert_eq!(seam.len(), 2 * 15);
+
+        let (coarse, _) = sphere.simplified(100, f32::INFINITY);
+        assert!(coarse.indices.len() < sphere.indices.len() / 4);
+        assert!(seam.is_subset(&used_positions(&coarse)));
+        // The sides of the seam keep their texture coordinates
+        for v in 0..coarse.vertices.len() / 3 {
+            let p = [0, 1, 2].map(|k| coarse.vertices[v * 3 + k].to_bits());
+            if seam.contains(&p) {
+                let u = coarse.texcoords[v * 2];
+                assert!(u == 0.0 || u == 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn farther_parts_get_coarser_levels() {
+        let chain = Mesh::icosphere(1.0, 4, COLOR).lod_chain(4, 0.5);
+        let set = &chain.sets[0];
+        assert_eq!(set.levels.len(), 4);
+        for pair in set.levels.windows(2) {
+            assert!(pair[1].index_count < pair[0].index_count);
+            assert!(pair[1].error > pair[0].error);
+        }
+
+        // The index of the selected level, by where it starts
+        let pixels_per_unit = 1000.0;
+        let level = |distance: f32| {
+            let lod = set.select(distance, pixels_per_unit);
+            set.levels
+                .iter()
+                .position(|l| l.first_index == lod.first_index)
+                .unwrap()
+        };
+        assert_eq!(level(0.0), 0);
+        assert_eq!(level(1e9), 3);
+        let mut previous = 0;
+        for step in 0..200 {
+            let selected = level(1.1f32.powi(step));
+            assert!(selected >= previous);
+            previous = selected;
+        }
+        assert_eq!(previous, 3);
+        // A level is picked once its error is at most a pixel away
+        let coarsest = set.levels[3];
+        assert_eq!(level(coarsest.error * pixels_per_unit * 1.01), 3);
+        assert!(level(coarsest.error * pixels_per_unit * 0.99) < 3);
+    }
+
+    #[test]
+    fn a_mesh_without_sub_meshes_gets_one_set() {
+        // Like the fallback terrain, which has to get a node of its own
+        let plane = Mesh::plane(400.0, 400.0, 8, 8, [0.5, 0.5, 0.5, 1.0]);
+        assert!(plane.sub_meshes.is_empty());
+        let chain = plane.lod_chain(3, 0.5);
+        assert_eq!(chain.sets.len(), 1);
+        let finest = chain.sets[0].levels[0];
+        assert_eq!(finest.first_index, 0);
+        assert_eq!(finest.index_count as usize, plane.indices.len());
+        assert!(chain.sets[0].levels.len() > 1);
+    }
+}
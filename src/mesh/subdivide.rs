@@ -0,0 +1,498 @@
+use super::{Mesh, Shading, SubMesh};
+use nalgebra_glm as glm;
+use std::collections::{HashMap, HashSet};
+
+// Subdivision surfaces, refining a mesh towards a smooth limit surface:
+// - Loop (1987) splits every triangle into four, Catmull-Clark (1978) splits every face into quads,
+//   so the triangles of the mesh turn into quads from the first level on
+// - the topology is built on corners, the positions shared by the vertices of a UV or normal seam,
+//   so seams don't tear. The vertices themselves are kept, and their colors and texture
+//   coordinates are interpolated linearly
+// - creases use the boundary rules: their new edge points are the midpoints, and their vertices
+//   only follow the crease. A corner on three or more creases stays where it is, and so does one
+//   where a crease turns by more than the crease angle, like the corners of a plane
+// - creases are the borders, the non-manifold edges and the edges whose faces meet at a sharper
+//   angle than the crease angle, and stay creases at every level
+
+/// The most levels `Mesh::subdivide` goes to. Every level makes four times as many triangles,
+/// except the first Catmull-Clark level on a triangle mesh, which makes six times as many.
+pub const MAX_SUBDIVISION_LEVELS: u32 = 4;
+
+/// The subdivision scheme used by `Mesh::subdivide`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subdivision {
+    /// Splits every triangle into four
+    Loop,
+    /// Splits every face into quads, each drawn as two triangles
+    CatmullClark,
+}
+
+// internal helper, an edge between two corners
+fn edge(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+// internal helper, the average of some points
+fn average<'a>(points: impl ExactSizeIterator<Item = &'a glm::Vec3>) -> glm::Vec3 {
+    let count = points.len().max(1) as f32;
+    points.sum::<glm::Vec3>() / count
+}
+
+// internal helper, the weight of the neighbours of a smooth vertex in the Loop scheme
+fn loop_beta(valence: usize) -> f32 {
+    let n = valence as f32;
+    let c = 3.0 / 8.0 + (2.0 * std::f32::consts::PI / n).cos() / 4.0;
+    (5.0 / 8.0 - c * c) / n
+}
+
+// internal helper, a surface being subdivided, made of polygons
+struct Surface {
+    // The position of each corner
+    positions: Vec<glm::Vec3>,
+    corner_of: Vec<u32>,
+    // The interpolated attributes of the vertices, with their width
+    attributes: Vec<(Vec<f32>, usize)>,
+    faces: Vec<Vec<u32>>,
+    // The triangle of the original mesh each face was split from
+    origins: Vec<usize>,
+    creases: HashSet<(u32, u32)>,
+    // Crease vertices turning by more than the crease angle are corners
+    cos_crease: f32,
+}
+
+impl Surface {
+    fn new(mesh: &Mesh, cos_crease: f32) -> Self {
+        let num_verts = mesh.vertices.len() / 3;
+        let mut corners = HashMap::new();
+        let mut positions = Vec::new();
+        let corner_of: Vec<u32> = mesh
+            .vertices
+            .chunks_exact(3)
+            .map(|p| {
+                // Adding zero turns -0.0 into 0.0, so they weld together
+                let key = [0, 1, 2].map(|k| (p[k] + 0.0).to_bits());
+                *corners.entry(key).or_insert_with(|| {
+                    positions.push(glm::vec3(p[0], p[1], p[2]));
+                    positions.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        let faces: Vec<Vec<u32>> = mesh.indices.chunks_exact(3).map(|t| t.to_vec()).collect();
+        let face_normals: Vec<glm::Vec3> = faces
+            .iter()
+            .map(|face| {
+                let p = [0, 1, 2].map(|k| positions[corner_of[face[k] as usize] as usize]);
+                let n = (p[1] - p[0]).cross(&(p[2] - p[0]));
+                if n.norm() > 0.0 {
+                    n.normalize()
+                } else {
+                    n
+                }
+            })
+            .collect();
+
+        let mut surface = Surface {
+            positions,
+            corner_of,
+            attributes: [(&mesh.colors, 4), (&mesh.texcoords, 2)]
+                .iter()
+                .filter(|(data, width)| data.len() == num_verts * width)
+                .map(|&(data, width)| (data.clone(), width))
+                .collect(),
+            origins: (0..faces.len()).collect(),
+            faces,
+            creases: HashSet::new(),
+            cos_crease,
+        };
+        for (e, faces) in surface.edge_faces() {
+            if let [a, b] = faces[..] {
+                if face_normals[a].dot(&face_normals[b]) < cos_crease {
+                    surface.creases.insert(e);
+                }
+            }
+        }
+        surface
+    }
+
+    // The faces around each edge, in the order the edges are first met
+    fn edge_faces(&self) -> Vec<((u32, u32), Vec<usize>)> {
+        let mut edges: Vec<((u32, u32), Vec<usize>)> = Vec::new();
+        let mut index = HashMap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for k in 0..face.len() {
+                let (a, b) = (face[k], face[(k + 1) % face.len()]);
+                let e = edge(self.corner_of[a as usize], self.corner_of[b as usize]);
+                let i = *index.entry(e).or_insert_with(|| {
+                    edges.push((e, Vec::new()));
+                    edges.len() - 1
+                });
+                edges[i].1.push(f);
+            }
+        }
+        edges
+    }
+
+    // Whether the crease through `a`, the corner `c` and `b` turns too sharply at `c` to be smoothed
+    fn is_corner(&self, c: usize, a: u32, b: u32) -> bool {
+        let p = self.positions[c];
+        let (incoming, outgoing) = (
+            p - self.positions[a as usize],
+            self.positions[b as usize] - p,
+        );
+        let lengths = incoming.norm() * outgoing.norm();
+        lengths > 0.0 && incoming.dot(&outgoing) < self.cos_crease * lengths
+    }
+
+    fn subdivide(&mut self, scheme: Subdivision) {
+        debug_assert!(
+            scheme == Subdivision::CatmullClark || self.faces.iter().all(|f| f.len() == 3)
+        );
+        let num_corners = self.positions.len();
+        let corner = |v: u32| self.corner_of[v as usize];
+        let face_points: Vec<glm::Vec3> = self
+            .faces
+            .iter()
+            .map(|face| average(face.iter().map(|&v| &self.positions[corner(v) as usize])))
+            .collect();
+
+        let edges = self.edge_faces();
+        let is_crease =
+            |(e, faces): &((u32, u32), Vec<usize>)| faces.len() != 2 || self.creases.contains(e);
+
+        let mut neighbours = vec![Vec::new(); num_corners];
+        let mut crease_neighbours = vec![Vec::new(); num_corners];
+        for edge in &edges {
+            let (a, b) = edge.0;
+            neighbours[a as usize].push(b);
+            neighbours[b as usize].push(a);
+            if is_crease(edge) {
+                crease_neighbours[a as usize].push(b);
+                crease_neighbours[b as usize].push(a);
+            }
+        }
+        let mut faces_of = vec![Vec::new(); num_corners];
+        for (f, face) in self.faces.iter().enumerate() {
+            for &v in face {
+                faces_of[corner(v) as usize].push(f);
+            }
+        }
+
+        // The old corners move first, then come the edge points and the Catmull-Clark face points
+        let mut positions: Vec<glm::Vec3> = (0..num_corners)
+            .map(|c| {
+                let p = self.positions[c];
+                let n = neighbours[c].len();
+                let q = |&other: &u32| &self.positions[other as usize];
+                match crease_neighbours[c][..] {
+                    [a, b] if !self.is_corner(c, a, b) => {
+                        p * 0.75 + (self.positions[a as usize] + self.positions[b as usize]) / 8.0
+                    }
+                    [] | [_] if n > 0 => match scheme {
+                        Subdivision::Loop => {
+                            let beta = loop_beta(n);
+                            p * (1.0 - n as f32 * beta)
+                                + neighbours[c].iter().map(q).sum::<glm::Vec3>() * beta
+                        }
+                        Subdivision::CatmullClark => {
+                            let f = average(faces_of[c].iter().map(|&f| &face_points[f]));
+                            let r = (p + average(neighbours[c].iter().map(q))) / 2.0;
+                            (f + r * 2.0 + p * (n as f32 - 3.0)) / n as f32
+                        }
+                    },
+                    _ => p,
+                }
+            })
+            .collect();
+
+        let mut edge_corners = HashMap::with_capacity(edges.len());
+        for edge in &edges {
+            let ((a, b), faces) = edge;
+            let (pa, pb) = (self.positions[*a as usize], self.positions[*b as usize]);
+            let point = if is_crease(edge) {
+                (pa + pb) / 2.0
+            } else {
+                match scheme {
+                    Subdivision::Loop => {
+                        let opposite: glm::Vec3 = faces
+                            .iter()
+                            .flat_map(|&f| self.faces[f].iter())
+                            .map(|&v| corner(v))
+                            .filter(|c| c != a && c != b)
+                            .map(|c| self.positions[c as usize])
+                            .sum();
+                        (pa + pb) * 3.0 / 8.0 + opposite / 8.0
+                    }
+                    Subdivision::CatmullClark => {
+                        (pa + pb + face_points[faces[0]] + face_points[faces[1]]) / 4.0
+                    }
+                }
+            };
+            positions.push(point);
+            edge_corners.insert(edge.0, positions.len() as u32 - 1);
+        }
+
+        // The old vertices are kept, each edge between two of them gets a new vertex,
+        // and for Catmull-Clark so does each face
+        let num_verts = self.corner_of.len();
+        let mut corner_of = self.corner_of.clone();
+        let mut parents: Vec<Vec<u32>> = (0..num_verts as u32).map(|v| vec![v]).collect();
+        let mut edge_vertices: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut faces = Vec::with_capacity(self.faces.len() * 4);
+        let mut origins = Vec::with_capacity(self.faces.len() * 4);
+
+        for (f, (face, &origin)) in self.faces.iter().zip(&self.origins).enumerate() {
+            let n = face.len();
+            let mut mids = Vec::with_capacity(n);
+            for k in 0..n {
+                let (a, b) = (face[k], face[(k + 1) % n]);
+                let vertex = *edge_vertices.entry(edge(a, b)).or_insert_with(|| {
+                    corner_of.push(edge_corners[&edge(corner(a), corner(b))]);
+                    parents.push(vec![a, b]);
+                    corner_of.len() as u32 - 1
+                });
+                mids.push(vertex);
+            }
+            match scheme {
+                Subdivision::Loop => {
+                    let [a, b, c] = [face[0], face[1], face[2]];
+                    let [ab, bc, ca] = [mids[0], mids[1], mids[2]];
+                    faces.extend([
+                        vec![a, ab, ca],
+                        vec![ab, b, bc],
+                        vec![ca, bc, c],
+                        vec![ab, bc, ca],
+                    ]);
+                }
+                Subdivision::CatmullClark => {
+                    positions.push(face_points[f]);
+                    corner_of.push(positions.len() as u32 - 1);
+                    parents.push(face.clone());
+                    let center = corner_of.len() as u32 - 1;
+                    for k in 0..n {
+                        faces.push(vec![face[k], mids[k], center, mids[(k + n - 1) % n]]);
+                    }
+                }
+            }
+            origins.resize(faces.len(), origin);
+        }
+
+        let crease_corners = |(a, b): (u32, u32)| {
+            let e = edge_corners[&(a, b)];
+            [edge(a, e), edge(e, b)]
+        };
+        self.creases = edges
+            .iter()
+            .filter(|edge| is_crease(edge))
+            .flat_map(|edge| crease_corners(edge.0))
+            .collect();
+        self.positions = positions;
+        self.corner_of = corner_of;
+        for (data, width) in &mut self.attributes {
+            let (old, width) = (std::mem::take(data), *width);
+            *data = parents
+                .iter()
+                .flat_map(|parents| {
+                    let old = &old;
+                    (0..width).map(move |k| {
+                        let sum: f32 = parents.iter().map(|&v| old[v as usize * width + k]).sum();
+                        sum / parents.len() as f32
+                    })
+                })
+                .collect();
+        }
+        self.faces = faces;
+        self.origins = origins;
+    }
+}
+
+impl Mesh {
+    /// Subdivides the mesh `levels` times, smoothing it towards the limit surface of `scheme`.
+    /// Borders, non-manifold edges and edges whose faces meet at a sharper angle than
+    /// `crease_angle` (in radians) stay sharp. Colors and texture coordinates are interpolated,
+    /// the normals are recomputed with the same crease angle, and tangents too if the mesh had them.
+    /// At most `MAX_SUBDIVISION_LEVELS` levels are applied.
+    pub fn subdivide(&mut self, scheme: Subdivision, levels: u32, crease_angle: f32) {
+        let levels = levels.min(MAX_SUBDIVISION_LEVELS);
+        if levels == 0 || self.indices.len() < 3 {
+            return;
+        }
+        let had_tangents = self.has_tangents();
+        let num_tris = self.indices.len() / 3;
+        let mut surface = Surface::new(self, crease_angle.cos());
+        for _ in 0..levels {
+            surface.subdivide(scheme);
+        }
+
+        // The faces are split in order, so every original triangle becomes a run of new triangles
+        let mut first_triangle = vec![0; num_tris + 1];
+        self.indices = Vec::with_capacity(surface.faces.len() * 6);
+        for (face, &origin) in surface.faces.iter().zip(&surface.origins) {
+            for k in 1..face.len() - 1 {
+                self.indices.extend([face[0], face[k], face[k + 1]]);
+            }
+            first_triangle[origin + 1] = self.indices.len() / 3;
+        }
+        let first_index =
+            |index: i32| first_triangle[(index.max(0) as usize / 3).min(num_tris)] as i32 * 3;
+        self.sub_meshes = std::mem::take(&mut self.sub_meshes)
+            .into_iter()
+            .map(|sub_mesh| SubMesh {
+                first_index: first_index(sub_mesh.first_index),
+                index_count: first_index(sub_mesh.first_index + sub_mesh.index_count)
+                    - first_index(sub_mesh.first_index),
+                ..sub_mesh
+            })
+            .collect();
+        self.index_count = self.indices.len() as i32;
+
+        self.vertices = surface
+            .corner_of
+            .iter()
+            .flat_map(|&c| {
+                let p = surface.positions[c as usize];
+                vec![p.x, p.y, p.z]
+            })
+            .collect();
+        let num_verts = surface.corner_of.len();
+        self.colors = Vec::new();
+        self.texcoords = Vec::new();
+        for (data, width) in surface.attributes {
+            match width {
+                4 => self.colors = data,
+                _ => self.texcoords = data,
+            }
+        }
+        if self.colors.len() != num_verts * 4 {
+            self.colors = super::generate_color_vec(self.material.color(), num_verts);
+        }
+        self.update_bounds();
+
+        self.generate_normals(Shading::Smooth { crease_angle });
+        if had_tangents {
+            self.generate_tangents();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_capped() {
+        let mut cube = Mesh::cube(2.0, 1, [1.0; 4]);
+        cube.subdivide(Subdivision::Loop, 100, std::f32::consts::PI);
+        assert_eq!(
+            cube.indices.len() / 3,
+            12 * 4usize.pow(MAX_SUBDIVISION_LEVELS)
+        );
+        assert_eq!(cube.index_count as usize, cube.indices.len());
+
+        // The first Catmull-Clark level splits each triangle into three quads
+        let mut cube = Mesh::cube(2.0, 1, [1.0; 4]);
+        cube.subdivide(Subdivision::CatmullClark, 100, std::f32::consts::PI);
+        assert_eq!(
+            cube.indices.len() / 3,
+            12 * 6 * 4usize.pow(MAX_SUBDIVISION_LEVELS - 1)
+        );
+    }
+
+    // internal helper, the distinct positions of a mesh
+    fn points(mesh: &Mesh) -> Vec<glm::Vec3> {
+        let mut points: Vec<glm::Vec3> = Vec::new();
+        for p in mesh.vertices.chunks_exact(3) {
+            let p = glm::vec3(p[0], p[1], p[2]);
+            if points.iter().all(|q| glm::distance(q, &p) > 1e-5) {
+                points.push(p);
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn sharp_cubes_stay_cubes() {
+        let mut cube = Mesh::cube(2.0, 1, [1.0; 4]);
+        cube.subdivide(Subdivision::Loop, 2, std::f32::consts::FRAC_PI_3);
+
+        let points = points(&cube);
+        let on_edges = |p: &&glm::Vec3| p.iter().filter(|x| (x.abs() - 1.0).abs() < 1e-5).count();
+        for p in &points {
+            // On the surface, and every face is still flat
+            assert!(p.iter().all(|x| x.abs() <= 1.0 + 1e-5), "{}", p);
+            assert!(on_edges(&p) >= 1, "{}", p);
+        }
+        // The corners stay, and the points on the edges are still on them
+        assert_eq!(points.iter().filter(|p| on_edges(p) == 3).count(), 8);
+        assert_eq!(points.iter().filter(|p| on_edges(p) == 2).count(), 12 * 3);
+        // The normals keep to the faces
+        for (n, p) in cube
+            .normals
+            .chunks_exact(3)
+            .zip(cube.vertices.chunks_exact(3))
+        {
+            let axis = (0..3).find(|&k| (n[k].abs() - 1.0).abs() < 1e-5).unwrap();
+            assert_eq!(n[axis].signum(), p[axis].signum());
+            assert!((p[axis].abs() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn plane_borders_stay_on_their_lines() {
+        let mut plane = Mesh::plane(2.0, 2.0, 4, 4, [1.0; 4]);
+        plane.subdivide(Subdivision::Loop, 2, std::f32::consts::FRAC_PI_3);
+
+        let points = points(&plane);
+        let border: Vec<&glm::Vec3> = points
+            .iter()
+            .filter(|p| (p.x.abs() - 1.0).abs() < 1e-5 || (p.z.abs() - 1.0).abs() < 1e-5)
+            .collect();
+        // Every side keeps its corners and is split into 16
+        assert_eq!(border.len(), 4 * 16);
+        assert!(points.iter().all(|p| p.y == 0.0));
+        assert!(points
+            .iter()
+            .all(|p| p.x.abs() <= 1.0 + 1e-5 && p.z.abs() <= 1.0 + 1e-5));
+        assert_eq!(plane.bounds.aabb.min, glm::vec3(-1.0, 0.0, -1.0));
+        assert_eq!(plane.bounds.aabb.max, glm::vec3(1.0, 0.0, 1.0));
+        assert_eq!(plane.analyze().boundary_edges, 4 * 16);
+    }
+
+    #[test]
+    fn uv_seams_dont_tear() {
+        // Every face of the cube has vertices of its own, with their own texture coordinates
+        let mut cube = Mesh::cube(2.0, 2, [1.0; 4]);
+        assert_eq!(cube.vertices.len() / 3, 6 * 9);
+        assert!(cube.analyze().is_closed());
+        for scheme in [Subdivision::Loop, Subdivision::CatmullClark] {
+            let mut smooth = Mesh::cube(2.0, 2, [1.0; 4]);
+            smooth.subdivide(scheme, 2, std::f32::consts::PI);
+            let analysis = smooth.analyze();
+            assert_eq!(analysis.boundary_edges, 0, "{:?}", scheme);
+            assert_eq!(analysis.genus(), Some(0));
+            assert_eq!(smooth.texcoords.len() / 2, smooth.vertices.len() / 3);
+        }
+        cube.subdivide(Subdivision::Loop, 1, std::f32::consts::PI);
+        // The seam keeps a vertex on each side, with the texture coordinates of its face
+        assert!(cube.vertices.len() / 3 > points(&cube).len());
+    }
+
+    #[test]
+    fn catmull_clark_stays_inside_the_hull() {
+        let mut cube = Mesh::cube(2.0, 1, [1.0; 4]);
+        cube.subdivide(Subdivision::CatmullClark, 3, std::f32::consts::PI);
+        for p in cube.vertices.chunks_exact(3) {
+            assert!(p.iter().all(|x| x.abs() <= 1.0), "{:?}", p);
+        }
+        let analysis = cube.analyze();
+        assert!(analysis.is_closed());
+        assert!(analysis.volume > 0.0 && analysis.volume < 8.0);
+        // The corners are rounded off
+        let corner = cube
+            .vertices
+            .chunks_exact(3)
+            .map(|p| p.iter().fold(f32::INFINITY, |m, x| m.min(x.abs())))
+            .fold(0.0, f32::max);
+        assert!(corner < 0.8, "{}", corner);
+    }
+}
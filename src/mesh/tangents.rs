@@ -0,0 +1,232 @@
+use super::Mesh;
+use nalgebra_glm as glm;
+
+// Tangents following the conventions of MikkTSpace, the tangent space glTF and most bakers use:
+// - each triangle gets the direction of increasing u, normalized rather than scaled by the area
+//   of its UVs, and the sign of its UV area as the bitangent sign
+// - at each corner it is projected onto the plane of the vertex normal, and the corners of a
+//   vertex are summed weighted by their angle
+// - a vertex used by triangles with mirrored UVs is split, so both sides keep their own sign
+// - shaders must not normalize the interpolated tangent and normal before rebuilding the
+//   bitangent as `sign * cross(normal, tangent)`
+
+// internal helper
+fn vec3(data: &[f32], i: u32) -> glm::Vec3 {
+    let i = i as usize * 3;
+    glm::vec3(data[i], data[i + 1], data[i + 2])
+}
+
+// internal helper, any unit vector perpendicular to `n`
+fn perpendicular(n: &glm::Vec3) -> glm::Vec3 {
+    let axis = if n.x.abs() < 0.9 {
+        glm::vec3(1.0, 0.0, 0.0)
+    } else {
+        glm::vec3(0.0, 1.0, 0.0)
+    };
+    let t = axis - n * n.dot(&axis);
+    if t.norm() > 0.0 {
+        t.normalize()
+    } else {
+        axis
+    }
+}
+
+impl Mesh {
+    /// Returns true if there is exactly one tangent per vertex
+    pub fn has_tangents(&self) -> bool {
+        !self.tangents.is_empty() && self.tangents.len() / 4 == self.vertices.len() / 3
+    }
+
+    /// Generates tangents if the mesh has none, or if they don't match the vertices
+    pub fn ensure_tangents(&mut self) -> bool {
+        self.has_tangents() || self.generate_tangents()
+    }
+
+    /// Replaces the tangents of the mesh, from its positions, normals and texture coordinates.
+    /// Normals are generated if missing. Returns false, leaving the mesh as it is, if it has no
+    /// texture coordinates.
+    pub fn generate_tangents(&mut self) -> bool {
+        if !self.has_texcoords() {
+            return false;
+        }
+        self.ensure_normals();
+        let num_verts = self.vertices.len() / 3;
+        let uv = |i: u32| {
+            let i = i as usize * 2;
+            glm::vec2(self.texcoords[i], self.texcoords[i + 1])
+        };
+
+        // The tangent of each corner's triangle, whether its UVs keep their orientation,
+        // and the angle at the corner. Triangles without any UV area have none.
+        let mut corners: Vec<Option<(glm::Vec3, bool, f32)>> =
+            Vec::with_capacity(self.indices.len());
+        for tri in self.indices.chunks_exact(3) {
+            let p = [0, 1, 2].map(|k| vec3(&self.vertices, tri[k]));
+            let t = [0, 1, 2].map(|k| uv(tri[k]));
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            let (d1, d2) = (t[1] - t[0], t[2] - t[0]);
+            let area = d1.x * d2.y - d2.x * d1.y;
+            let tangent = e1 * d2.y - e2 * d1.y;
+            let tangent = if area > 0.0 { tangent } else { -tangent };
+
+            for k in 0..3 {
+                let a = p[(k + 1) % 3] - p[k];
+                let b = p[(k + 2) % 3] - p[k];
+                let valid = area != 0.0 && tangent.norm() > 0.0;
+                corners.push(if valid && a.norm() > 0.0 && b.norm() > 0.0 {
+                    Some((tangent.normalize(), area > 0.0, glm::angle(&a, &b)))
+                } else {
+                    None
+                });
+            }
+        }
+
+        // Corners with mirrored UVs move to a copy of their vertex, if it has unmirrored ones too
+        let mut unmirrored = vec![false; num_verts];
+        for (c, corner) in corners.iter().enumerate() {
+            if let Some((_, true, _)) = corner {
+                unmirrored[self.indices[c] as usize] = true;
+            }
+        }
+        let mut source: Vec<u32> = (0..num_verts as u32).collect();
+        let mut mirrored_copy: Vec<Option<u32>> = vec![None; num_verts];
+        for (c, corner) in corners.iter().enumerate() {
+            let v = self.indices[c] as usize;
+            if let Some((_, false, _)) = corner {
+                if unmirrored[v] {
+                    let copy = *mirrored_copy[v].get_or_insert_with(|| {
+                        source.push(v as u32);
+                        source.len() as u32 - 1
+                    });
+                    self.indices[c] = copy;
+                }
+            }
+        }
+        if source.len() > num_verts {
+            self.remap_vertices(&source);
+        }
+
+        let num_verts = source.len();
+        let mut sums = vec![glm::Vec3::zeros(); num_verts];
+        let mut signs = vec![1.0; num_verts];
+        for (c, corner) in corners.iter().enumerate() {
+            if let Some((tangent, orientation, angle)) = corner {
+                let v = self.indices[c];
+                let n = vec3(&self.normals, v);
+                let projected = tangent - n * n.dot(tangent);
+                if projected.norm() > 0.0 {
+                    sums[v as usize] += projected.normalize() * *angle;
+                }
+                signs[v as usize] = if *orientation { 1.0 } else { -1.0 };
+            }
+        }
+
+        self.tangents = Vec::with_capacity(num_verts * 4);
+        for v in 0..num_verts {
+            let tangent = if sums[v].norm() > 0.0 {
+                sums[v].normalize()
+            } else {
+                perpendicular(&vec3(&self.normals, v as u32))
+            };
+            self.tangents.extend(tangent.iter());
+            self.tangents.push(signs[v]);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR: [f32; 4] = [1.0; 4];
+
+    // internal helper
+    fn tangent(mesh: &Mesh, v: u32) -> (glm::Vec3, f32) {
+        let i = v as usize * 4;
+        let t = &mesh.tangents[i..i + 4];
+        (glm::vec3(t[0], t[1], t[2]), t[3])
+    }
+
+    #[test]
+    fn cube_tangents_follow_the_u_axis_of_each_face() {
+        let mut cube = Mesh::cube(2.0, 1, COLOR);
+        let num_verts = cube.vertices.len() / 3;
+        assert!(!cube.has_tangents());
+        assert!(cube.generate_tangents());
+        assert!(cube.has_tangents());
+        assert_eq!(cube.vertices.len() / 3, num_verts);
+
+        // The faces in the order `Mesh::cube` builds them, with their direction of increasing u
+        let expected = [
+            glm::vec3(0.0, 0.0, -1.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(-1.0, 0.0, 0.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(1.0, 0.0, 0.0),
+        ];
+        for v in 0..num_verts as u32 {
+            let (t, sign) = tangent(&cube, v);
+            assert!(
+                glm::distance(&t, &expected[v as usize / 4]) < 1e-6,
+                "{}: {}",
+                v,
+                t
+            );
+            assert_eq!(sign, 1.0);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_split_the_seam() {
+        // Two quads side by side, u running away from the middle column on both sides
+        let mut plane = Mesh::plane(2.0, 1.0, 2, 1, COLOR);
+        for (uv, p) in plane.texcoords.chunks_mut(2).zip(plane.vertices.chunks(3)) {
+            uv[0] = p[0].abs();
+        }
+        assert_eq!(plane.vertices.len() / 3, 6);
+        assert!(plane.generate_tangents());
+
+        // Both vertices of the middle column get a copy with the other sign
+        assert_eq!(plane.vertices.len() / 3, 8);
+        assert_eq!(plane.texcoords.len() / 2, 8);
+        assert!(plane.has_tangents());
+        let middle: Vec<u32> = (0..8)
+            .filter(|&v| plane.vertices[v as usize * 3] == 0.0)
+            .collect();
+        assert_eq!(middle.len(), 4);
+        let signs: Vec<f32> = middle.iter().map(|&v| tangent(&plane, v).1).collect();
+        assert_eq!(signs.iter().filter(|&&s| s > 0.0).count(), 2);
+        assert_eq!(signs.iter().filter(|&&s| s < 0.0).count(), 2);
+
+        // Each side keeps its own frame: the tangent along increasing u, the bitangent
+        // rebuilt from the sign along increasing v
+        for triangle in plane.indices.chunks(3) {
+            let right = triangle
+                .iter()
+                .any(|&v| plane.vertices[v as usize * 3] > 0.0);
+            let u = if right {
+                glm::vec3(1.0, 0.0, 0.0)
+            } else {
+                glm::vec3(-1.0, 0.0, 0.0)
+            };
+            for &v in triangle {
+                let (t, sign) = tangent(&plane, v);
+                let bitangent = vec3(&plane.normals, v).cross(&t) * sign;
+                assert!(glm::distance(&t, &u) < 1e-6, "{}: {}", v, t);
+                assert_eq!(sign, if right { 1.0 } else { -1.0 });
+                assert!(glm::distance(&bitangent, &glm::vec3(0.0, 0.0, -1.0)) < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn meshes_without_uvs_get_no_tangents() {
+        let mut cube = Mesh::cube(2.0, 1, COLOR);
+        cube.texcoords.clear();
+        assert!(!cube.generate_tangents());
+        assert!(!cube.ensure_tangents());
+        assert!(cube.tangents.is_empty());
+    }
+}
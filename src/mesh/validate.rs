@@ -0,0 +1,588 @@
+use super::{generate_color_vec, Mesh};
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::fmt;
+
+// How far from unit length a normal may be before it is reported
+const NORMAL_TOLERANCE: f32 = 1e-3;
+
+/// A problem found by `Mesh::validate`
+#[derive(Clone, Debug, PartialEq)]
+pub enum MeshIssue {
+    /// The number of position values is not a multiple of 3
+    VertexCount { values: usize },
+    /// A per-vertex attribute has the wrong number of values for the vertices
+    AttributeCount {
+        attribute: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// The number of indices is not a whole number of triangles
+    IndexCount { count: usize },
+    /// `index_count` does not match the length of `indices`
+    IndexCountMismatch { index_count: i32, indices: usize },
+    /// An index points past the last vertex
+    IndexOutOfRange {
+        position: usize,
+        index: u32,
+        vertex_count: usize,
+    },
+    /// A sub-mesh covers indices that don't exist
+    SubMeshOutOfRange { name: String },
+    /// A vertex attribute is NaN or infinite
+    NonFinite {
+        attribute: &'static str,
+        vertex: usize,
+    },
+    /// A normal is not of unit length
+    UnnormalizedNormal { vertex: usize, length: f32 },
+    /// A triangle uses the same vertex twice, or has no area
+    DegenerateTriangle { triangle: usize },
+    /// Some vertices are exact copies of others, with every attribute equal
+    DuplicateVertices { count: usize },
+}
+
+// The attributes a mesh may leave empty
+const OPTIONAL_ATTRIBUTES: [&str; 2] = ["texcoords", "tangents"];
+
+impl MeshIssue {
+    /// True for issues that make the mesh unsafe to upload or draw, as opposed to just looking wrong
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            MeshIssue::VertexCount { .. }
+            | MeshIssue::IndexCount { .. }
+            | MeshIssue::IndexCountMismatch { .. }
+            | MeshIssue::IndexOutOfRange { .. }
+            | MeshIssue::SubMeshOutOfRange { .. } => true,
+            // Texture coordinates and tangents are only uploaded if they match the vertices
+            MeshIssue::AttributeCount { attribute, .. } => !OPTIONAL_ATTRIBUTES.contains(attribute),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for MeshIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshIssue::VertexCount { values } => {
+                write!(f, "{} position values is not a multiple of 3", values)
+            }
+            MeshIssue::AttributeCount {
+                attribute,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Expected {} values of {}, found {}",
+                expected, attribute, found
+            ),
+            MeshIssue::IndexCount { count } => {
+                write!(f, "{} indices is not a whole number of triangles", count)
+            }
+            MeshIssue::IndexCountMismatch {
+                index_count,
+                indices,
+            } => write!(
+                f,
+                "The index count is {}, but there are {} indices",
+                index_count, indices
+            ),
+            MeshIssue::IndexOutOfRange {
+                position,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "Index {} at {} is out of range for {} vertices",
+                index, position, vertex_count
+            ),
+            MeshIssue::SubMeshOutOfRange { name } => {
+                write!(f, "Sub-mesh {} is out of range of the indices", name)
+            }
+            MeshIssue::NonFinite { attribute, vertex } => {
+                write!(
+                    f,
+                    "Vertex {} has a non-finite value in {}",
+                    vertex, attribute
+                )
+            }
+            MeshIssue::UnnormalizedNormal { vertex, length } => {
+                write!(f, "The normal of vertex {} has length {}", vertex, length)
+            }
+            MeshIssue::DegenerateTriangle { triangle } => {
+                write!(f, "Triangle {} is degenerate", triangle)
+            }
+            MeshIssue::DuplicateVertices { count } => {
+                write!(f, "{} vertices are duplicates of others", count)
+            }
+        }
+    }
+}
+
+/// What `Mesh::repair` should fix besides the fatal issues, which are always fixed
+#[derive(Clone, Debug)]
+pub struct RepairOptions {
+    pub drop_degenerate: bool,
+    pub weld_duplicates: bool,
+    pub renormalize_normals: bool,
+    /// The color given to every vertex if the colors are missing, or `None` to leave them
+    pub fill_color: Option<[f32; 4]>,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        RepairOptions {
+            drop_degenerate: true,
+            weld_duplicates: true,
+            renormalize_normals: true,
+            fill_color: Some([1.0, 1.0, 1.0, 1.0]),
+        }
+    }
+}
+
+/// What `Mesh::repair` changed
+#[derive(Clone, Debug, Default)]
+pub struct RepairReport {
+    pub dropped_triangles: usize,
+    pub welded_vertices: usize,
+    pub removed_vertices: usize,
+    pub renormalized_normals: usize,
+    pub regenerated_normals: bool,
+    pub filled_colors: bool,
+    pub dropped_texcoords: bool,
+    pub dropped_tangents: bool,
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dropped {} triangles, welded {} and removed {} vertices, renormalized {} normals",
+            self.dropped_triangles,
+            self.welded_vertices,
+            self.removed_vertices,
+            self.renormalized_normals
+        )?;
+        if self.regenerated_normals {
+            write!(f, ", regenerated the normals")?;
+        }
+        if self.filled_colors {
+            write!(f, ", filled the colors")?;
+        }
+        if self.dropped_texcoords {
+            write!(f, ", dropped the texture coordinates")?;
+        }
+        if self.dropped_tangents {
+            write!(f, ", dropped the tangents")?;
+        }
+        Ok(())
+    }
+}
+
+// internal helper, true if the triangle repeats a vertex or has no area
+fn is_degenerate(vertices: &[f32], tri: &[u32]) -> bool {
+    if tri[0] == tri[1] || tri[1] == tri[2] || tri[2] == tri[0] {
+        return true;
+    }
+    let p = |i: u32| {
+        let i = i as usize * 3;
+        glm::vec3(vertices[i], vertices[i + 1], vertices[i + 2])
+    };
+    let (a, b) = (p(tri[1]) - p(tri[0]), p(tri[2]) - p(tri[0]));
+    a.cross(&b).norm() <= f32::EPSILON * a.norm() * b.norm()
+}
+
+// internal helper, the exact bits of every attribute of a vertex
+fn vertex_key(mesh: &Mesh, v: usize) -> Vec<u32> {
+    let mut key = Vec::with_capacity(12);
+    for (data, width) in [
+        (&mesh.vertices, 3),
+        (&mesh.normals, 3),
+        (&mesh.colors, 4),
+        (&mesh.texcoords, 2),
+        (&mesh.tangents, 4),
+    ] {
+        if let Some(values) = data.get(v * width..(v + 1) * width) {
+            key.extend(values.iter().map(|x| x.to_bits()));
+        }
+    }
+    key
+}
+
+impl Mesh {
+    /// Checks the mesh for everything that could go wrong when it is uploaded or drawn
+    pub fn validate(&self) -> Vec<MeshIssue> {
+        let mut issues = Vec::new();
+        if !self.vertices.chunks_exact(3).remainder().is_empty() {
+            issues.push(MeshIssue::VertexCount {
+                values: self.vertices.len(),
+            });
+        }
+        let num_verts = self.vertices.len() / 3;
+
+        let attributes = [
+            ("vertices", &self.vertices, 3),
+            ("normals", &self.normals, 3),
+            ("colors", &self.colors, 4),
+            ("texcoords", &self.texcoords, 2),
+            ("tangents", &self.tangents, 4),
+        ];
+        // Missing texture coordinates and tangents are fine, the other attributes are always uploaded
+        for &(attribute, data, width) in &attributes[1..] {
+            if data.len() != num_verts * width
+                && (!OPTIONAL_ATTRIBUTES.contains(&attribute) || !data.is_empty())
+            {
+                issues.push(MeshIssue::AttributeCount {
+                    attribute,
+                    expected: num_verts * width,
+                    found: data.len(),
+                });
+            }
+        }
+        for &(attribute, data, width) in &attributes {
+            let mut values = data.chunks_exact(width).enumerate();
+            if let Some((vertex, _)) = values.find(|(_, v)| v.iter().any(|x| !x.is_finite())) {
+                issues.push(MeshIssue::NonFinite { attribute, vertex });
+            }
+        }
+        if self.has_normals() {
+            for (vertex, n) in self.normals.chunks_exact(3).enumerate() {
+                let length = glm::vec3(n[0], n[1], n[2]).norm();
+                if length.is_finite() && (length - 1.0).abs() > NORMAL_TOLERANCE {
+                    issues.push(MeshIssue::UnnormalizedNormal { vertex, length });
+                }
+            }
+        }
+
+        if !self.indices.chunks_exact(3).remainder().is_empty() {
+            issues.push(MeshIssue::IndexCount {
+                count: self.indices.len(),
+            });
+        }
+        if self.index_count as usize != self.indices.len() {
+            issues.push(MeshIssue::IndexCountMismatch {
+                index_count: self.index_count,
+                indices: self.indices.len(),
+            });
+        }
+        for (position, &index) in self.indices.iter().enumerate() {
+            if index as usize >= num_verts {
+                issues.push(MeshIssue::IndexOutOfRange {
+                    position,
+                    index,
+                    vertex_count: num_verts,
+                });
+            }
+        }
+        for sub_mesh in &self.sub_meshes {
+            // Corrupt counts can be large enough to overflow
+            let in_range = match sub_mesh.first_index.checked_add(sub_mesh.index_count) {
+                Some(end) => {
+                    sub_mesh.first_index >= 0
+                        && sub_mesh.index_count >= 0
+                        && end as usize <= self.indices.len()
+                }
+                None => false,
+            };
+            if !in_range {
+                issues.push(MeshIssue::SubMeshOutOfRange {
+                    name: sub_mesh.name.clone(),
+                });
+            }
+        }
+        for (triangle, tri) in self.indices.chunks_exact(3).enumerate() {
+            let in_range = tri.iter().all(|&i| (i as usize) < num_verts);
+            if in_range && is_degenerate(&self.vertices, tri) {
+                issues.push(MeshIssue::DegenerateTriangle { triangle });
+            }
+        }
+
+        let mut seen = HashMap::new();
+        let duplicates = (0..num_verts)
+            .filter(|&v| seen.insert(vertex_key(self, v), v).is_some())
+            .count();
+        if duplicates > 0 {
+            issues.push(MeshIssue::DuplicateVertices { count: duplicates });
+        }
+        issues
+    }
+
+    /// Fixes the issues `validate` would report, so the mesh is at least safe to draw.
+    /// Triangles using missing or broken vertices are always dropped, the rest depends on the options.
+    pub fn repair(&mut self, options: &RepairOptions) -> RepairReport {
+        let mut report = RepairReport::default();
+        let num_verts = self.vertices.len() / 3;
+        self.vertices.truncate(num_verts * 3);
+
+        // Attributes that don't fit are rebuilt, or dropped if they can't be
+        let broken_normals = self.normals.len() != num_verts * 3
+            || self.normals.iter().any(|x| !x.is_finite())
+            || self
+                .normals
+                .chunks_exact(3)
+                .any(|n| n.iter().all(|&x| x == 0.0));
+        if broken_normals && !self.normals.is_empty() {
+            self.normals.clear();
+        }
+        if !self.texcoords.is_empty()
+            && (self.texcoords.len() != num_verts * 2
+                || self.texcoords.iter().any(|x| !x.is_finite()))
+        {
+            self.texcoords.clear();
+            report.dropped_texcoords = true;
+        }
+        if self.colors.len() != num_verts * 4 || self.colors.iter().any(|x| !x.is_finite()) {
+            self.colors.clear();
+        }
+        // Tangents are only any good along with the normals they were made for
+        if !self.tangents.is_empty()
+            && (broken_normals
+                || self.tangents.len() != num_verts * 4
+                || self.tangents.iter().any(|x| !x.is_finite()))
+        {
+            self.tangents.clear();
+            report.dropped_tangents = true;
+        }
+
+        // Before welding, so vertices that only differ in the length of their normal are merged
+        if !broken_normals && options.renormalize_normals {
+            for n in self.normals.chunks_exact_mut(3) {
+                let length = glm::vec3(n[0], n[1], n[2]).norm();
+                if (length - 1.0).abs() > NORMAL_TOLERANCE {
+                    n.iter_mut().for_each(|x| *x /= length);
+                    report.renormalized_normals += 1;
+                }
+            }
+        }
+
+        // Drop the triangles that can't be drawn, keeping the sub-meshes in step
+        let finite: Vec<bool> = self
+            .vertices
+            .chunks_exact(3)
+            .map(|p| p.iter().all(|x| x.is_finite()))
+            .collect();
+        let keep: Vec<bool> = self
+            .indices
+            .chunks_exact(3)
+            .map(|tri| {
+                tri.iter()
+                    .all(|&i| (i as usize) < num_verts && finite[i as usize])
+                    && !(options.drop_degenerate && is_degenerate(&self.vertices, tri))
+            })
+            .collect();
+        let num_tris = keep.len();
+        self.retain_triangles(&keep);
+        report.dropped_triangles = num_tris - self.indices.len() / 3;
+
+        // Weld exact duplicates and drop the vertices no triangle uses anymore
+        let mut target: Vec<Option<u32>> = vec![None; num_verts];
+        let mut source = Vec::new();
+        let mut welded = HashMap::new();
+        let mut indices = std::mem::take(&mut self.indices);
+        for index in indices.iter_mut() {
+            let v = *index as usize;
+            if target[v].is_none() {
+                let new_index = if options.weld_duplicates {
+                    *welded
+                        .entry(vertex_key(self, v))
+                        .or_insert(source.len() as u32)
+                } else {
+                    source.len() as u32
+                };
+                if new_index as usize == source.len() {
+                    source.push(v as u32);
+                } else {
+                    report.welded_vertices += 1;
+                }
+                target[v] = Some(new_index);
+            }
+            *index = target[v].unwrap();
+        }
+        self.indices = indices;
+        report.removed_vertices = num_verts - source.len() - report.welded_vertices;
+        if source.len() != num_verts {
+            self.remap_vertices(&source);
+        }
+
+        if broken_normals {
+            self.generate_normals(Default::default());
+            report.regenerated_normals = true;
+        }
+        if self.colors.is_empty() {
+            if let Some(color) = options.fill_color {
+                self.colors = generate_color_vec(color, self.vertices.len() / 3);
+                report.filled_colors = true;
+            }
+        }
+
+        self.index_count = self.indices.len() as i32;
+        self.update_bounds();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::SubMesh;
+
+    const COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+    // internal helper, a cube with one face split off as a sub-mesh
+    fn cube() -> Mesh {
+        let mut cube = Mesh::cube(2.0, 1, COLOR);
+        cube.sub_meshes = vec![
+            SubMesh {
+                name: "front".to_string(),
+                first_index: 0,
+                index_count: 6,
+            },
+            SubMesh {
+                name: "rest".to_string(),
+                first_index: 6,
+                index_count: 30,
+            },
+        ];
+        cube
+    }
+
+    #[test]
+    fn a_cube_has_no_issues() {
+        assert_eq!(cube().validate(), []);
+    }
+
+    #[test]
+    fn nan_position() {
+        let mut mesh = cube();
+        mesh.vertices[4] = f32::NAN;
+        let issues = mesh.validate();
+        assert!(issues.contains(&MeshIssue::NonFinite {
+            attribute: "vertices",
+            vertex: 1,
+        }));
+        assert!(issues.iter().all(|issue| !issue.is_fatal()));
+
+        // The triangles using it are dropped
+        let report = mesh.repair(&RepairOptions::default());
+        assert!(report.dropped_triangles > 0);
+        assert!(mesh.vertices.iter().all(|x| x.is_finite()));
+        assert_eq!(mesh.validate(), []);
+    }
+
+    #[test]
+    fn out_of_range_index() {
+        let mut mesh = cube();
+        mesh.indices[4] = 999;
+        let issues = mesh.validate();
+        let issue = MeshIssue::IndexOutOfRange {
+            position: 4,
+            index: 999,
+            vertex_count: 24,
+        };
+        assert!(issues.contains(&issue));
+        assert!(issue.is_fatal());
+
+        let report = mesh.repair(&RepairOptions::default());
+        assert_eq!(report.dropped_triangles, 1);
+        assert_eq!(mesh.sub_meshes[0].index_count, 3);
+        assert_eq!(mesh.sub_meshes[1].first_index, 3);
+        assert_eq!(mesh.validate(), []);
+    }
+
+    #[test]
+    fn degenerate_triangle() {
+        let mut mesh = cube();
+        mesh.indices.extend_from_slice(&[0, 0, 1]);
+        mesh.index_count += 3;
+        let issues = mesh.validate();
+        assert_eq!(issues, [MeshIssue::DegenerateTriangle { triangle: 12 }]);
+        assert!(!issues[0].is_fatal());
+    }
+
+    #[test]
+    fn mismatched_normals_and_colors() {
+        let mut mesh = cube();
+        mesh.normals.truncate(mesh.normals.len() - 3);
+        mesh.colors.push(1.0);
+        let issues = mesh.validate();
+        assert!(issues.contains(&MeshIssue::AttributeCount {
+            attribute: "normals",
+            expected: 72,
+            found: 69,
+        }));
+        assert!(issues.contains(&MeshIssue::AttributeCount {
+            attribute: "colors",
+            expected: 96,
+            found: 97,
+        }));
+        assert!(issues.iter().all(|issue| issue.is_fatal()));
+
+        let report = mesh.repair(&RepairOptions::default());
+        assert!(report.regenerated_normals);
+        assert!(report.filled_colors);
+        assert_eq!(mesh.validate(), []);
+    }
+
+    #[test]
+    fn overflowing_sub_mesh() {
+        let mut mesh = cube();
+        mesh.sub_meshes[1].first_index = i32::MAX;
+        mesh.sub_meshes[1].index_count = i32::MAX;
+        let issues = mesh.validate();
+        assert_eq!(
+            issues,
+            [MeshIssue::SubMeshOutOfRange {
+                name: "rest".to_string(),
+            }]
+        );
+        assert!(issues[0].is_fatal());
+
+        mesh.repair(&RepairOptions::default());
+        assert_eq!(mesh.sub_meshes[1].first_index, 36);
+        assert_eq!(mesh.sub_meshes[1].index_count, 0);
+        assert_eq!(mesh.validate(), []);
+    }
+
+    #[test]
+    fn repair_welds_renormalizes_and_fills() {
+        let mut mesh = cube();
+        // The first triangle uses copies of its vertices, one with a longer normal
+        let first = [mesh.indices[0], mesh.indices[1], mesh.indices[2]];
+        for (k, &v) in first.iter().enumerate() {
+            let v = v as usize;
+            let (position, normal) = (
+                mesh.vertices[v * 3..v * 3 + 3].to_vec(),
+                mesh.normals[v * 3..v * 3 + 3].to_vec(),
+            );
+            let texcoord = mesh.texcoords[v * 2..v * 2 + 2].to_vec();
+            let color = mesh.colors[v * 4..v * 4 + 4].to_vec();
+            mesh.vertices.extend(position);
+            mesh.normals
+                .extend(normal.iter().map(|x| x * if k == 1 { 2.0 } else { 1.0 }));
+            mesh.texcoords.extend(texcoord);
+            mesh.colors.extend(color);
+            mesh.indices[k] = 24 + k as u32;
+        }
+        mesh.colors.clear();
+        assert!(mesh.validate().contains(&MeshIssue::UnnormalizedNormal {
+            vertex: 25,
+            length: 2.0,
+        }));
+        assert!(mesh
+            .validate()
+            .contains(&MeshIssue::DuplicateVertices { count: 2 }));
+
+        let report = mesh.repair(&RepairOptions::default());
+        assert_eq!(report.dropped_triangles, 0);
+        assert_eq!(report.renormalized_normals, 1);
+        // Two copies meet their originals in the next triangle, the third one's original is unused
+        assert_eq!(report.welded_vertices, 2);
+        assert_eq!(report.removed_vertices, 1);
+        assert!(report.filled_colors);
+        assert!(!report.regenerated_normals);
+        assert_eq!(mesh.vertices.len() / 3, 24);
+        assert_eq!(mesh.colors, generate_color_vec([1.0; 4], 24));
+        assert_eq!(mesh.index_count, 36);
+        assert_eq!(mesh.validate(), []);
+    }
+}
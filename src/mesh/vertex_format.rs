@@ -0,0 +1,660 @@
+use super::Mesh;
+
+// Meshes are kept as separate f32 arrays on the CPU, and packed into a single interleaved
+// buffer for the GPU. Every attribute starts on a 4 byte boundary. The compressed formats are
+// all expanded back to floats by the vertex fetch hardware, except octahedral normals which the
+// vertex shader decodes from their own attribute location.
+
+/// How positions are stored on the GPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionFormat {
+    F32,
+    /// Only about three significant digits, fine for small models around the origin
+    #[allow(dead_code)]
+    F16,
+}
+
+/// How colors are stored on the GPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorFormat {
+    F32,
+    /// Normalised, clamped to [0, 1]
+    U8,
+}
+
+/// How normals are stored on the GPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalFormat {
+    F32,
+    /// Two normalised i16 on the octahedron, decoded by the vertex shader
+    #[allow(dead_code)]
+    Octahedral,
+    /// Normalised 10-10-10-2 signed integers
+    Packed,
+}
+
+/// How tangents and their bitangent signs are stored on the GPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TangentFormat {
+    F32,
+    /// Normalised 10-10-10-2 signed integers, with the sign in the two bit component
+    Packed,
+}
+
+/// How texture coordinates are stored on the GPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TexcoordFormat {
+    F32,
+    /// Precise to a texel of a 2048 wide texture within [0, 1]
+    F16,
+}
+
+/// The type of the indices in the index buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    U16,
+    U32,
+}
+
+impl IndexFormat {
+    /// The size of one index, in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            IndexFormat::U16 => 2,
+            IndexFormat::U32 => 4,
+        }
+    }
+}
+
+/// Describes how the attributes of a mesh are packed for the GPU.
+/// The default keeps everything at full precision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexLayout {
+    pub positions: PositionFormat,
+    pub colors: ColorFormat,
+    pub normals: NormalFormat,
+    pub texcoords: TexcoordFormat,
+    pub tangents: TangentFormat,
+    /// Use u16 indices when the mesh has few enough vertices
+    pub compact_indices: bool,
+}
+
+impl Default for VertexLayout {
+    fn default() -> Self {
+        Self {
+            positions: PositionFormat::F32,
+            colors: ColorFormat::F32,
+            normals: NormalFormat::F32,
+            texcoords: TexcoordFormat::F32,
+            tangents: TangentFormat::F32,
+            compact_indices: false,
+        }
+    }
+}
+
+impl VertexLayout {
+    /// Compresses everything but the positions, taking a vertex from 48 down to 24 bytes,
+    /// or from 64 down to 28 with tangents
+    pub fn compact() -> Self {
+        Self {
+            positions: PositionFormat::F32,
+            colors: ColorFormat::U8,
+            normals: NormalFormat::Packed,
+            texcoords: TexcoordFormat::F16,
+            tangents: TangentFormat::Packed,
+            compact_indices: true,
+        }
+    }
+}
+
+/// The type of the components of an attribute in the interleaved buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentType {
+    F32,
+    F16,
+    /// Normalised to [0, 1]
+    U8Norm,
+    /// Normalised to [-1, 1]
+    I16Norm,
+    /// Four normalised signed components packed in 10-10-10-2 bits, x in the lowest
+    I2101010Norm,
+}
+
+/// Where to find one attribute in the interleaved buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexAttribute {
+    /// The shader attribute location
+    pub location: u32,
+    pub components: i32,
+    pub component_type: ComponentType,
+    /// In bytes, from the start of the vertex
+    pub offset: usize,
+}
+
+/// The indices, in the smallest type the layout allows
+#[derive(Clone, Debug)]
+pub enum IndexData {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+#[allow(dead_code)]
+impl IndexData {
+    pub fn format(&self) -> IndexFormat {
+        match self {
+            IndexData::U16(_) => IndexFormat::U16,
+            IndexData::U32(_) => IndexFormat::U32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IndexData::U16(indices) => indices.len(),
+            IndexData::U32(indices) => indices.len(),
+        }
+    }
+}
+
+/// A mesh packed for upload, see `Mesh::interleave`
+#[derive(Clone, Debug)]
+pub struct VertexData {
+    pub bytes: Vec<u8>,
+    /// The size of one vertex, in bytes
+    pub stride: usize,
+    pub attributes: Vec<VertexAttribute>,
+    pub indices: IndexData,
+}
+
+#[allow(dead_code)]
+impl VertexData {
+    /// The total size of the vertex and index buffers, in bytes
+    pub fn size(&self) -> usize {
+        self.bytes.len() + self.indices.len() * self.indices.format().size()
+    }
+}
+
+// The attribute locations used by the shaders
+const POSITION_LOCATION: u32 = 0;
+const COLOR_LOCATION: u32 = 1;
+const NORMAL_LOCATION: u32 = 2;
+const TEXCOORD_LOCATION: u32 = 3;
+const OCTAHEDRAL_NORMAL_LOCATION: u32 = 4;
+const TANGENT_LOCATION: u32 = 5;
+
+impl Mesh {
+    /// Packs the vertices into one interleaved buffer following `layout`.
+    /// Attributes the mesh doesn't have are left out.
+    pub fn interleave(&self, layout: &VertexLayout) -> VertexData {
+        let num_verts = self.vertices.len() / 3;
+
+        let mut attributes = Vec::new();
+        let mut stride = 0;
+        let mut add = |location, components, component_type| {
+            attributes.push(VertexAttribute {
+                location,
+                components,
+                component_type,
+                offset: stride,
+            });
+            stride += aligned_size(components, component_type);
+        };
+        add(
+            POSITION_LOCATION,
+            3,
+            match layout.positions {
+                PositionFormat::F32 => ComponentType::F32,
+                PositionFormat::F16 => ComponentType::F16,
+            },
+        );
+        if num_verts > 0 && self.colors.len() == num_verts * 4 {
+            add(
+                COLOR_LOCATION,
+                4,
+                match layout.colors {
+                    ColorFormat::F32 => ComponentType::F32,
+                    ColorFormat::U8 => ComponentType::U8Norm,
+                },
+            );
+        }
+        if self.has_normals() {
+            match layout.normals {
+                NormalFormat::F32 => add(NORMAL_LOCATION, 3, ComponentType::F32),
+                NormalFormat::Octahedral => {
+                    add(OCTAHEDRAL_NORMAL_LOCATION, 2, ComponentType::I16Norm)
+                }
+                NormalFormat::Packed => add(NORMAL_LOCATION, 4, ComponentType::I2101010Norm),
+            }
+        }
+        if self.has_texcoords() {
+            add(
+                TEXCOORD_LOCATION,
+                2,
+                match layout.texcoords {
+                    TexcoordFormat::F32 => ComponentType::F32,
+                    TexcoordFormat::F16 => ComponentType::F16,
+                },
+            );
+        }
+
+        if self.has_tangents() {
+            add(
+                TANGENT_LOCATION,
+                4,
+                match layout.tangents {
+                    TangentFormat::F32 => ComponentType::F32,
+                    TangentFormat::Packed => ComponentType::I2101010Norm,
+                },
+            );
+        }
+
+        let mut bytes = Vec::with_capacity(num_verts * stride);
+        for v in 0..num_verts {
+            for attribute in &attributes {
+                let start = bytes.len();
+                match attribute.location {
+                    POSITION_LOCATION => pack(
+                        &mut bytes,
+                        attribute.component_type,
+                        &self.vertices[v * 3..v * 3 + 3],
+                    ),
+                    COLOR_LOCATION => pack(
+                        &mut bytes,
+                        attribute.component_type,
+                        &self.colors[v * 4..v * 4 + 4],
+                    ),
+                    NORMAL_LOCATION => pack(
+                        &mut bytes,
+                        attribute.component_type,
+                        &self.normals[v * 3..v * 3 + 3],
+                    ),
+                    OCTAHEDRAL_NORMAL_LOCATION => {
+                        let normal = [0, 1, 2].map(|k| self.normals[v * 3 + k]);
+                        pack(
+                            &mut bytes,
+                            attribute.component_type,
+                            &octahedral_encode(normal),
+                        )
+                    }
+                    TEXCOORD_LOCATION => pack(
+                        &mut bytes,
+                        attribute.component_type,
+                        &self.texcoords[v * 2..v * 2 + 2],
+                    ),
+                    TANGENT_LOCATION => pack(
+                        &mut bytes,
+                        attribute.component_type,
+                        &self.tangents[v * 4..v * 4 + 4],
+                    ),
+                    location => unreachable!("no attribute is packed for location {}", location),
+                }
+                bytes.resize(
+                    start + aligned_size(attribute.components, attribute.component_type),
+                    0,
+                );
+            }
+        }
+
+        let indices = if layout.compact_indices && num_verts <= u16::MAX as usize + 1 {
+            IndexData::U16(self.indices.iter().map(|&i| i as u16).collect())
+        } else {
+            IndexData::U32(self.indices.clone())
+        };
+
+        VertexData {
+            bytes,
+            stride,
+            attributes,
+            indices,
+        }
+    }
+}
+
+// internal helper, the size of an attribute padded to 4 bytes
+fn aligned_size(components: i32, component_type: ComponentType) -> usize {
+    let size = match component_type {
+        ComponentType::F32 => 4 * components as usize,
+        ComponentType::F16 | ComponentType::I16Norm => 2 * components as usize,
+        ComponentType::U8Norm => components as usize,
+        ComponentType::I2101010Norm => 4,
+    };
+    (size + 3) & !3
+}
+
+// internal helper, appends the values converted to `component_type`
+fn pack(out: &mut Vec<u8>, component_type: ComponentType, values: &[f32]) {
+    match component_type {
+        ComponentType::F32 => {
+            for value in values {
+                out.extend(value.to_le_bytes());
+            }
+        }
+        ComponentType::F16 => {
+            for &value in values {
+                out.extend(f32_to_f16(value).to_le_bytes());
+            }
+        }
+        ComponentType::U8Norm => {
+            out.extend(
+                values
+                    .iter()
+                    .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8),
+            );
+        }
+        ComponentType::I16Norm => {
+            for value in values {
+                let value = (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                out.extend(value.to_le_bytes());
+            }
+        }
+        ComponentType::I2101010Norm => {
+            // Ten bits each for x, y and z, and the sign of w in the top two, 1 if there is no w
+            let w: u32 = if matches!(values.get(3), Some(&w) if w < 0.0) {
+                0b11
+            } else {
+                0b01
+            };
+            let packed = values[..3]
+                .iter()
+                .enumerate()
+                .fold(w << 30, |packed: u32, (k, c)| {
+                    let c = (c.clamp(-1.0, 1.0) * 511.0).round() as i32 & 0x3ff;
+                    packed | (c as u32) << (10 * k)
+                });
+            out.extend(packed.to_le_bytes());
+        }
+    }
+}
+
+// internal helper, converts to an IEEE half float rounding to nearest even.
+// Out of range values become infinite.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity, or NaN keeping a mantissa bit set
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, or too small and flushed to zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        return sign | round_shift(mantissa, shift) as u16;
+    }
+    // Rounding may carry into the exponent, which is still the right result
+    sign | round_shift(((exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+// internal helper, shifts right rounding to nearest even
+fn round_shift(value: u32, shift: u32) -> u32 {
+    let half = 1 << (shift - 1);
+    let remainder = value & ((1 << shift) - 1);
+    let result = value >> shift;
+    if remainder > half || (remainder == half && result & 1 == 1) {
+        result + 1
+    } else {
+        result
+    }
+}
+
+// internal helper, maps a unit vector onto the octahedron unfolded into [-1, 1]²
+fn octahedral_encode(n: [f32; 3]) -> [f32; 2] {
+    let length = n[0].abs() + n[1].abs() + n[2].abs();
+    if length == 0.0 {
+        return [0.0, 0.0];
+    }
+    let [x, y, z] = n.map(|c| c / length);
+    if z >= 0.0 {
+        [x, y]
+    } else {
+        // Fold the lower half over the diagonals
+        let sign = |c: f32| if c >= 0.0 { 1.0 } else { -1.0 };
+        [(1.0 - y.abs()) * sign(x), (1.0 - x.abs()) * sign(y)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // internal helper, the reference decoding of a half float
+    fn f16_to_f32(half: u16) -> f32 {
+        let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = ((half >> 10) & 0x1f) as i32;
+        let mantissa = (half & 0x3ff) as f32;
+        sign * match exponent {
+            0 => mantissa * 2f32.powi(-24),
+            0x1f if mantissa == 0.0 => f32::INFINITY,
+            0x1f => f32::NAN,
+            _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    // internal helper, what the vertex shader does with an octahedral normal
+    fn octahedral_decode([u, v]: [f32; 2]) -> [f32; 3] {
+        let sign = |c: f32| if c >= 0.0 { 1.0 } else { -1.0 };
+        let z = 1.0 - u.abs() - v.abs();
+        let (x, y) = if z >= 0.0 {
+            (u, v)
+        } else {
+            ((1.0 - v.abs()) * sign(u), (1.0 - u.abs()) * sign(v))
+        };
+        let length = (x * x + y * y + z * z).sqrt();
+        [x / length, y / length, z / length]
+    }
+
+    // internal helper, what the vertex fetch does with a 10-10-10-2 value
+    fn unpack_2_10_10_10(packed: u32) -> [f32; 4] {
+        let signed = |bits: u32, width: u32| {
+            let shift = 32 - width;
+            ((bits << shift) as i32 >> shift) as f32
+        };
+        let component = |k: u32| (signed((packed >> (10 * k)) & 0x3ff, 10) / 511.0).max(-1.0);
+        [
+            component(0),
+            component(1),
+            component(2),
+            signed(packed >> 30, 2).max(-1.0),
+        ]
+    }
+
+    // internal helper
+    fn packed(component_type: ComponentType, values: &[f32]) -> Vec<u8> {
+        let mut out = Vec::new();
+        pack(&mut out, component_type, values);
+        out
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.5), 0xc100);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        let nan = f32_to_f16(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x3ff, 0);
+        // Too large rounds to infinity, too small to zero
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert_eq!(f32_to_f16(1e-9), 0x0000);
+        assert_eq!(f32_to_f16(-1e-9), 0x8000);
+
+        // Subnormals
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(2f32.powi(-15)), 0x0200);
+        assert_eq!(f32_to_f16(2f32.powi(-14)), 0x0400);
+
+        // Ties go to the even neighbour, anything past them to the nearest
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11) + 2f32.powi(-20)), 0x3c01);
+        assert_eq!(f32_to_f16(2f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_f16(3.0 * 2f32.powi(-25)), 0x0002);
+        // Rounding up carries into the exponent
+        assert_eq!(f32_to_f16(2.0 - 2f32.powi(-12)), 0x4000);
+
+        // Every half float survives the trip through f32
+        for half in 0..=u16::MAX {
+            let value = f16_to_f32(half);
+            if !value.is_nan() {
+                assert_eq!(f32_to_f16(value), half, "{:#06x} is {}", half, value);
+            }
+        }
+    }
+
+    #[test]
+    fn octahedral_normals() {
+        let axes = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        let encoded = axes.map(octahedral_encode);
+        assert_eq!(
+            encoded,
+            [
+                [1.0, 0.0],
+                [-1.0, 0.0],
+                [0.0, 1.0],
+                [0.0, -1.0],
+                [0.0, 0.0],
+                [1.0, 1.0],
+            ]
+        );
+
+        // The lower hemisphere is folded out into the corners
+        let lower = [
+            [0.3, -0.5, -0.8],
+            [-0.6, 0.2, -0.7],
+            [-0.1, -0.1, -1.0],
+            [0.7, 0.7, -0.1],
+        ];
+        for n in axes.iter().chain(&lower) {
+            let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            let n = n.map(|c| c / length);
+            let [u, v] = octahedral_encode(n);
+            assert!(u.abs() <= 1.0 && v.abs() <= 1.0);
+            if n[2] < 0.0 {
+                assert!(u.abs() + v.abs() >= 1.0);
+            }
+            let decoded = octahedral_decode([u, v]);
+            for k in 0..3 {
+                assert!((decoded[k] - n[k]).abs() < 1e-5, "{:?} -> {:?}", n, decoded);
+            }
+
+            // And through the 16 bit attribute
+            let bytes = packed(ComponentType::I16Norm, &[u, v]);
+            let quantized = [0, 2]
+                .map(|i| i16::from_le_bytes([bytes[i], bytes[i + 1]]) as f32 / i16::MAX as f32);
+            let decoded = octahedral_decode(quantized);
+            for k in 0..3 {
+                assert!((decoded[k] - n[k]).abs() < 1e-4);
+            }
+        }
+        assert_eq!(octahedral_encode([0.0, 0.0, 0.0]), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn packed_2_10_10_10() {
+        let unpacked = |values: &[f32]| {
+            let bytes = packed(ComponentType::I2101010Norm, values);
+            assert_eq!(bytes.len(), 4);
+            unpack_2_10_10_10(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        let bits = |values: &[f32]| {
+            let bytes = packed(ComponentType::I2101010Norm, values);
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        };
+        assert_eq!(
+            bits(&[1.0, -1.0, 0.0, 1.0]),
+            0b01 << 30 | 0x201 << 10 | 0x1ff
+        );
+        assert_eq!(unpacked(&[1.0, -1.0, 0.0, 1.0]), [1.0, -1.0, 0.0, 1.0]);
+        // Only the sign of w is kept, and no w counts as positive
+        assert_eq!(unpacked(&[0.0, 1.0, -1.0, -1.0]), [0.0, 1.0, -1.0, -1.0]);
+        assert_eq!(unpacked(&[0.0, 0.0, 1.0, -0.25])[3], -1.0);
+        assert_eq!(unpacked(&[0.0, 0.0, 1.0])[3], 1.0);
+        assert_eq!(bits(&[0.0, 0.0, 0.0]), 0b01 << 30);
+        // Out of range values are clamped
+        assert_eq!(unpacked(&[2.0, -2.0, 0.5, 1.0])[..2], [1.0, -1.0]);
+        let half = unpacked(&[0.5, -0.5, 0.25, 1.0]);
+        for (k, &expected) in [0.5, -0.5, 0.25].iter().enumerate() {
+            assert!((half[k] - expected).abs() <= 0.5 / 511.0);
+        }
+    }
+
+    #[test]
+    fn compact_layout() {
+        let mut cube = Mesh::cube(2.0, 1, [0.2, 0.4, 0.6, 1.0]);
+        let data = cube.interleave(&VertexLayout::compact());
+        let locations: Vec<(u32, usize)> = data
+            .attributes
+            .iter()
+            .map(|a| (a.location, a.offset))
+            .collect();
+        assert_eq!(
+            locations,
+            [
+                (POSITION_LOCATION, 0),
+                (COLOR_LOCATION, 12),
+                (NORMAL_LOCATION, 16),
+                (TEXCOORD_LOCATION, 20),
+            ]
+        );
+        assert_eq!(data.stride, 24);
+        assert_eq!(data.bytes.len(), 24 * data.stride);
+        assert_eq!(data.indices.format(), IndexFormat::U16);
+        assert_eq!(cube.interleave(&VertexLayout::default()).stride, 48);
+
+        assert!(cube.generate_tangents());
+        let data = cube.interleave(&VertexLayout::compact());
+        assert_eq!(data.stride, 28);
+        assert_eq!(data.attributes[4].location, TANGENT_LOCATION);
+        assert_eq!(data.attributes[4].offset, 24);
+        assert_eq!(cube.interleave(&VertexLayout::default()).stride, 64);
+
+        // Every attribute of a vertex can be read back from its offset
+        for v in [0, 7, 23] {
+            let vertex = &data.bytes[v * data.stride..(v + 1) * data.stride];
+            let word = |offset: usize| {
+                u32::from_le_bytes([
+                    vertex[offset],
+                    vertex[offset + 1],
+                    vertex[offset + 2],
+                    vertex[offset + 3],
+                ])
+            };
+            for k in 0..3 {
+                assert_eq!(f32::from_bits(word(4 * k)), cube.vertices[v * 3 + k]);
+            }
+            assert_eq!(vertex[12..16], [51, 102, 153, 255]);
+            let normal = unpack_2_10_10_10(word(16));
+            let tangent = unpack_2_10_10_10(word(24));
+            for k in 0..3 {
+                assert!((normal[k] - cube.normals[v * 3 + k]).abs() < 1e-6);
+                assert!((tangent[k] - cube.tangents[v * 4 + k]).abs() < 1e-6);
+            }
+            assert_eq!(tangent[3], cube.tangents[v * 4 + 3]);
+            for k in 0..2 {
+                let half = u16::from_le_bytes([vertex[20 + 2 * k], vertex[21 + 2 * k]]);
+                assert_eq!(f16_to_f32(half), cube.texcoords[v * 2 + k]);
+            }
+        }
+    }
+}
@@ -1,67 +1,105 @@
+use crate::mesh::{IndexFormat, LodSet, Material};
 use nalgebra_glm as glm;
+use std::error::Error;
 use std::fmt;
+use std::ops::{Index, IndexMut};
+
+/// A vertex array object on the GPU, with what is needed to draw from its index buffer
+#[derive(Clone, Copy, Debug)]
+pub struct VertexArray {
+    pub id: u32,
+    pub index_format: IndexFormat,
+}
+
+/// The textures of a material on the GPU, 0 for those it doesn't have
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaterialTextures {
+    pub diffuse: u32,
+    pub normal: u32,
+}
 
 /// The SceneNode data structure from the handout code, rewritten in safe Rust
 pub struct SceneNode {
+    pub name: Option<String>,       // What I'm called, to be found by path
     pub position: glm::Vec3,        // Where I should be in relation to my parent
     pub rotation: glm::Vec3,        // How I should be rotated, around the X, the Y and the Z axes
     pub scale: glm::Vec3,           // How I should be scaled
     pub reference_point: glm::Vec3, // The point I shall rotate and scale about
 
-    pub vao_id: u32,      // What I should draw
-    pub index_count: i32, // How much of it there is to draw
-
-    children: Vec<SceneNode>, // Those I command
+    pub vao_id: u32,               // What I should draw
+    pub index_format: IndexFormat, // The type of the indices in the VAO
+    pub first_index: i32,          // Where in the index buffer I should start drawing
+    pub index_count: i32,          // How much of it there is to draw
+    pub material: Material,        // What it should look like
+    pub texture_id: u32,           // What should be painted on it, 0 if nothing
+    pub normal_texture_id: u32,    // What should bump it, 0 if nothing
+    pub lod: Option<LodSet>,       // Coarser ranges to draw instead when far away
 }
 
 impl Default for SceneNode {
     fn default() -> Self {
         Self {
+            name: None,
             position: glm::zero(),
             rotation: glm::zero(),
             scale: glm::vec3(1.0, 1.0, 1.0),
             reference_point: glm::zero(),
             vao_id: 0,
+            index_format: IndexFormat::U32,
+            first_index: 0,
             index_count: -1,
-            children: Vec::new(),
+            material: Material::default(),
+            texture_id: 0,
+            normal_texture_id: 0,
+            lod: None,
         }
     }
 }
 
 impl SceneNode {
-    pub fn new(vao_id: u32, index_count: i32) -> Self {
+    pub fn new(vao: VertexArray, index_count: i32) -> Self {
         Self {
+            name: None,
             position: glm::zero(),
             rotation: glm::zero(),
             scale: glm::vec3(1.0, 1.0, 1.0),
             reference_point: glm::zero(),
-            vao_id,
+            vao_id: vao.id,
+            index_format: vao.index_format,
+            first_index: 0,
             index_count,
-            children: Vec::new(),
+            material: Material::default(),
+            texture_id: 0,
+            normal_texture_id: 0,
+            lod: None,
         }
     }
 
-    pub fn add_child(&mut self, child: SceneNode) {
-        self.children.push(child)
+    /// Creates an empty named node, to group its children and move them together
+    pub fn group(name: &str) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            ..Self::default()
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn get_child_mut(&mut self, index: usize) -> Option<&mut SceneNode> {
-        self.children.get_mut(index)
+    /// Creates a node drawing only a range of the indices in the VAO, e.g. a `SubMesh`
+    pub fn with_range(vao: VertexArray, first_index: i32, index_count: i32) -> Self {
+        Self {
+            first_index,
+            ..Self::new(vao, index_count)
+        }
     }
 
-    /// Returns the number of children of this node.
-    #[allow(dead_code)]
-    pub fn len(&self) -> usize {
-        self.children.len()
-    }
-    
-    pub fn iter(&self) -> impl Iterator<Item = &SceneNode> {
-        self.children.iter()
-    }
-    
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut SceneNode> {
-        self.children.iter_mut()
+    /// The transform from this node to its parent: scaled and rotated about the reference point, then moved
+    pub fn local_transform(&self) -> glm::Mat4 {
+        glm::translation(&self.position)
+            * glm::translation(&self.reference_point)
+            * glm::rotation(self.rotation.x, &glm::vec3(1.0, 0.0, 0.0))
+            * glm::rotation(self.rotation.y, &glm::vec3(0.0, 1.0, 0.0))
+            * glm::rotation(self.rotation.z, &glm::vec3(0.0, 0.0, 1.0))
+            * glm::scaling(&self.scale)
+            * glm::translation(&-self.reference_point)
     }
 }
 
@@ -70,16 +108,26 @@ impl fmt::Debug for SceneNode {
         writeln!(
             f,
             "SceneNode {{
+    Name:      {}
     VAO:       {}
+    First:     {}
     Indices:   {}
-    Children:  {}
+    Material:  {}
+    Texture:   {}
+    Normals:   {}
+    LODs:      {}
     Position:  [{:.2}, {:.2}, {:.2}]
     Rotation:  [{:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
 }}",
+            self.name.as_deref().unwrap_or("-"),
             self.vao_id,
+            self.first_index,
             self.index_count,
-            self.children.len(),
+            self.material.name,
+            self.texture_id,
+            self.normal_texture_id,
+            self.lod.as_ref().map_or(1, |lod| lod.levels.len()),
             self.position.x,
             self.position.y,
             self.position.z,
@@ -92,3 +140,642 @@ impl fmt::Debug for SceneNode {
         )
     }
 }
+
+/// A handle to a node in a `SceneGraph`. It stays valid until the node is removed,
+/// and never refers to another node after that.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
+/// Everything that can go wrong when changing the shape of a scene graph
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SceneGraphError {
+    /// The node was removed, or belongs to another graph
+    InvalidNode(NodeId),
+    /// The root can't be removed or moved
+    Root,
+    /// A node can't become a child of itself or of one of its descendants
+    Cycle { node: NodeId, new_parent: NodeId },
+}
+
+impl fmt::Display for SceneGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneGraphError::InvalidNode(id) => write!(f, "There is no node {:?}", id),
+            SceneGraphError::Root => write!(f, "The root node can't be removed or moved"),
+            SceneGraphError::Cycle { node, new_parent } => write!(
+                f,
+                "{:?} can't become a child of its descendant {:?}",
+                node, new_parent
+            ),
+        }
+    }
+}
+
+impl Error for SceneGraphError {}
+
+// internal helper, a node in the arena with its links
+struct Entry {
+    node: SceneNode,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+// internal helper, a place in the arena. The generation grows every time its node is removed.
+struct Slot {
+    generation: u32,
+    entry: Option<Entry>,
+}
+
+// internal helper, matches a name against a pattern with `*` and `?` wildcards.
+// A failed `*` is retried one character longer, from where the last one matched.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The scene as a tree of `SceneNode`s, stored in an arena and addressed by `NodeId`s.
+/// There is always a root node, which transforms the whole scene.
+pub struct SceneGraph {
+    slots: Vec<Slot>,
+    // Slots of removed nodes, to be reused
+    free: Vec<u32>,
+    root: NodeId,
+}
+
+impl Default for SceneGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneGraph {
+    /// A graph with just an empty root node
+    pub fn new() -> Self {
+        let root = NodeId {
+            index: 0,
+            generation: 0,
+        };
+        SceneGraph {
+            slots: vec![Slot {
+                generation: 0,
+                entry: Some(Entry {
+                    node: SceneNode::default(),
+                    parent: None,
+                    children: Vec::new(),
+                }),
+            }],
+            free: Vec::new(),
+            root,
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// Returns the number of nodes, including the root
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    /// Returns true if the node hasn't been removed
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.entry(id).is_some()
+    }
+
+    // internal helper
+    fn entry(&self, id: NodeId) -> Option<&Entry> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.entry.as_ref())
+    }
+
+    // internal helper
+    fn entry_mut(&mut self, id: NodeId) -> Option<&mut Entry> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.entry.as_mut())
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&SceneNode> {
+        self.entry(id).map(|entry| &entry.node)
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        self.entry_mut(id).map(|entry| &mut entry.node)
+    }
+
+    /// Adds a node as the last child of `parent`, which must not have been removed
+    pub fn insert(&mut self, parent: NodeId, node: SceneNode) -> Result<NodeId, SceneGraphError> {
+        if !self.contains(parent) {
+            return Err(SceneGraphError::InvalidNode(parent));
+        }
+        let entry = Entry {
+            node,
+            parent: Some(parent),
+            children: Vec::new(),
+        };
+        let id = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.entry = Some(entry);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    entry: Some(entry),
+                });
+                NodeId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        };
+        self.entry_mut(parent).unwrap().children.push(id);
+        Ok(id)
+    }
+
+    /// Removes a node along with all of its descendants, returning the node itself
+    #[allow(dead_code)]
+    pub fn remove(&mut self, id: NodeId) -> Result<SceneNode, SceneGraphError> {
+        if id == self.root {
+            return Err(SceneGraphError::Root);
+        }
+        let parent = self
+            .entry(id)
+            .ok_or(SceneGraphError::InvalidNode(id))?
+            .parent
+            .unwrap();
+        self.entry_mut(parent)
+            .unwrap()
+            .children
+            .retain(|&child| child != id);
+
+        let removed: Vec<NodeId> = self.descendants(id).collect();
+        let mut node = None;
+        for removed in removed {
+            let slot = &mut self.slots[removed.index as usize];
+            let entry = slot.entry.take().unwrap();
+            slot.generation += 1;
+            self.free.push(removed.index);
+            if removed == id {
+                node = Some(entry.node);
+            }
+        }
+        Ok(node.unwrap())
+    }
+
+    /// Moves a node, along with its descendants, to the end of the children of `new_parent`.
+    /// Its transform stays relative to its parent, so it moves along with the new one.
+    #[allow(dead_code)]
+    pub fn reparent(&mut self, id: NodeId, new_parent: NodeId) -> Result<(), SceneGraphError> {
+        if id == self.root {
+            return Err(SceneGraphError::Root);
+        }
+        for node in [id, new_parent] {
+            if !self.contains(node) {
+                return Err(SceneGraphError::InvalidNode(node));
+            }
+        }
+        if new_parent == id || self.ancestors(new_parent).any(|ancestor| ancestor == id) {
+            return Err(SceneGraphError::Cycle {
+                node: id,
+                new_parent,
+            });
+        }
+
+        let old_parent = self.parent(id).unwrap();
+        self.entry_mut(old_parent)
+            .unwrap()
+            .children
+            .retain(|&child| child != id);
+        self.entry_mut(new_parent).unwrap().children.push(id);
+        self.entry_mut(id).unwrap().parent = Some(new_parent);
+        Ok(())
+    }
+
+    /// The parent of a node, `None` for the root or a removed node
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.entry(id).and_then(|entry| entry.parent)
+    }
+
+    /// The children of a node in order, none for a removed node
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        self.entry(id).map_or(&[], |entry| &entry.children)
+    }
+
+    /// The other children of the node's parent, in order
+    #[allow(dead_code)]
+    pub fn siblings(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let siblings = match self.parent(id) {
+            Some(parent) => self.children(parent),
+            None => &[],
+        };
+        siblings
+            .iter()
+            .cloned()
+            .filter(move |&sibling| sibling != id)
+    }
+
+    /// The parent of the node, its parent and so on up to the root
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.parent(id), move |&ancestor| self.parent(ancestor))
+    }
+
+    /// The node and everything below it, depth first with parents before their children
+    #[allow(dead_code)]
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let mut stack: Vec<NodeId> = if self.contains(id) {
+            vec![id]
+        } else {
+            Vec::new()
+        };
+        std::iter::from_fn(move || {
+            let next = stack.pop()?;
+            stack.extend(self.children(next).iter().rev());
+            Some(next)
+        })
+    }
+
+    /// Finds the first node matching a path below `from`, see `find_all`
+    pub fn find(&self, from: NodeId, path: &str) -> Option<NodeId> {
+        self.find_all(from, path).into_iter().next()
+    }
+
+    /// Finds the nodes matching a path below `from`, like `helicopters/heli_3/main_rotor`,
+    /// with parents before their children. Each part of the path matches the name of a node,
+    /// where `*` stands for any text and `?` for any single character, and a part that is just
+    /// `**` stands for any number of nodes in between. Nodes without a name are only passed
+    /// through by `**`.
+    pub fn find_all(&self, from: NodeId, path: &str) -> Vec<NodeId> {
+        let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+        let mut found = Vec::new();
+        if self.contains(from) {
+            self.find_below(from, &parts, &mut found);
+        }
+        found
+    }
+
+    // internal helper, matches the rest of the path against the children of a node
+    fn find_below(&self, id: NodeId, parts: &[&str], found: &mut Vec<NodeId>) {
+        let (part, rest) = match parts.split_first() {
+            Some(split) => split,
+            None => {
+                if !found.contains(&id) {
+                    found.push(id);
+                }
+                return;
+            }
+        };
+        if *part == "**" {
+            self.find_below(id, rest, found);
+        }
+        for &child in self.children(id) {
+            if *part == "**" {
+                self.find_below(child, parts, found);
+            } else if matches!(&self[child].name, Some(name) if glob_matches(part, name)) {
+                self.find_below(child, rest, found);
+            }
+        }
+    }
+
+    /// The transform from a node to the world, through all of its ancestors
+    pub fn world_transform(&self, id: NodeId) -> glm::Mat4 {
+        std::iter::once(id)
+            .chain(self.ancestors(id))
+            .filter_map(|node| self.get(node))
+            .fold(glm::identity(), |transform, node| {
+                node.local_transform() * transform
+            })
+    }
+}
+
+impl Index<NodeId> for SceneGraph {
+    type Output = SceneNode;
+
+    fn index(&self, id: NodeId) -> &SceneNode {
+        self.get(id)
+            .expect("the node was removed from the scene graph")
+    }
+}
+
+impl IndexMut<NodeId> for SceneGraph {
+    fn index_mut(&mut self, id: NodeId) -> &mut SceneNode {
+        self.get_mut(id)
+            .expect("the node was removed from the scene graph")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // internal helper, a graph with `a` and `b` below the root and `c`, `d` and `e` below `a`
+    fn sample() -> (SceneGraph, [NodeId; 5]) {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let a = graph.insert(root, SceneNode::group("a")).unwrap();
+        let b = graph.insert(root, SceneNode::group("b")).unwrap();
+        let c = graph.insert(a, SceneNode::group("c")).unwrap();
+        let d = graph.insert(a, SceneNode::group("d")).unwrap();
+        let e = graph.insert(a, SceneNode::group("e")).unwrap();
+        (graph, [a, b, c, d, e])
+    }
+
+    #[test]
+    fn removed_nodes_stay_removed_when_their_slot_is_reused() {
+        let (mut graph, [a, b, c, d, e]) = sample();
+        assert_eq!(graph.len(), 6);
+
+        let removed = graph.remove(a).unwrap();
+        assert_eq!(removed.name.as_deref(), Some("a"));
+        assert_eq!(graph.len(), 2);
+        for id in [a, c, d, e] {
+            assert!(!graph.contains(id));
+            assert!(graph.get(id).is_none());
+            assert!(graph.children(id).is_empty());
+        }
+        assert_eq!(graph.children(graph.root()), &[b]);
+        assert_eq!(
+            graph.remove(a).unwrap_err(),
+            SceneGraphError::InvalidNode(a)
+        );
+        assert_eq!(
+            graph.insert(c, SceneNode::group("f")).unwrap_err(),
+            SceneGraphError::InvalidNode(c)
+        );
+
+        // The new nodes take the freed slots, but the old ids don't see them
+        let new: Vec<NodeId> = (0..4)
+            .map(|i| graph.insert(b, SceneNode::group(&i.to_string())).unwrap())
+            .collect();
+        assert_eq!(graph.len(), 6);
+        assert_eq!(graph.slots.len(), 6);
+        for id in [a, c, d, e] {
+            assert!(!graph.contains(id));
+            assert!(!new.contains(&id));
+            assert!(new.iter().any(|new| new.index == id.index));
+        }
+        assert_eq!(graph.children(b), &new[..]);
+    }
+
+    #[test]
+    fn reparenting_moves_the_subtree() {
+        let (mut graph, [a, b, c, d, e]) = sample();
+        let f = graph.insert(d, SceneNode::group("f")).unwrap();
+        graph[b].position = glm::vec3(1.0, 2.0, 3.0);
+
+        graph.reparent(d, b).unwrap();
+        assert_eq!(graph.parent(d), Some(b));
+        assert_eq!(graph.children(a), &[c, e]);
+        assert_eq!(graph.children(b), &[d]);
+        assert_eq!(graph.ancestors(f).collect::<Vec<_>>(), [d, b, graph.root()]);
+        let origin = graph.world_transform(f) * glm::vec4(0.0, 0.0, 0.0, 1.0);
+        assert_eq!(origin.xyz(), glm::vec3(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn reparenting_below_itself_is_rejected() {
+        let (mut graph, [a, _, c, _, _]) = sample();
+        let f = graph.insert(c, SceneNode::group("f")).unwrap();
+        for new_parent in [a, c, f] {
+            assert_eq!(
+                graph.reparent(a, new_parent).unwrap_err(),
+                SceneGraphError::Cycle {
+                    node: a,
+                    new_parent
+                }
+            );
+        }
+        // Nothing moved
+        assert_eq!(graph.parent(a), Some(graph.root()));
+        assert_eq!(graph.parent(f), Some(c));
+    }
+
+    #[test]
+    fn the_root_stays_put() {
+        let (mut graph, [a, b, ..]) = sample();
+        let root = graph.root();
+        assert_eq!(graph.remove(root).unwrap_err(), SceneGraphError::Root);
+        assert_eq!(graph.reparent(root, a).unwrap_err(), SceneGraphError::Root);
+        assert!(graph.contains(root));
+        assert_eq!(graph.parent(root), None);
+        assert_eq!(graph.children(root), &[a, b]);
+        assert_eq!(graph.ancestors(root).count(), 0);
+        assert_eq!(graph.siblings(root).count(), 0);
+    }
+
+    #[test]
+    fn removed_nodes_are_rejected_when_reparenting() {
+        let (mut graph, [a, b, c, ..]) = sample();
+        graph.remove(b).unwrap();
+        assert_eq!(
+            graph.reparent(c, b).unwrap_err(),
+            SceneGraphError::InvalidNode(b)
+        );
+        assert_eq!(
+            graph.reparent(b, a).unwrap_err(),
+            SceneGraphError::InvalidNode(b)
+        );
+    }
+
+    #[test]
+    fn order_is_kept() {
+        let (mut graph, [a, b, c, d, e]) = sample();
+        let root = graph.root();
+        assert_eq!(graph.siblings(d).collect::<Vec<_>>(), [c, e]);
+        assert_eq!(graph.siblings(a).collect::<Vec<_>>(), [b]);
+        assert_eq!(graph.ancestors(e).collect::<Vec<_>>(), [a, root]);
+        assert_eq!(
+            graph.descendants(root).collect::<Vec<_>>(),
+            [root, a, c, d, e, b]
+        );
+
+        // A moved node goes last, and a removed one leaves the others in order
+        graph.reparent(c, a).unwrap();
+        assert_eq!(graph.children(a), &[d, e, c]);
+        graph.remove(e).unwrap();
+        assert_eq!(graph.siblings(d).collect::<Vec<_>>(), [c]);
+        assert_eq!(graph.descendants(a).collect::<Vec<_>>(), [a, d, c]);
+        assert_eq!(graph.descendants(e).count(), 0);
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_matches("main_rotor", "main_rotor"));
+        assert!(!glob_matches("main_rotor", "main_rotors"));
+        assert!(!glob_matches("main_rotor", "main_roto"));
+
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("*", "anything"));
+        assert!(glob_matches("heli_*", "heli_"));
+        assert!(glob_matches("heli_*", "heli_12"));
+        assert!(!glob_matches("heli_*", "hel"));
+        assert!(glob_matches("*_rotor", "main_rotor"));
+        assert!(glob_matches("*_rotor", "tail_rotor_rotor"));
+        assert!(!glob_matches("*_rotor", "main_rotor_hub"));
+        // A failed `*` is retried longer
+        assert!(glob_matches("*a*b", "xaxaxb"));
+        assert!(!glob_matches("*a*b", "xaxbxa"));
+        assert!(glob_matches("a**b", "ab"));
+
+        assert!(glob_matches("heli_?", "heli_3"));
+        assert!(!glob_matches("heli_?", "heli_"));
+        assert!(!glob_matches("heli_?", "heli_12"));
+        assert!(glob_matches("?*", "x"));
+        assert!(!glob_matches("?*", ""));
+        // Characters, not bytes
+        assert!(glob_matches("r?tor", "rötor"));
+
+        assert!(glob_matches("", ""));
+        assert!(!glob_matches("", "a"));
+    }
+
+    // internal helper, helicopters like the ones in main, with an unnamed node in between
+    fn helicopters() -> (SceneGraph, Vec<NodeId>, Vec<NodeId>) {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let group = graph.insert(root, SceneNode::group("helicopters")).unwrap();
+        let mut bodies = Vec::new();
+        let mut rotors = Vec::new();
+        for i in 0..4 {
+            let body = graph
+                .insert(group, SceneNode::group(&format!("heli_{}", i)))
+                .unwrap();
+            let hub = graph.insert(body, SceneNode::default()).unwrap();
+            rotors.push(graph.insert(hub, SceneNode::group("main_rotor")).unwrap());
+            graph.insert(body, SceneNode::group("tail_rotor")).unwrap();
+            bodies.push(body);
+        }
+        (graph, bodies, rotors)
+    }
+
+    #[test]
+    fn paths_with_wildcards() {
+        let (graph, bodies, rotors) = helicopters();
+        let root = graph.root();
+        assert_eq!(graph.find(root, "helicopters/heli_2"), Some(bodies[2]));
+        assert_eq!(graph.find_all(root, "helicopters/*"), bodies);
+        assert_eq!(graph.find_all(root, "helicopters/heli_?"), bodies);
+        assert_eq!(graph.find_all(root, "helicopters/heli_1*"), [bodies[1]]);
+        assert!(graph.find_all(root, "helicopters/heli_??").is_empty());
+        assert_eq!(graph.find_all(root, "helicopters/*/tail_rotor").len(), 4);
+        // Unnamed nodes are only passed through by `**`
+        assert!(graph.find_all(root, "helicopters/*/main_rotor").is_empty());
+        assert!(graph
+            .find_all(root, "helicopters/*/*/main_rotor")
+            .is_empty());
+        assert_eq!(graph.find_all(root, "helicopters/*/**/main_rotor"), rotors);
+        assert_eq!(graph.find_all(root, "**/main_rotor"), rotors);
+        assert_eq!(graph.find(bodies[3], "**/main_rotor"), Some(rotors[3]));
+        assert!(graph.find(root, "helicopters/heli_4").is_none());
+    }
+
+    #[test]
+    fn empty_parts_are_skipped() {
+        let (graph, bodies, _) = helicopters();
+        let root = graph.root();
+        assert_eq!(graph.find(root, "/helicopters//heli_1/"), Some(bodies[1]));
+        assert_eq!(graph.find_all(root, ""), [root]);
+        assert_eq!(graph.find_all(bodies[0], "/"), [bodies[0]]);
+    }
+
+    #[test]
+    fn double_stars_find_each_node_once() {
+        let (graph, bodies, rotors) = helicopters();
+        let root = graph.root();
+        let all: Vec<NodeId> = graph.descendants(root).collect();
+        assert_eq!(graph.find_all(root, "**"), all);
+        assert_eq!(graph.find_all(root, "**/**"), all);
+        assert_eq!(graph.find_all(root, "**/**/main_rotor"), rotors);
+        assert_eq!(graph.find_all(root, "**/heli_*/**/**"), {
+            let mut below: Vec<NodeId> = Vec::new();
+            for &body in &bodies {
+                below.extend(graph.descendants(body));
+            }
+            below
+        });
+        // Parents come before their children
+        let found = graph.find_all(root, "helicopters/**");
+        assert_eq!(found[0], graph.find(root, "helicopters").unwrap());
+        assert_eq!(found.len(), all.len() - 1);
+    }
+
+    #[test]
+    fn paths_follow_nodes_that_move() {
+        let (mut graph, bodies, rotors) = helicopters();
+        let root = graph.root();
+        graph.reparent(bodies[0], bodies[1]).unwrap();
+        assert!(graph.find(root, "helicopters/heli_0").is_none());
+        assert_eq!(
+            graph.find(root, "helicopters/heli_1/heli_0"),
+            Some(bodies[0])
+        );
+        assert_eq!(
+            graph.find(root, "helicopters/**/heli_0/**/main_rotor"),
+            Some(rotors[0])
+        );
+    }
+
+    #[test]
+    fn paths_follow_siblings_that_are_reordered() {
+        let (mut graph, bodies, rotors) = helicopters();
+        let root = graph.root();
+        let group = graph.find(root, "helicopters").unwrap();
+        let tail_rotor = graph.find(bodies[2], "tail_rotor").unwrap();
+        let hub = graph.parent(rotors[2]).unwrap();
+
+        // Moving a node to the end of its siblings reorders them
+        for &body in &bodies[..2] {
+            graph.reparent(body, group).unwrap();
+        }
+        graph.reparent(hub, bodies[2]).unwrap();
+        assert_eq!(
+            graph.children(group),
+            &[bodies[2], bodies[3], bodies[0], bodies[1]]
+        );
+        assert_eq!(graph.children(bodies[2]), &[tail_rotor, hub]);
+
+        assert_eq!(
+            graph.find(root, "helicopters/heli_2/**/main_rotor"),
+            Some(rotors[2])
+        );
+        assert_eq!(
+            graph.find_all(root, "helicopters/*/**/main_rotor"),
+            [2, 3, 0, 1].map(|i| rotors[i])
+        );
+    }
+}
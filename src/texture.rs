@@ -0,0 +1,101 @@
+use std::{os::raw::c_void, path::Path};
+
+/// How a texture should be sampled
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    pub wrap: gl::types::GLenum,
+    pub min_filter: gl::types::GLenum,
+    pub mag_filter: gl::types::GLenum,
+    pub mipmaps: bool,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self {
+            wrap: gl::REPEAT,
+            min_filter: gl::LINEAR_MIPMAP_LINEAR,
+            mag_filter: gl::LINEAR,
+            mipmaps: true,
+        }
+    }
+}
+
+pub struct Texture {
+    pub texture_id: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Texture {
+    /// Loads a PNG or JPEG image into a new 2D texture
+    pub unsafe fn load(path: &Path, sampler: &Sampler) -> image::ImageResult<Texture> {
+        Ok(Texture::from_image(&image::open(path)?, sampler))
+    }
+
+    /// Uploads an already decoded image into a new 2D texture, e.g. one embedded in a model file
+    pub unsafe fn from_image(image: &image::DynamicImage, sampler: &Sampler) -> Texture {
+        // OpenGL expects the first row of pixels to be the bottom one
+        let image = image.flipv().into_rgba8();
+        let (width, height) = image.dimensions();
+
+        let mut texture_id = 0;
+        gl::GenTextures(1, &mut texture_id);
+        gl::BindTexture(gl::TEXTURE_2D, texture_id);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA8 as i32,
+            width as i32,
+            height as i32,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            image.as_ptr() as *const c_void,
+        );
+
+        // Mipmapped min filters sample nothing without mipmaps, so fall back to linear filtering
+        let min_filter = match (sampler.mipmaps, sampler.min_filter) {
+            (true, filter) => filter,
+            (false, gl::NEAREST_MIPMAP_NEAREST) | (false, gl::NEAREST_MIPMAP_LINEAR) => gl::NEAREST,
+            (false, gl::LINEAR_MIPMAP_NEAREST) | (false, gl::LINEAR_MIPMAP_LINEAR) => gl::LINEAR,
+            (false, filter) => filter,
+        };
+        if sampler.mipmaps {
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+        }
+
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, sampler.wrap as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, sampler.wrap as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+        gl::TexParameteri(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_MAG_FILTER,
+            sampler.mag_filter as i32,
+        );
+
+        Texture {
+            texture_id,
+            width,
+            height,
+        }
+    }
+
+    /// Loads a texture, printing the error and returning `None` if that fails
+    pub unsafe fn load_or_warn(path: &Path, sampler: &Sampler) -> Option<Texture> {
+        match Texture::load(path, sampler) {
+            Ok(texture) => {
+                println!(
+                    "Loaded texture {} ({}x{})",
+                    path.display(),
+                    texture.width,
+                    texture.height
+                );
+                Some(texture)
+            }
+            Err(e) => {
+                println!("Failed to load texture {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}